@@ -26,6 +26,31 @@ pub struct Course {
     pub level: Option<String>,
 }
 
+// 课程列表接口返回的分页结构
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CoursePage {
+    pub items: Vec<Course>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
+// 登录之后 access token 保存在 localStorage 的这个 key 中
+const ACCESS_TOKEN_KEY: &str = "access_token";
+
+// 新增和删除课程需要登录, 请求带上 Authorization: Bearer <access token>
+// ? 没有保存 token 时不设置, 后端返回 401
+fn set_authorization(request: &Request) -> Result<(), JsValue> {
+    let window = web_sys::window().ok_or("no window exists".to_string())?;
+    let token = match window.local_storage()? {
+        Some(storage) => storage.get_item(ACCESS_TOKEN_KEY)?,
+        None => None,
+    };
+    match token {
+        Some(token) => request.headers().set("Authorization", &format!("Bearer {}", token)),
+        None => Ok(()),
+    }
+}
+
 pub async fn get_courses_by_teacher(teacher_id: i32) -> Result<Vec<Course>, MyError> {
     // 创建一个 request
     let mut opts = RequestInit::new();
@@ -48,10 +73,10 @@ pub async fn get_courses_by_teacher(teacher_id: i32) -> Result<Vec<Course>, MyEr
 
     let resp: Response = resp_value.dyn_into().unwrap();
     let json = JsFuture::from(resp.json()?).await?;
-    // 序列化
-    let courses: Vec<Course> = json.into_serde().unwrap();
+    // 序列化, 课程列表在分页结构的 items 里面
+    let page: CoursePage = json.into_serde().unwrap();
 
-    Ok(courses)
+    Ok(page.items)
 }
 
 pub async fn delete_course(teacher_id: i32, course_id: i32) -> () {
@@ -63,6 +88,7 @@ pub async fn delete_course(teacher_id: i32, course_id: i32) -> () {
     let url = format!("http://localhost:3000/courses/{}/{}", teacher_id, course_id);
     let request = Request::new_with_str_and_init(&url, &opts).unwrap();
     request.headers().set("Accept", "application/json").unwrap();
+    set_authorization(&request).unwrap();
 
     let window = web_sys::window().ok_or("no window exists".to_string()).unwrap();
     let resp_value = JsFuture::from(window.fetch_with_request(&request)).await.unwrap();
@@ -93,6 +119,7 @@ pub async fn add_course(name: String, description: String) -> Result<Promise, Js
     let request = Request::new_with_str_and_init(&url, &opts)?;
     request.headers().set("Content-Type", "application/json")?;
     request.headers().set("Accept", "application/json")?;
+    set_authorization(&request)?;
     let window = web_sys::window().ok_or("no window exists".to_string())?;
     let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
     assert!(resp_value.is_instance_of::<Response>());
@@ -1,5 +1,5 @@
 use crate::errors::MyError;
-use crate::models::{TeacherRegisterForm, TeacherResponse};
+use crate::models::{PageResponse, ProblemResponse, TeacherRegisterForm, TeacherResponse};
 use actix_web::{web, Error, HttpResponse, Result};
 use serde_json::json;
 use tera::Context;
@@ -24,20 +24,29 @@ fn get_default_client() -> Client {
 pub async fn get_all_teachers(tmpl: web::Data<tera::Tera>) -> Result<HttpResponse, Error> {
     // 创建一个 http 客户端, 用它可以访问到 webService 下的东西
     let awc_client = get_default_client();
-    let res = awc_client
-        .get("http://localhost:3000/teachers")
-        .send()
-        .await
-        .unwrap()
-        // 将获取的json数据转换为 Vec<TeacherResponse>
-        .json::<Vec<TeacherResponse>>()
-        .await
-        .unwrap();
+    // 将获取的json数据转换为 PageResponse<TeacherResponse>, 老师列表在 items 里面
+    let res = match awc_client.get("http://localhost:3000/teachers").send().await {
+        Ok(mut res) if res.status().is_success() => res
+            .json::<PageResponse<TeacherResponse>>()
+            .await
+            .map(|page| page.items)
+            .map_err(|_| "Unexpected response from teacher service".to_string()),
+        Ok(res) => Err(format!("Unable to load teachers: {}", res.status())),
+        Err(_) => Err("Teacher service is unavailable".to_string()),
+    };
 
     // 创建一个上下文, 用于向 html 模板内添加数据
     let mut ctx = get_context();
-    ctx.insert("error", "");
-    ctx.insert("teachers", &res);
+    match res {
+        Ok(teachers) => {
+            ctx.insert("error", "");
+            ctx.insert("teachers", &teachers);
+        }
+        Err(error) => {
+            ctx.insert("error", &error);
+            ctx.insert("teachers", &Vec::<TeacherResponse>::new());
+        }
+    }
 
     // 渲染模板, 这里就是找 static/teachers.html
     let s = tmpl.render("teachers.html", &ctx)
@@ -53,6 +62,7 @@ pub async fn show_register_form(tmpl: web::Data<tera::Tera>) -> Result<HttpRespo
     ctx.insert("current_name", "");
     ctx.insert("current_image_url", "");
     ctx.insert("current_profile", "");
+    ctx.insert("current_username", "");
     let s = tmpl.render("register.html", &ctx)
         .map_err(|_| MyError::TeraError("Template error".to_string()));
     Ok(HttpResponse::Ok().content_type("text/html").body(s.unwrap()))
@@ -60,35 +70,53 @@ pub async fn show_register_form(tmpl: web::Data<tera::Tera>) -> Result<HttpRespo
 
 // 注册教师
 pub async fn handle_register(tmpl: web::Data<tera::Tera>, params: web::Form<TeacherRegisterForm>) -> Result<HttpResponse, Error> {
-    let mut ctx = get_context();
-    let s;
     if params.name == "Dave" {
-        ctx.insert("error", "Dave is already exists!");
-        ctx.insert("current_name", &params.name);
-        ctx.insert("current_profile", &params.profile);
-        ctx.insert("current_image_url", &params.image_url);
-        s = tmpl.render("register.html", &ctx)
-            .map_err(|_| MyError::TeraError("Template Error".to_string())).unwrap();
-    } else {
-        let new_teacher = json!({
-            // 这里面的字段要和后端服务中新增老师的字段对应上
-            "name": &params.name,
-            "picture_url": &params.image_url,
-            "profile": &params.profile
-        });
-        
-        let awc_client = get_default_client();
-        let res = awc_client
-            .post("http://localhost:3000/teachers")
-            .send_json(&new_teacher)
-            .await
-            .unwrap()
-            .body()
-            .await
-            .unwrap();
-        // 将上面获取的结果转换为一个字符串切片, 再从字符串切片转换为一个 TeacherResponse 格式的 json
-        let teacher_response: TeacherResponse = serde_json::from_str(&std::str::from_utf8(&res).unwrap()).unwrap();
-        s = format!("Congratulation! Your id is: {}.", teacher_response.id);
+        return render_register_error(&tmpl, &params, "Dave is already exists!");
+    }
+    let new_teacher = json!({
+        // 这里面的字段要和后端服务中注册老师的字段对应上
+        "name": &params.name,
+        "picture_url": &params.image_url,
+        "profile": &params.profile,
+        "username": &params.username,
+        "password": &params.password
+    });
+
+    // ? POST /teachers 需要管理员登录, 这里使用不需要登录的注册接口
+    let awc_client = get_default_client();
+    let mut res = match awc_client.post("http://localhost:3000/auth/register").send_json(&new_teacher).await {
+        Ok(res) => res,
+        Err(_) => return render_register_error(&tmpl, &params, "Teacher service is unavailable"),
+    };
+    let body = match res.body().await {
+        Ok(body) => body,
+        Err(_) => return render_register_error(&tmpl, &params, "Teacher service is unavailable"),
+    };
+    // 注册失败时后端返回 problem+json, 把 detail 显示在表单上
+    if !res.status().is_success() {
+        let message = match serde_json::from_slice::<ProblemResponse>(&body) {
+            Ok(problem) => problem.detail,
+            Err(_) => format!("Registration failed: {}", res.status()),
+        };
+        return render_register_error(&tmpl, &params, &message);
+    }
+    match serde_json::from_slice::<TeacherResponse>(&body) {
+        Ok(teacher_response) => Ok(HttpResponse::Ok()
+            .content_type("text/html")
+            .body(format!("Congratulation! Your id is: {}.", teacher_response.id))),
+        Err(_) => render_register_error(&tmpl, &params, "Unexpected response from teacher service"),
     }
-    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+// 注册失败, 重新显示表单, 保留已经填写的内容, 密码除外
+fn render_register_error(tmpl: &tera::Tera, params: &TeacherRegisterForm, error: &str) -> Result<HttpResponse, Error> {
+    let mut ctx = get_context();
+    ctx.insert("error", error);
+    ctx.insert("current_name", &params.name);
+    ctx.insert("current_profile", &params.profile);
+    ctx.insert("current_image_url", &params.image_url);
+    ctx.insert("current_username", &params.username);
+    let s = tmpl.render("register.html", &ctx)
+        .map_err(|_| MyError::TeraError("Template Error".to_string()));
+    Ok(HttpResponse::Ok().content_type("text/html").body(s.unwrap()))
 }
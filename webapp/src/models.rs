@@ -6,6 +6,9 @@ pub struct TeacherRegisterForm {
     pub name: String,
     pub image_url: String,
     pub profile: String,
+    // 后端的注册接口同时创建登录账号
+    pub username: String,
+    pub password: String,
 }
 
 // 查询老师返回的结果(包含id的)
@@ -13,6 +16,21 @@ pub struct TeacherRegisterForm {
 pub struct TeacherResponse {
     pub id: i32,
     pub name: String,
-    pub picture_url: String,
-    pub profile: String,
+    // 老师可以清空头像和简介, 这时后端返回 null
+    pub picture_url: Option<String>,
+    pub profile: Option<String>,
+}
+
+// 后端出错时返回的 application/problem+json, 只关心 detail
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProblemResponse {
+    pub detail: String,
+}
+
+// 列表接口返回的分页结构, 只关心 items
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PageResponse<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
 }
@@ -0,0 +1,26 @@
+-- 教师表和课程表, 对应 models/teacher.rs 和 models/course.rs
+-- 使用 IF NOT EXISTS, 这样之前手工建好表的数据库也可以直接接入迁移
+CREATE TABLE IF NOT EXISTS teacher
+(
+    id          SERIAL PRIMARY KEY,
+    name        VARCHAR(200),
+    picture_url VARCHAR(200),
+    profile     VARCHAR(2000)
+);
+
+CREATE TABLE IF NOT EXISTS course
+(
+    id          SERIAL PRIMARY KEY,
+    teacher_id  INT          NOT NULL,
+    name        VARCHAR(140) NOT NULL,
+    time        TIMESTAMP DEFAULT now(),
+    description VARCHAR(2000),
+    format      VARCHAR(30),
+    structure   VARCHAR(200),
+    duration    VARCHAR(30),
+    price       INT,
+    language    VARCHAR(30),
+    level       VARCHAR(30)
+);
+
+CREATE INDEX IF NOT EXISTS course_teacher_id_idx ON course (teacher_id);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS course_search_idx;
+DROP FUNCTION IF EXISTS course_search_vector(TEXT, TEXT, TEXT);
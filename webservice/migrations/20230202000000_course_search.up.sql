@@ -0,0 +1,16 @@
+-- 课程全文检索, 按 name > description > structure 的权重建立 tsvector
+-- ? 使用 simple 配置, 不做词干处理, 中英文课程名都可以按原词匹配
+CREATE OR REPLACE FUNCTION course_search_vector(name TEXT, description TEXT, structure TEXT)
+    RETURNS tsvector
+    LANGUAGE sql
+    IMMUTABLE
+AS
+$$
+SELECT setweight(to_tsvector('simple', coalesce(name, '')), 'A')
+           || setweight(to_tsvector('simple', coalesce(description, '')), 'B')
+           || setweight(to_tsvector('simple', coalesce(structure, '')), 'C')
+$$;
+
+-- 表达式索引, 查询时必须使用同样的 course_search_vector(name, description, structure) 才能命中
+CREATE INDEX IF NOT EXISTS course_search_idx
+    ON course USING GIN (course_search_vector(name, description, structure));
@@ -0,0 +1,7 @@
+-- 回滚前先把已经软删除的记录真正删掉, 否则它们会重新出现
+DELETE FROM course WHERE deleted_at IS NOT NULL;
+DELETE FROM teacher WHERE deleted_at IS NOT NULL;
+DROP INDEX IF EXISTS course_deleted_at_idx;
+DROP INDEX IF EXISTS teacher_deleted_at_idx;
+ALTER TABLE course DROP COLUMN IF EXISTS deleted_at;
+ALTER TABLE teacher DROP COLUMN IF EXISTS deleted_at;
@@ -0,0 +1,7 @@
+-- 软删除, deleted_at 不为空的记录视为已删除, 所有的读取都会跳过它们
+ALTER TABLE course ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP;
+ALTER TABLE teacher ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP;
+
+-- 清理过期的墓碑记录时按 deleted_at 查找
+CREATE INDEX IF NOT EXISTS course_deleted_at_idx ON course (deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS teacher_deleted_at_idx ON teacher (deleted_at) WHERE deleted_at IS NOT NULL;
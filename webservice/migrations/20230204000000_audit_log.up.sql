@@ -0,0 +1,15 @@
+-- 课程和老师的变更历史, 每次新增, 修改, 删除, 恢复都会写入一条
+-- ? 不加外键, 记录被彻底删除之后历史依然保留
+CREATE TABLE IF NOT EXISTS audit_log (
+    id SERIAL PRIMARY KEY,
+    entity_type VARCHAR(30) NOT NULL, -- course 或者 teacher
+    entity_id INT NOT NULL,
+    teacher_id INT NOT NULL, -- 课程所属的老师, 老师自己的记录就是老师的 id
+    action VARCHAR(30) NOT NULL, -- create, update, delete, restore, purge
+    actor VARCHAR(200) NOT NULL,
+    before JSONB, -- 变更前的字段值, 新增时为空
+    after JSONB, -- 变更后的字段值, 彻底删除时为空
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_entity_idx ON audit_log (entity_type, entity_id, id);
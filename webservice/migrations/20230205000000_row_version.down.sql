@@ -0,0 +1,2 @@
+ALTER TABLE course DROP COLUMN IF EXISTS version;
+ALTER TABLE teacher DROP COLUMN IF EXISTS version;
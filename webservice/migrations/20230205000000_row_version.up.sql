@@ -0,0 +1,3 @@
+-- 乐观锁, 每次修改都会把 version 加一, 接口通过 ETag 和 If-Match 暴露给客户端
+ALTER TABLE course ADD COLUMN IF NOT EXISTS version INT NOT NULL DEFAULT 1;
+ALTER TABLE teacher ADD COLUMN IF NOT EXISTS version INT NOT NULL DEFAULT 1;
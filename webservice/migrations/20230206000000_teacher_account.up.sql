@@ -0,0 +1,7 @@
+-- 老师的登录账号, 一个老师一个账号, 密码只保存 argon2 哈希
+CREATE TABLE IF NOT EXISTS teacher_account (
+    teacher_id INT PRIMARY KEY REFERENCES teacher (id) ON DELETE CASCADE,
+    username VARCHAR(100) NOT NULL UNIQUE,
+    password_hash VARCHAR(200) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
@@ -0,0 +1 @@
+ALTER TABLE teacher_account DROP COLUMN IF EXISTS role;
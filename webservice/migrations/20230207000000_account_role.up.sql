@@ -0,0 +1,5 @@
+-- 账号的角色, teacher 只能修改自己的数据, admin 可以修改所有老师的数据
+-- ? 管理员通过 UPDATE teacher_account SET role = 'admin' WHERE username = '...' 设置
+ALTER TABLE teacher_account
+    ADD COLUMN IF NOT EXISTS role VARCHAR(20) NOT NULL DEFAULT 'teacher'
+    CHECK (role IN ('teacher', 'admin'));
@@ -0,0 +1,11 @@
+ALTER TABLE course
+    DROP CONSTRAINT IF EXISTS course_level_check,
+    DROP CONSTRAINT IF EXISTS course_format_check,
+    DROP CONSTRAINT IF EXISTS course_language_check;
+
+-- 还原无法识别的旧值, 已经统一写法的值保持不变
+UPDATE course c SET level = o.value FROM course_legacy_option o WHERE o.course_id = c.id AND o.column_name = 'level';
+UPDATE course c SET format = o.value FROM course_legacy_option o WHERE o.course_id = c.id AND o.column_name = 'format';
+UPDATE course c SET language = o.value FROM course_legacy_option o WHERE o.course_id = c.id AND o.column_name = 'language';
+
+DROP TABLE IF EXISTS course_legacy_option;
@@ -0,0 +1,76 @@
+-- 课程的 level, format, language 改为固定的几个值, 统一为小写下划线形式
+-- ? 和 src/models/course_options.rs 中的枚举以及别名保持一致
+
+-- 统一大小写和分隔符
+CREATE OR REPLACE FUNCTION pg_temp.normalize_course_option(value TEXT) RETURNS TEXT AS $$
+    SELECT NULLIF(replace(replace(lower(trim(value)), ' ', '_'), '-', '_'), '')
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION pg_temp.course_level(value TEXT) RETURNS TEXT AS $$
+    SELECT CASE
+        WHEN v IN ('beginner', '初级', '入门', 'basic', 'elementary') THEN 'beginner'
+        WHEN v IN ('intermediate', '中级') THEN 'intermediate'
+        WHEN v IN ('advanced', '高级') THEN 'advanced'
+    END
+    FROM (SELECT pg_temp.normalize_course_option(value) AS v) s
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION pg_temp.course_format(value TEXT) RETURNS TEXT AS $$
+    SELECT CASE
+        WHEN v IN ('self_paced', '自定进度', '自行安排进度', 'on_demand') THEN 'self_paced'
+        WHEN v IN ('scheduled', '安排进度', 'instructor_paced') THEN 'scheduled'
+        WHEN v IN ('live', '直播', '直播课') THEN 'live'
+        WHEN v IN ('offline', '线下', '线下课', 'in_person') THEN 'offline'
+    END
+    FROM (SELECT pg_temp.normalize_course_option(value) AS v) s
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION pg_temp.course_language(value TEXT) RETURNS TEXT AS $$
+    SELECT CASE
+        WHEN v IN ('english', '英文', '英语', 'en') THEN 'english'
+        WHEN v IN ('chinese', '中文', '汉语', '普通话', 'zh', 'mandarin') THEN 'chinese'
+        WHEN v IN ('japanese', '日语', '日文', 'ja') THEN 'japanese'
+        WHEN v IN ('korean', '韩语', '韩文', 'ko') THEN 'korean'
+        WHEN v IN ('french', '法语', 'fr') THEN 'french'
+        WHEN v IN ('german', '德语', 'de') THEN 'german'
+        WHEN v IN ('spanish', '西班牙语', 'es') THEN 'spanish'
+    END
+    FROM (SELECT pg_temp.normalize_course_option(value) AS v) s
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- 无法识别的旧值先备份下来再置空, 回滚迁移时会还原
+CREATE TABLE IF NOT EXISTS course_legacy_option (
+    course_id INT NOT NULL,
+    column_name VARCHAR(30) NOT NULL,
+    value VARCHAR(30) NOT NULL,
+    PRIMARY KEY (course_id, column_name)
+);
+
+INSERT INTO course_legacy_option (course_id, column_name, value)
+SELECT id, 'level', level FROM course
+WHERE pg_temp.normalize_course_option(level) IS NOT NULL AND pg_temp.course_level(level) IS NULL
+UNION ALL
+SELECT id, 'format', format FROM course
+WHERE pg_temp.normalize_course_option(format) IS NOT NULL AND pg_temp.course_format(format) IS NULL
+UNION ALL
+SELECT id, 'language', language FROM course
+WHERE pg_temp.normalize_course_option(language) IS NOT NULL AND pg_temp.course_language(language) IS NULL
+ON CONFLICT DO NOTHING;
+
+UPDATE course SET
+    level = pg_temp.course_level(level),
+    format = pg_temp.course_format(format),
+    language = pg_temp.course_language(language)
+WHERE level IS NOT NULL OR format IS NOT NULL OR language IS NOT NULL;
+
+-- 先删除再添加, 重复执行时不会报错
+ALTER TABLE course
+    DROP CONSTRAINT IF EXISTS course_level_check,
+    DROP CONSTRAINT IF EXISTS course_format_check,
+    DROP CONSTRAINT IF EXISTS course_language_check,
+    ADD CONSTRAINT course_level_check
+        CHECK (level IN ('beginner', 'intermediate', 'advanced')),
+    ADD CONSTRAINT course_format_check
+        CHECK (format IN ('self_paced', 'scheduled', 'live', 'offline')),
+    ADD CONSTRAINT course_language_check
+        CHECK (language IN ('english', 'chinese', 'japanese', 'korean', 'french', 'german', 'spanish'));
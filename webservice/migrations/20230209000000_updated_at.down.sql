@@ -0,0 +1,6 @@
+DROP TRIGGER IF EXISTS teacher_touch_updated_at ON teacher;
+DROP TRIGGER IF EXISTS course_touch_updated_at ON course;
+DROP FUNCTION IF EXISTS touch_updated_at();
+
+ALTER TABLE teacher DROP COLUMN IF EXISTS updated_at;
+ALTER TABLE course DROP COLUMN IF EXISTS updated_at;
@@ -0,0 +1,20 @@
+-- 最后修改时间, 接口通过 Last-Modified 和 If-Modified-Since 暴露给客户端
+-- 带时区, 换算成 HTTP 日期 (GMT) 时不依赖会话的时区设置
+ALTER TABLE course ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE teacher ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+-- 由触发器维护, 修改, 软删除, 恢复和转给其他老师都会更新, 不需要每条 UPDATE 都记得设置
+CREATE OR REPLACE FUNCTION touch_updated_at() RETURNS trigger AS $$
+BEGIN
+    NEW.updated_at := now();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS course_touch_updated_at ON course;
+CREATE TRIGGER course_touch_updated_at BEFORE UPDATE ON course
+    FOR EACH ROW EXECUTE FUNCTION touch_updated_at();
+
+DROP TRIGGER IF EXISTS teacher_touch_updated_at ON teacher;
+CREATE TRIGGER teacher_touch_updated_at BEFORE UPDATE ON teacher
+    FOR EACH ROW EXECUTE FUNCTION touch_updated_at();
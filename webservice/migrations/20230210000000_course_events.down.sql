@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS audit_log_teacher_idx;
+DROP TRIGGER IF EXISTS audit_log_notify_course_event ON audit_log;
+DROP FUNCTION IF EXISTS notify_course_event();
@@ -0,0 +1,17 @@
+-- 课程变更事件, GET /courses/{teacher_id}/events 通过 SSE 推送
+-- 事件就是 audit_log 中的课程记录, 事件 id 就是 audit_log.id, 客户端重连时通过 Last-Event-ID 带回来
+-- ? 写入课程记录时通过 NOTIFY 通知所有 teacher-service 实例, payload 只是老师的 id, 实例收到之后再查询 audit_log
+-- ? NOTIFY 在事务提交时才发出, 回滚的修改不会推送
+CREATE OR REPLACE FUNCTION notify_course_event() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('course_events', NEW.teacher_id::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_notify_course_event ON audit_log;
+CREATE TRIGGER audit_log_notify_course_event AFTER INSERT ON audit_log
+    FOR EACH ROW WHEN (NEW.entity_type = 'course') EXECUTE FUNCTION notify_course_event();
+
+-- 按老师查询某个事件 id 之后的课程事件
+CREATE INDEX IF NOT EXISTS audit_log_teacher_idx ON audit_log (entity_type, teacher_id, id);
@@ -0,0 +1,6 @@
+DROP TRIGGER IF EXISTS audit_log_enqueue_webhook_deliveries ON audit_log;
+DROP FUNCTION IF EXISTS enqueue_webhook_deliveries();
+
+DROP TABLE IF EXISTS webhook_attempt;
+DROP TABLE IF EXISTS webhook_delivery;
+DROP TABLE IF EXISTS webhook;
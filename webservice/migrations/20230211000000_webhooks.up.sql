@@ -0,0 +1,72 @@
+-- webhook, 外部系统 (例如 LMS) 订阅课程和老师的变更, 不再需要轮询
+-- ? teacher_id 为 NULL 的订阅接收所有老师的事件, 只有管理员可以创建
+CREATE TABLE IF NOT EXISTS webhook (
+    id SERIAL PRIMARY KEY,
+    teacher_id INT REFERENCES teacher (id) ON DELETE CASCADE,
+    url VARCHAR(2000) NOT NULL,
+    secret VARCHAR(200) NOT NULL, -- 请求体签名用的密钥, 只在创建时返回一次
+    events TEXT[] NOT NULL, -- 订阅的事件, 例如 course.created
+    created_by VARCHAR(200) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS webhook_teacher_idx ON webhook (teacher_id);
+
+-- 一个事件对一个订阅的投递, 失败之后按指数退避重试, 超过次数之后状态改为 dead, 也就是死信
+CREATE TABLE IF NOT EXISTS webhook_delivery (
+    id SERIAL PRIMARY KEY,
+    webhook_id INT NOT NULL REFERENCES webhook (id) ON DELETE CASCADE,
+    event_id INT NOT NULL, -- audit_log.id, 请求体中的事件 id, 接收方用来去重
+    event_type VARCHAR(50) NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending', -- pending, delivered, dead
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_status_code INT,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at TIMESTAMPTZ,
+    UNIQUE (webhook_id, event_id)
+);
+
+CREATE INDEX IF NOT EXISTS webhook_delivery_due_idx ON webhook_delivery (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS webhook_delivery_webhook_idx ON webhook_delivery (webhook_id, id);
+
+-- 每一次请求的结果
+CREATE TABLE IF NOT EXISTS webhook_attempt (
+    id SERIAL PRIMARY KEY,
+    delivery_id INT NOT NULL REFERENCES webhook_delivery (id) ON DELETE CASCADE,
+    attempt INT NOT NULL,
+    status_code INT,
+    error TEXT,
+    duration_ms INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS webhook_attempt_delivery_idx ON webhook_attempt (delivery_id, id);
+
+-- 写入变更历史时为订阅了这个事件的 webhook 创建投递
+-- ? 和课程/老师的修改在同一个事务中, 回滚的修改不会投递, 提交之后由投递任务发送
+-- ? 事件类型和 models/webhook.rs 中的 event_type 保持一致, 彻底删除不投递
+CREATE OR REPLACE FUNCTION enqueue_webhook_deliveries() RETURNS trigger AS $$
+DECLARE
+    kind TEXT;
+BEGIN
+    IF NEW.action = 'purge' THEN
+        RETURN NULL;
+    END IF;
+    kind := NEW.entity_type || '.' || CASE NEW.action
+        WHEN 'create' THEN 'created'
+        WHEN 'restore' THEN 'created'
+        WHEN 'delete' THEN 'deleted'
+        ELSE 'updated'
+    END;
+    INSERT INTO webhook_delivery (webhook_id, event_id, event_type)
+    SELECT w.id, NEW.id, kind FROM webhook w
+    WHERE (w.teacher_id IS NULL OR w.teacher_id = NEW.teacher_id) AND kind = ANY (w.events);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_enqueue_webhook_deliveries ON audit_log;
+CREATE TRIGGER audit_log_enqueue_webhook_deliveries AFTER INSERT ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION enqueue_webhook_deliveries();
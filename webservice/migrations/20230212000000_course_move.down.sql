@@ -0,0 +1,23 @@
+-- 之前的版本不认识 move, 连同投递一起删除
+DELETE FROM webhook_delivery WHERE event_id IN (SELECT id FROM audit_log WHERE action = 'move');
+DELETE FROM audit_log WHERE action = 'move';
+
+CREATE OR REPLACE FUNCTION enqueue_webhook_deliveries() RETURNS trigger AS $$
+DECLARE
+    kind TEXT;
+BEGIN
+    IF NEW.action = 'purge' THEN
+        RETURN NULL;
+    END IF;
+    kind := NEW.entity_type || '.' || CASE NEW.action
+        WHEN 'create' THEN 'created'
+        WHEN 'restore' THEN 'created'
+        WHEN 'delete' THEN 'deleted'
+        ELSE 'updated'
+    END;
+    INSERT INTO webhook_delivery (webhook_id, event_id, event_type)
+    SELECT w.id, NEW.id, kind FROM webhook w
+    WHERE (w.teacher_id IS NULL OR w.teacher_id = NEW.teacher_id) AND kind = ANY (w.events);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,22 @@
+-- 课程转给其他老师时, 原来的老师名下会写一条 action = 'move' 的记录 (见 AuditRecord::course_moved)
+-- ? 原来的老师的 webhook 收到 course.deleted, 所有老师的 webhook 已经通过同一次修改的 update 收到 course.updated, 不再重复投递
+CREATE OR REPLACE FUNCTION enqueue_webhook_deliveries() RETURNS trigger AS $$
+DECLARE
+    kind TEXT;
+BEGIN
+    IF NEW.action = 'purge' THEN
+        RETURN NULL;
+    END IF;
+    kind := NEW.entity_type || '.' || CASE NEW.action
+        WHEN 'create' THEN 'created'
+        WHEN 'restore' THEN 'created'
+        WHEN 'delete' THEN 'deleted'
+        WHEN 'move' THEN 'deleted'
+        ELSE 'updated'
+    END;
+    INSERT INTO webhook_delivery (webhook_id, event_id, event_type)
+    SELECT w.id, NEW.id, kind FROM webhook w
+    WHERE (w.teacher_id = NEW.teacher_id OR (w.teacher_id IS NULL AND NEW.action <> 'move')) AND kind = ANY (w.events);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,7 @@
+DROP INDEX IF EXISTS audit_log_teacher_commit_seq_idx;
+CREATE INDEX IF NOT EXISTS audit_log_teacher_idx ON audit_log (entity_type, teacher_id, id);
+
+DROP TRIGGER IF EXISTS audit_log_assign_commit_seq ON audit_log;
+DROP FUNCTION IF EXISTS assign_audit_commit_seq();
+ALTER TABLE audit_log DROP COLUMN IF EXISTS commit_seq;
+DROP SEQUENCE IF EXISTS audit_log_commit_seq;
@@ -0,0 +1,33 @@
+-- 变更记录提交的顺序, 课程变更事件 (SSE) 按这个顺序推送
+-- ? audit_log.id 在写入时分配, 事务提交的顺序可能不同: 较小的 id 可能晚提交, 只按 id 推送会跳过这条记录
+-- ? 提交之前由延迟的约束触发器分配 commit_seq, 分配之前先取得事务级的 advisory lock, 锁在事务结束之后才释放
+-- ?   所以拿到下一个 commit_seq 的事务, 一定在前一个事务提交之后, 读到某条记录时, 比它小的 commit_seq 都已经可见
+-- ? 代价: 写入变更记录的事务, 从分配 commit_seq 到提交完成 (包括写 WAL) 这一段是串行的
+-- ?   和 audit_log 无关的事务不受影响, 也不会让 SSE 等待
+-- ? 事务还没有提交时 commit_seq 是 NULL, 其他连接也读不到这些记录
+CREATE SEQUENCE IF NOT EXISTS audit_log_commit_seq;
+ALTER TABLE audit_log ADD COLUMN IF NOT EXISTS commit_seq BIGINT;
+
+-- 已有的记录都已经提交, 按 id 的顺序编号
+UPDATE audit_log SET commit_seq = ordered.seq
+FROM (SELECT id, row_number() OVER (ORDER BY id) AS seq FROM audit_log) ordered
+WHERE audit_log.id = ordered.id AND audit_log.commit_seq IS NULL;
+SELECT setval('audit_log_commit_seq', COALESCE(MAX(commit_seq), 0) + 1, false) FROM audit_log;
+
+-- advisory lock 的 key 使用 audit_log 表的 oid, 避免和其他地方的 advisory lock 冲突
+-- ? 所有老师共用一把锁: 一个事务可能写入多位老师的记录 (课程转给其他老师), 按老师加锁可能死锁
+CREATE OR REPLACE FUNCTION assign_audit_commit_seq() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock('audit_log'::regclass::oid::int, 0);
+    UPDATE audit_log SET commit_seq = nextval('audit_log_commit_seq') WHERE id = NEW.id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_assign_commit_seq ON audit_log;
+CREATE CONSTRAINT TRIGGER audit_log_assign_commit_seq AFTER INSERT ON audit_log
+    DEFERRABLE INITIALLY DEFERRED
+    FOR EACH ROW EXECUTE FUNCTION assign_audit_commit_seq();
+
+DROP INDEX IF EXISTS audit_log_teacher_idx;
+CREATE INDEX IF NOT EXISTS audit_log_teacher_commit_seq_idx ON audit_log (entity_type, teacher_id, commit_seq);
@@ -0,0 +1,89 @@
+use super::Principal;
+use crate::errors::MyError;
+use crate::state::AppState;
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, Method};
+use actix_web::{web, Error, HttpMessage};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+
+// 认证中间件, 挂在课程和老师的 scope 上
+// ? 带了 Authorization: Bearer <access_token> 时校验 token, 通过后把 Principal 放进请求的 extensions
+// ? GET 等只读请求可以不登录, POST, PUT, PATCH, DELETE 必须登录, 否则返回 401
+pub struct RequireAuth;
+
+impl<S, B> Transform<S, ServiceRequest> for RequireAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireAuthMiddleware { service }))
+    }
+}
+
+pub struct RequireAuthMiddleware<S> {
+    service: S,
+}
+
+// 只读的请求方法, 不需要登录
+fn is_safe_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+// 从请求头中解析出登录身份, 没有带 token 时返回 None
+fn authenticate(req: &ServiceRequest) -> Result<Option<Principal>, MyError> {
+    let value = match req.headers().get(header::AUTHORIZATION) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let token = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| MyError::Unauthorized("Authorization header must be Bearer <token>".into()))?;
+    let app_state = req
+        .app_data::<web::Data<AppState>>()
+        .ok_or_else(|| MyError::ActixError("AppState is not registered".into()))?;
+    app_state.tokens.verify_access(token.trim()).map(Some)
+}
+
+impl<S, B> Service<ServiceRequest> for RequireAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let principal = match authenticate(&req) {
+            Ok(Some(principal)) => principal,
+            Ok(None) if is_safe_method(req.method()) => {
+                let fut = self.service.call(req);
+                return Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) });
+            }
+            Ok(None) => {
+                let err = MyError::Unauthorized("Authentication required".into());
+                return Box::pin(async move { Ok(req.error_response(err).map_into_right_body()) });
+            }
+            // token 不合法时即使是 GET 也拒绝, 避免客户端以为自己已经登录
+            Err(err) => return Box::pin(async move { Ok(req.error_response(err).map_into_right_body()) }),
+        };
+        // 记录到请求的 span 上, 日志中能看到是谁发起的请求
+        tracing::Span::current().record("principal", principal.teacher_id);
+        req.extensions_mut().insert(principal);
+        let fut = self.service.call(req);
+        Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) })
+    }
+}
@@ -0,0 +1,190 @@
+// 登录认证
+// 密码使用 argon2 哈希保存, 登录成功后签发 JWT 格式的 access token 和 refresh token
+// 中间件在 middleware.rs 中, 负责校验 access token 并拒绝未登录的修改请求
+pub mod middleware;
+
+use crate::errors::MyError;
+use crate::models::auth::{Role, TokenResponse};
+use actix_web::{dev::Payload, FromRequest, HttpMessage, HttpRequest};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::future::{ready, Ready};
+
+// access token 有效期短, 过期之后用 refresh token 换一对新的
+pub const ACCESS_TOKEN_TTL_MINUTES: i64 = 15;
+pub const REFRESH_TOKEN_TTL_DAYS: i64 = 7;
+
+// 登录之后的身份, 中间件校验 access token 之后放到请求的 extensions 中
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Principal {
+    pub teacher_id: i32,
+    pub role: Role,
+}
+
+impl Principal {
+    #[cfg(test)]
+    pub fn teacher(teacher_id: i32) -> Self {
+        Principal { teacher_id, role: Role::Teacher }
+    }
+
+    #[cfg(test)]
+    pub fn admin(teacher_id: i32) -> Self {
+        Principal { teacher_id, role: Role::Admin }
+    }
+
+    /**
+     * 校验能否修改某个老师名下的数据, 老师只能修改自己的, 管理员不受限制
+     * ? 课程的查询和修改都带着路径中的 teacher_id, 课程不属于这个老师时查不到, 所以只需要校验路径中的 teacher_id
+     */
+    pub fn authorize(&self, teacher_id: i32) -> Result<(), MyError> {
+        if self.role == Role::Admin || self.teacher_id == teacher_id {
+            Ok(())
+        } else {
+            Err(MyError::Forbidden(format!(
+                "Teacher {} is not allowed to modify data of teacher {}",
+                self.teacher_id, teacher_id
+            )))
+        }
+    }
+
+    // 只有管理员才能执行的操作
+    pub fn require_admin(&self) -> Result<(), MyError> {
+        match self.role {
+            Role::Admin => Ok(()),
+            Role::Teacher => Err(MyError::Forbidden("Only admins are allowed to do this".into())),
+        }
+    }
+}
+
+// handler 的参数里写 principal: Principal, 没有登录时返回 401
+impl FromRequest for Principal {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<Principal>()
+                .copied()
+                .ok_or_else(|| MyError::Unauthorized("Authentication required".into())),
+        )
+    }
+}
+
+/**
+ * 用 argon2 哈希密码, 每次生成随机的盐
+ * ? 哈希比较耗 CPU, handler 中需要放到 web::block 里执行
+ */
+pub fn hash_password(password: &str) -> Result<String, MyError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| MyError::ActixError(format!("Unable to hash password: {}", err)))
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        .unwrap_or(false)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TokenKind {
+    Access,
+    Refresh,
+}
+
+// JWT 中保存的内容
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String, // 老师的 id
+    typ: TokenKind,
+    // 之前签发的 token 中没有 role, 按 teacher 处理
+    #[serde(default)]
+    role: Role,
+    iat: i64,
+    exp: i64,
+}
+
+// 签发和校验 token, 使用 HS256 对称签名
+pub struct TokenService {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl TokenService {
+    pub fn new(secret: &[u8]) -> Self {
+        TokenService {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+
+    // 密钥从环境变量 JWT_SECRET 读取
+    // ? 没有配置时生成一个随机密钥, 只适合本地开发, 重启之后之前签发的 token 都会失效
+    pub fn from_env() -> Self {
+        match env::var("JWT_SECRET") {
+            Ok(secret) if !secret.is_empty() => TokenService::new(secret.as_bytes()),
+            _ => {
+                tracing::warn!("JWT_SECRET 没有配置, 使用随机密钥, 重启后需要重新登录");
+                let mut secret = [0u8; 32];
+                OsRng.fill_bytes(&mut secret);
+                TokenService::new(&secret)
+            }
+        }
+    }
+
+    fn sign(&self, principal: Principal, typ: TokenKind, ttl: Duration) -> Result<String, MyError> {
+        let now = Utc::now();
+        let claims = Claims {
+            sub: principal.teacher_id.to_string(),
+            typ,
+            role: principal.role,
+            iat: now.timestamp(),
+            exp: (now + ttl).timestamp(),
+        };
+        encode(&Header::default(), &claims, &self.encoding)
+            .map_err(|err| MyError::ActixError(format!("Unable to sign token: {}", err)))
+    }
+
+    // 登录或者刷新成功之后签发一对新的 token
+    pub fn issue(&self, principal: Principal) -> Result<TokenResponse, MyError> {
+        let access_ttl = Duration::minutes(ACCESS_TOKEN_TTL_MINUTES);
+        Ok(TokenResponse {
+            access_token: self.sign(principal, TokenKind::Access, access_ttl)?,
+            refresh_token: self.sign(principal, TokenKind::Refresh, Duration::days(REFRESH_TOKEN_TTL_DAYS))?,
+            token_type: "Bearer".into(),
+            expires_in: access_ttl.num_seconds(),
+        })
+    }
+
+    fn verify(&self, token: &str, expected: TokenKind) -> Result<Principal, MyError> {
+        let claims = decode::<Claims>(token, &self.decoding, &Validation::default())
+            .map_err(|_err| MyError::Unauthorized("Invalid or expired token".into()))?
+            .claims;
+        // refresh token 不能当作 access token 使用, 反之亦然
+        if claims.typ != expected {
+            return Err(MyError::Unauthorized("Invalid or expired token".into()));
+        }
+        let teacher_id = claims
+            .sub
+            .parse::<i32>()
+            .map_err(|_err| MyError::Unauthorized("Invalid or expired token".into()))?;
+        Ok(Principal { teacher_id, role: claims.role })
+    }
+
+    pub fn verify_access(&self, token: &str) -> Result<Principal, MyError> {
+        self.verify(token, TokenKind::Access)
+    }
+
+    pub fn verify_refresh(&self, token: &str) -> Result<Principal, MyError> {
+        self.verify(token, TokenKind::Refresh)
+    }
+}
@@ -1,11 +1,19 @@
-use actix_web::{web, App, HttpServer, http};
+use actix_web::{error::JsonPayloadError, web, App, HttpServer, http};
 use dotenv::dotenv;
 use std::env;
 use std::io;
-use std::sync::Mutex;
+use std::sync::Arc;
 use actix_cors::Cors;
+use auth::TokenService;
+use chrono::Duration;
 
 // 定义模块
+#[path = "../auth/mod.rs"]
+mod auth;
+#[path = "../cache.rs"]
+mod cache;
+#[path = "../config.rs"]
+mod config;
 #[path = "../db_access/mod.rs"]
 mod db_access;
 #[path = "../handlers/mod.rs"]
@@ -14,65 +22,246 @@ mod handlers;
 mod models;
 #[path = "../routers.rs"]
 mod routers;
+#[path = "../shutdown.rs"]
+mod shutdown;
 #[path = "../state.rs"]
 mod state;
+#[path = "../telemetry.rs"]
+mod telemetry;
+#[path = "../events.rs"]
+mod events;
 #[path = "../errors.rs"]
 mod errors;
+#[path = "../metrics.rs"]
+mod metrics;
+#[path = "../migrations.rs"]
+mod migrations;
+#[path = "../rate_limit.rs"]
+mod rate_limit;
+#[path = "../request_id.rs"]
+mod request_id;
+#[path = "../webhooks.rs"]
+mod webhooks;
 
+use config::{Backend, CliArgs, CorsSettings, DatabaseSettings, Settings};
+use db_access::memory::{MemoryCourseRepository, MemoryTeacherRepository, MemoryWebhookRepository};
+use db_access::postgres::{PgCourseRepository, PgTeacherRepository, PgWebhookRepository};
+use db_access::{CourseRepository, TeacherRepository, WebhookRepository};
+use request_id::{AssignRequestId, REQUEST_ID_HEADER};
+use handlers::general::not_found_handler;
+use metrics::{Metrics, RecordMetrics};
 use routers::*;
-use sqlx::{postgres::PgPoolOptions, Executor};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use shutdown::Shutdown;
 use state::AppState;
 
 use crate::errors::MyError;
 
-// 异步 main
-#[actix_rt::main]
-async fn main() -> io::Result<()> {
-    // 读取环境变量
-    dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-    // 创建数据库连接池
-    let db_pool = PgPoolOptions::new()
-        .after_connect(|conn, _x| {
+// 创建数据库连接池, 连接数, 超时和会话时区都来自配置
+async fn connect_db_pool(settings: &DatabaseSettings) -> PgPool {
+    let database_url = settings.url.clone().expect("database.url is not defined");
+    let timezone = settings.timezone.clone();
+    PgPoolOptions::new()
+        .min_connections(settings.min_connections)
+        .max_connections(settings.max_connections)
+        .acquire_timeout(settings.acquire_timeout())
+        .idle_timeout(settings.idle_timeout())
+        .max_lifetime(settings.max_lifetime())
+        .after_connect(move |conn, _x| {
+            let timezone = timezone.clone();
             Box::pin(async move {
-                conn.execute("SET TIME ZONE 'Asia/Shanghai';").await?;
+                // 时区作为参数传入, 不拼接 SQL
+                sqlx::query("SELECT set_config('TimeZone', $1, false)").bind(timezone).execute(conn).await?;
                 Ok(())
             })
         })
         .connect(&database_url)
         .await
-        .expect("Could not create database pool");
+        .expect("Could not create database pool")
+}
+
+// 允许跨域的来源和方法来自配置, 配置已经校验过, 这里不会失败
+fn cors(settings: &CorsSettings) -> Cors {
+    let mut cors = Cors::default();
+    for origin in &settings.allowed_origins {
+        cors = cors.allowed_origin(origin);
+    }
+    if settings.allow_localhost {
+        cors = cors.allowed_origin_fn(|origin, _req_head| {
+            // 任意端口的 http://localhost 和 http://127.0.0.1
+            let origin = origin.to_str().unwrap_or_default();
+            ["http://localhost", "http://127.0.0.1"].iter().any(|host| {
+                origin
+                    .strip_prefix(host)
+                    .is_some_and(|port| port.is_empty() || port.strip_prefix(':').is_some_and(|port| port.parse::<u16>().is_ok()))
+            })
+        });
+    }
+    cors.allowed_methods(settings.allowed_methods.iter().map(String::as_str)) // 允许的请求方法
+        .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
+        .allowed_header(http::header::CONTENT_TYPE) // 允许的请求头
+        .allowed_header(http::header::IF_MATCH) // 乐观锁, 修改时带上 GET 返回的 ETag
+        .allowed_headers(vec![http::header::IF_NONE_MATCH, http::header::IF_MODIFIED_SINCE]) // 条件请求, 没有变化时返回 304
+        .allowed_header(http::header::HeaderName::from_static("last-event-id")) // EventSource 重连时带上最后收到的事件 id
+        .expose_headers(vec![http::header::ETAG, http::header::LAST_MODIFIED]) // 前端才能读到响应中的 ETag 和 Last-Modified
+        .expose_headers(vec![http::header::HeaderName::from_static(REQUEST_ID_HEADER)]) // 报告问题时带上请求 id
+        // 限流的剩余次数和重试时间
+        .expose_headers(["ratelimit-limit", "ratelimit-remaining", "ratelimit-reset", "ratelimit-policy", "retry-after"])
+        .max_age(settings.max_age) // 预检请求的结果缓存的秒数
+}
+
+// 每小时清理一次软删除时间超过 retention 的课程和老师
+async fn purge_trash_periodically(app_state: web::Data<AppState>, retention: Duration) {
+    let mut interval = actix_rt::time::interval(std::time::Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        // 先清理课程, 再清理老师
+        match app_state.courses.purge_deleted_courses(retention).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!(purged, "purged deleted courses"),
+            Err(err) => tracing::error!(error = %err, "failed to purge deleted courses"),
+        }
+        match app_state.teachers.purge_deleted_teachers(retention).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!(purged, "purged deleted teachers"),
+            Err(err) => tracing::error!(error = %err, "failed to purge deleted teachers"),
+        }
+    }
+}
+
+// 异步 main
+#[actix_rt::main]
+async fn main() -> io::Result<()> {
+    // 读取环境变量
+    dotenv().ok();
+    // 配置: 默认值 -> 配置文件 -> 环境变量 -> 命令行参数, 有误时列出所有问题并退出
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (settings, args) = match CliArgs::parse(&args).and_then(|cli| Ok((Settings::load(&cli)?, cli.rest))) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            // 日志还没有初始化, 直接输出到 stderr
+            eprintln!("invalid configuration:");
+            for problem in &err.0 {
+                eprintln!("  - {}", problem);
+            }
+            std::process::exit(2);
+        }
+    };
+    telemetry::init_tracing(&settings.log);
+    // teacher-service migrate <up|down|status>, 只执行迁移, 不启动服务
+    if args.first().map(String::as_str) == Some("migrate") {
+        let db_pool = connect_db_pool(&settings.database).await;
+        return migrations::run_migrate_command(&db_pool, &args[1..])
+            .await
+            .map_err(|err| io::Error::other(err.to_string()));
+    }
+    // 选择数据存储, 默认 postgres, database.backend 为 memory 时可以不依赖数据库运行
+    // ? 连接池在这里留一份, 停机时关闭
+    let mut db_pool_to_close = None;
+    let (courses, teachers, webhooks): (Arc<dyn CourseRepository>, Arc<dyn TeacherRepository>, Arc<dyn WebhookRepository>) = match settings.database.backend {
+        Backend::Memory => {
+            tracing::warn!("使用内存存储, 重启后数据会丢失");
+            let courses = Arc::new(MemoryCourseRepository::new());
+            (
+                courses.clone(),
+                Arc::new(MemoryTeacherRepository::new(courses.clone())),
+                Arc::new(MemoryWebhookRepository::new(courses)),
+            )
+        }
+        Backend::Postgres => {
+            let db_pool = connect_db_pool(&settings.database).await;
+            // 启动时自动执行未执行的迁移, database.auto_migrate 为 false 时关闭
+            if settings.database.auto_migrate {
+                migrations::migrate_up(&db_pool)
+                    .await
+                    .expect("Could not run database migrations");
+            }
+            db_pool_to_close = Some(db_pool.clone());
+            let courses = PgCourseRepository::new(db_pool.clone(), settings.database.max_connections);
+            // 接收所有实例上的课程变更通知 (LISTEN), 推送给本实例的 SSE 连接, 连接池关闭时退出
+            actix_rt::spawn(events::listen_for_course_changes(db_pool.clone(), courses.changes()));
+            (
+                Arc::new(courses),
+                Arc::new(PgTeacherRepository::new(db_pool.clone())),
+                Arc::new(PgWebhookRepository::new(db_pool)),
+            )
+        }
+    };
     // 创建共享state
     let shared_data = web::Data::new(AppState {
-        health_check_response: "I'm OK.".to_string(),
-        visit_count: Mutex::new(0),
-        db: db_pool,
+        metrics: Metrics::new(),
+        courses,
+        teachers,
+        webhooks,
+        tokens: TokenService::from_env(),
+        rate_limits: settings.rate_limit.limits(),
+        cache: settings.cache.policies(),
+        webhook_policy: settings.webhooks.policy(),
+        shutdown: Shutdown::new(),
     });
+    // 定期清理回收站, 保留天数见配置中的 trash.retention_days
+    actix_rt::spawn(purge_trash_periodically(shared_data.clone(), settings.trash.retention()));
+    // 发送到期的 webhook 投递, 开始停机时退出
+    if settings.webhooks.enabled {
+        actix_rt::spawn(webhooks::deliver_periodically(shared_data.clone()));
+    }
     // app是一个闭包, 就是创建一个 web 应用
+    let server = settings.server.clone();
+    let app_state = shared_data.clone();
+    let cors_settings = settings.cors.clone();
     let app = move || {
-        let cors = Cors::default()
-            .allowed_origin("http://localhost:8080/")
-            .allowed_origin_fn(|origin, _req_head| {
-                // 允许所有以 localhost 开头的域
-                origin.as_bytes().starts_with(b"http://localhost")
-            })
-            .allowed_methods(vec!["GET", "POST", "DELETE"]) // 允许的请求方法
-            .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
-            .allowed_header(http::header::CONTENT_TYPE) // 允许的请求头
-            .max_age(3600); // 3600s未响应就截断
-
         App::new()
             // 注入 注册共享state, 此时就可以向 handler 中注入数据了
             .app_data(shared_data.clone())
-            .app_data(web::JsonConfig::default().error_handler(|_err, _req| {
+            // 批量导入以 Bytes 读取请求体, 使用同样的大小限制
+            .app_data(web::PayloadConfig::new(server.json_limit))
+            .app_data(web::JsonConfig::default().limit(server.json_limit).error_handler(|err, _req| {
                 // 注册拦截不合法请求, 如果检测到前端传递不合法输入, 就会进入
-                MyError::InvalidInput("Please provide valid json input".to_string()).into()
+                // ? 字段的值不合法时 (例如课程等级不在允许的范围内) 带上具体原因
+                let message = match err {
+                    // 超过 server.json_limit 时保留 413
+                    JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => return err.into(),
+                    JsonPayloadError::Deserialize(err) => format!("Please provide valid json input: {}", err),
+                    _ => "Please provide valid json input".to_string(),
+                };
+                MyError::InvalidInput(message).into()
             }))
             .configure(general_routes)
+            .configure(auth_routes)
             .configure(course_routes)
-            .wrap(cors)
+            .wrap(cors(&cors_settings))
             .configure(teacher_routes) // 注册老师路由
+            .configure(webhook_routes)
+            // 没有匹配的路由时同样返回 problem+json
+            .default_service(web::route().to(not_found_handler))
+            // 统计所有请求, 在 AssignRequestId 里面, 还能拿到响应中的错误
+            .wrap(RecordMetrics)
+            // 放在最后, 也就是最外层, 所有的错误响应都会经过它
+            .wrap(AssignRequestId)
     };
-    println!("监听到了端口 localhost:3000");
-    HttpServer::new(app).bind("127.0.0.1:3000")?.run().await
+    // 信号由 shutdown.rs 处理, 先让就绪检查失败, 再停止服务
+    let mut http_server = HttpServer::new(app)
+        .disable_signals()
+        .shutdown_timeout(settings.server.shutdown_timeout_secs);
+    if let Some(workers) = settings.server.workers {
+        http_server = http_server.workers(workers);
+    }
+    for address in &settings.server.bind {
+        http_server = http_server.bind(address)?;
+        tracing::info!(%address, "监听到了端口");
+    }
+    let running = http_server.run();
+    actix_rt::spawn(shutdown::drain_on_signal(
+        running.handle(),
+        app_state,
+        std::time::Duration::from_secs(settings.server.shutdown_delay_secs),
+    ));
+    running.await?;
+    // 所有请求都已经结束或者超时断开, 关闭连接池, 数据库那边的连接会正常断开, 不会留下空闲的会话
+    if let Some(db_pool) = db_pool_to_close {
+        db_pool.close().await;
+        tracing::info!("database pool closed");
+    }
+    tracing::info!("server stopped");
+    Ok(())
 }
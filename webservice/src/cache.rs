@@ -0,0 +1,224 @@
+// 条件请求和 HTTP 缓存, 用在课程和老师的查询接口上
+// 响应带上 ETag, Last-Modified 和 Cache-Control, 客户端下次请求时通过 If-None-Match 或者 If-Modified-Since 带回来
+// 内容没有变化时返回 304, 不带响应体
+// ? 单个课程或老师的 ETag 是版本号, 和 If-Match 使用的相同 (见 models/version.rs)
+// ? 列表的 ETag 是响应体的哈希, 没有 Last-Modified, 只带 If-Modified-Since 时总是返回 200
+// ?   列表中删除或者转走了一条记录时, 剩下记录的 updated_at 都没有变化, 用它们作为 Last-Modified 会得到过期的 304
+// ? 同时带了两个请求头时按 RFC 7232 只看 If-None-Match
+// ? Cache-Control 按路由配置, 见 config.rs 中的 cache
+use crate::errors::MyError;
+use crate::models::version::etag;
+use actix_web::http::header::{self, ContentType, EntityTag, ETag, Header, HeaderValue, HttpDate, IfModifiedSince, IfNoneMatch, LastModified};
+use actix_web::{dev::Payload, FromRequest, HttpRequest, HttpResponse, HttpResponseBuilder};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::future::{ready, Ready};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 默认每次使用缓存之前都要向服务端确认
+pub const DEFAULT_CACHE_CONTROL: &str = "no-cache";
+
+// 分别配置 Cache-Control 的查询接口
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheRoute {
+    CourseDetail,
+    CourseList,
+    TeacherDetail,
+    TeacherList,
+}
+
+// 每个查询接口的 Cache-Control, 启动时已经校验过, 放在 AppState 中
+#[derive(Debug, Clone)]
+pub struct CachePolicies {
+    course_detail: HeaderValue,
+    course_list: HeaderValue,
+    teacher_detail: HeaderValue,
+    teacher_list: HeaderValue,
+}
+
+impl CachePolicies {
+    pub fn new(course_detail: HeaderValue, course_list: HeaderValue, teacher_detail: HeaderValue, teacher_list: HeaderValue) -> Self {
+        CachePolicies {
+            course_detail,
+            course_list,
+            teacher_detail,
+            teacher_list,
+        }
+    }
+
+    pub fn cache_control(&self, route: CacheRoute) -> &HeaderValue {
+        match route {
+            CacheRoute::CourseDetail => &self.course_detail,
+            CacheRoute::CourseList => &self.course_list,
+            CacheRoute::TeacherDetail => &self.teacher_detail,
+            CacheRoute::TeacherList => &self.teacher_list,
+        }
+    }
+}
+
+impl Default for CachePolicies {
+    fn default() -> Self {
+        let value = HeaderValue::from_static(DEFAULT_CACHE_CONTROL);
+        CachePolicies::new(value.clone(), value.clone(), value.clone(), value)
+    }
+}
+
+// 响应体的 FNV-1a 哈希, 作为列表的 ETag
+// ? 不需要防碰撞, 只要同样的内容得到同样的值
+pub fn content_etag(body: &[u8]) -> EntityTag {
+    let hash = body.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    EntityTag::new_strong(format!("{:016x}", hash))
+}
+
+// Last-Modified 只精确到秒, 比较时也按秒
+fn to_http_date(time: DateTime<Utc>) -> HttpDate {
+    let secs = u64::try_from(time.timestamp()).unwrap_or(0);
+    HttpDate::from(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+// If-None-Match 和 If-Modified-Since 请求头
+#[derive(Debug, Clone, Default)]
+pub struct ConditionalGet {
+    if_none_match: Option<IfNoneMatch>,
+    if_modified_since: Option<SystemTime>,
+}
+
+impl ConditionalGet {
+    // 没有带条件, 总是返回 200
+    #[cfg(test)]
+    pub fn none() -> Self {
+        ConditionalGet::default()
+    }
+
+    #[cfg(test)]
+    pub fn if_none_match(tag: EntityTag) -> Self {
+        ConditionalGet {
+            if_none_match: Some(IfNoneMatch::Items(vec![tag])),
+            if_modified_since: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn if_modified_since(time: DateTime<Utc>) -> Self {
+        ConditionalGet {
+            if_none_match: None,
+            if_modified_since: Some(to_http_date(time).into()),
+        }
+    }
+
+    // 客户端的缓存是否还可以使用, If-None-Match 用弱比较
+    fn not_modified(&self, tag: &EntityTag, last_modified: Option<DateTime<Utc>>) -> bool {
+        match (&self.if_none_match, self.if_modified_since, last_modified) {
+            (Some(IfNoneMatch::Any), _, _) => true,
+            (Some(IfNoneMatch::Items(tags)), _, _) => tags.iter().any(|item| item.weak_eq(tag)),
+            (None, Some(since), Some(modified)) => SystemTime::from(to_http_date(modified)) <= since,
+            _ => false,
+        }
+    }
+
+    // 单个课程或老师, ETag 是版本号
+    pub fn entity<T: Serialize>(
+        &self,
+        cache_control: &HeaderValue,
+        version: i32,
+        last_modified: DateTime<Utc>,
+        body: &T,
+    ) -> Result<HttpResponse, MyError> {
+        let tag = etag(version).0;
+        if self.not_modified(&tag, Some(last_modified)) {
+            return Ok(validators(HttpResponse::NotModified(), cache_control, tag, Some(last_modified)).finish());
+        }
+        Ok(validators(HttpResponse::Ok(), cache_control, tag, Some(last_modified))
+            .insert_header(ContentType::json())
+            .body(serialize(body)?))
+    }
+
+    // 列表, ETag 是响应体的哈希, 没有 Last-Modified
+    pub fn collection<T: Serialize>(&self, cache_control: &HeaderValue, body: &T) -> Result<HttpResponse, MyError> {
+        let bytes = serialize(body)?;
+        let tag = content_etag(&bytes);
+        if self.not_modified(&tag, None) {
+            return Ok(validators(HttpResponse::NotModified(), cache_control, tag, None).finish());
+        }
+        Ok(validators(HttpResponse::Ok(), cache_control, tag, None)
+            .insert_header(ContentType::json())
+            .body(bytes))
+    }
+}
+
+// 200 和 304 都带上同样的 ETag, Last-Modified 和 Cache-Control
+fn validators(
+    mut builder: HttpResponseBuilder,
+    cache_control: &HeaderValue,
+    tag: EntityTag,
+    last_modified: Option<DateTime<Utc>>,
+) -> HttpResponseBuilder {
+    builder.insert_header(ETag(tag)).insert_header((header::CACHE_CONTROL, cache_control.clone()));
+    if let Some(modified) = last_modified {
+        builder.insert_header(LastModified(to_http_date(modified)));
+    }
+    builder
+}
+
+fn serialize<T: Serialize>(body: &T) -> Result<Vec<u8>, MyError> {
+    serde_json::to_vec(body).map_err(|err| MyError::ActixError(err.to_string()))
+}
+
+impl FromRequest for ConditionalGet {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    // ? 格式不对的条件请求头按没有传处理 (RFC 7232), 不返回 400
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let if_none_match = req
+            .headers()
+            .contains_key(header::IF_NONE_MATCH)
+            .then(|| IfNoneMatch::parse(req).ok())
+            .flatten();
+        let if_modified_since = req
+            .headers()
+            .contains_key(header::IF_MODIFIED_SINCE)
+            .then(|| IfModifiedSince::parse(req).ok())
+            .flatten()
+            .map(|IfModifiedSince(date)| SystemTime::from(date));
+        ready(Ok(ConditionalGet {
+            if_none_match,
+            if_modified_since,
+        }))
+    }
+}
+
+// * 测试
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    #[actix_rt::test]
+    async fn malformed_conditions_are_ignored() {
+        let (req, mut payload) = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "not a tag"))
+            .insert_header((header::IF_MODIFIED_SINCE, "yesterday"))
+            .to_http_parts();
+        let conditions = ConditionalGet::from_request(&req, &mut payload).await.unwrap();
+        let res = conditions.entity(&HeaderValue::from_static("no-cache"), 1, Utc::now(), &"body").unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let modified = Utc::now();
+        let conditions = ConditionalGet {
+            if_none_match: Some(IfNoneMatch::Items(vec![EntityTag::new_strong("1".into())])),
+            if_modified_since: Some(SystemTime::now() + Duration::from_secs(60)),
+        };
+        assert!(!conditions.not_modified(&etag(2).0, Some(modified)));
+        assert!(conditions.not_modified(&etag(1).0, Some(modified)));
+        // Last-Modified 按秒截断, 同一秒内的修改也算没有变化
+        assert!(ConditionalGet::if_modified_since(modified).not_modified(&etag(2).0, Some(modified)));
+        assert!(!ConditionalGet::if_modified_since(modified - chrono::Duration::seconds(1)).not_modified(&etag(2).0, Some(modified)));
+    }
+}
@@ -0,0 +1,550 @@
+// teacher-service 的配置
+// 按优先级从低到高分层合并: 代码中的默认值 -> 配置文件 -> 环境变量 -> 命令行参数
+// ? 配置文件默认是 config/teacher-service.toml, 不存在时跳过, 可以用 --config 或者 TEACHER_CONFIG 指定, 示例见 config/teacher-service.example.toml
+// ? 环境变量以 TEACHER_ 开头, 层级之间用 __ 分隔, 例如 TEACHER_SERVER__WORKERS=4, 列表用逗号分隔
+// ? 原来的 DATABASE_URL, DB_BACKEND, AUTO_MIGRATE, DB_MAX_CONNECTIONS, LOG_FORMAT, LOG_LEVEL, TRASH_RETENTION_DAYS 仍然有效, 优先于 TEACHER_ 开头的变量
+// ? 命令行参数: --config <文件> --bind <地址> (可以重复) --workers <数量> --set <键>=<值> (可以重复, 例如 --set database.timezone=UTC)
+use crate::cache::{CachePolicies, DEFAULT_CACHE_CONTROL};
+use crate::rate_limit::{RateLimitPolicy, RateLimits};
+use crate::telemetry::{LogFormat, DEFAULT_LOG_LEVEL};
+use crate::webhooks::WebhookPolicy;
+use actix_web::http::header::HeaderValue;
+use actix_web::http::Method;
+use config::{Config, Environment, File, FileFormat};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+
+pub const DEFAULT_CONFIG_FILE: &str = "config/teacher-service.toml";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub cors: CorsSettings,
+    pub database: DatabaseSettings,
+    pub rate_limit: RateLimitSettings,
+    pub cache: CacheSettings,
+    pub webhooks: WebhookSettings,
+    pub trash: TrashSettings,
+    pub log: LogSettings,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerSettings {
+    // 监听的地址, 可以同时监听多个, 例如 IPv4 和 IPv6
+    pub bind: Vec<String>,
+    // worker 线程数, 不配置时和 CPU 核数相同
+    pub workers: Option<usize>,
+    // JSON 请求体的最大字节数, 批量导入课程 (CSV 或 JSON) 的请求体也使用这个限制
+    pub json_limit: usize,
+    // 收到停机信号之后, 先让就绪检查失败, 等待多少秒再停止接受新连接, 给负载均衡摘除的时间
+    pub shutdown_delay_secs: u64,
+    // 停止接受新连接之后, 最多等待正在处理的请求多少秒
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CorsSettings {
+    // 允许跨域的前端地址, 例如 https://teacher.example.com, 不带路径和结尾的 /
+    pub allowed_origins: Vec<String>,
+    // 开发时允许任意端口的 http://localhost 和 http://127.0.0.1
+    pub allow_localhost: bool,
+    pub allowed_methods: Vec<String>,
+    // 预检请求的结果可以缓存多少秒
+    pub max_age: usize,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Postgres,
+    Memory, // 不依赖数据库运行, 重启后数据会丢失
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct DatabaseSettings {
+    pub backend: Backend,
+    // 使用 postgres 时必须配置
+    pub url: Option<String>,
+    // 启动时自动执行未执行的迁移
+    pub auto_migrate: bool,
+    pub min_connections: u32,
+    pub max_connections: u32,
+    // 从连接池获取连接最多等待的秒数
+    pub acquire_timeout_secs: u64,
+    // 空闲连接多少秒之后关闭, 0 表示不关闭
+    pub idle_timeout_secs: u64,
+    // 连接最长使用多少秒之后重建, 0 表示不限制
+    pub max_lifetime_secs: u64,
+    // 每个连接建立之后设置的会话时区, 例如 Asia/Shanghai, UTC
+    pub timezone: String,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RateLimitSettings {
+    pub enabled: bool,
+    // 在反向代理后面时打开, 按 X-Forwarded-For 中由代理追加的最后一个地址计数
+    pub trust_proxy_headers: bool,
+    pub courses: RateLimitPolicy,
+    pub teachers: RateLimitPolicy,
+}
+
+impl RateLimitSettings {
+    pub fn limits(&self) -> RateLimits {
+        let enabled = |policy: RateLimitPolicy| Some(policy).filter(|_| self.enabled);
+        RateLimits::new(enabled(self.courses), enabled(self.teachers), self.trust_proxy_headers)
+    }
+}
+
+// 查询接口的 Cache-Control, 原样写到响应头中, 例如 "no-cache" 或者 "public, max-age=60"
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CacheSettings {
+    pub course_detail: String,
+    pub course_list: String,
+    pub teacher_detail: String,
+    pub teacher_list: String,
+}
+
+impl CacheSettings {
+    fn entries(&self) -> [(&'static str, &String); 4] {
+        [
+            ("course_detail", &self.course_detail),
+            ("course_list", &self.course_list),
+            ("teacher_detail", &self.teacher_detail),
+            ("teacher_list", &self.teacher_list),
+        ]
+    }
+
+    // 在 validate 之后调用, 非法的值已经在启动时报错
+    pub fn policies(&self) -> CachePolicies {
+        let value = |value: &String| HeaderValue::from_str(value).unwrap_or_else(|_err| HeaderValue::from_static(DEFAULT_CACHE_CONTROL));
+        CachePolicies::new(
+            value(&self.course_detail),
+            value(&self.course_list),
+            value(&self.teacher_detail),
+            value(&self.teacher_list),
+        )
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct WebhookSettings {
+    // 是否启动投递任务
+    pub enabled: bool,
+    // 最多发送的次数, 超过之后进入死信
+    pub max_attempts: i32,
+    // 第一次失败之后等待的秒数, 之后每次翻倍, 最多等待 max_backoff_secs 秒
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    // 一次请求的超时秒数
+    pub timeout_secs: u64,
+    // 检查到期投递的间隔秒数
+    pub poll_interval_secs: u64,
+    pub batch_size: i64,
+    // 允许 localhost 和内网的接收地址, 只在开发和测试时打开
+    pub allow_private_targets: bool,
+}
+
+impl WebhookSettings {
+    pub fn policy(&self) -> WebhookPolicy {
+        WebhookPolicy {
+            enabled: self.enabled,
+            max_attempts: self.max_attempts,
+            initial_backoff: Duration::from_secs(self.initial_backoff_secs),
+            max_backoff: Duration::from_secs(self.max_backoff_secs),
+            timeout: Duration::from_secs(self.timeout_secs),
+            poll_interval: Duration::from_secs(self.poll_interval_secs),
+            batch_size: self.batch_size,
+            allow_private_targets: self.allow_private_targets,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TrashSettings {
+    // 软删除的课程和老师在回收站中保留的天数, 之后由后台任务彻底删除
+    pub retention_days: i64,
+}
+
+impl TrashSettings {
+    pub fn retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.retention_days)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct LogSettings {
+    pub format: LogFormat,
+    // 过滤规则, 语法和 RUST_LOG 相同
+    pub level: String,
+}
+
+impl DatabaseSettings {
+    pub fn acquire_timeout(&self) -> Duration {
+        Duration::from_secs(self.acquire_timeout_secs)
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        Some(self.idle_timeout_secs).filter(|secs| *secs > 0).map(Duration::from_secs)
+    }
+
+    pub fn max_lifetime(&self) -> Option<Duration> {
+        Some(self.max_lifetime_secs).filter(|secs| *secs > 0).map(Duration::from_secs)
+    }
+}
+
+// 配置文件, 环境变量或者命令行参数有误, 启动失败
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.join("; "))
+    }
+}
+
+impl From<config::ConfigError> for ConfigError {
+    fn from(err: config::ConfigError) -> Self {
+        ConfigError(vec![err.to_string()])
+    }
+}
+
+// 命令行中的配置参数, 其余的参数 (例如 migrate up) 原样保留
+#[derive(Debug, Default, PartialEq)]
+pub struct CliArgs {
+    pub config_file: Option<String>,
+    pub bind: Vec<String>,
+    pub overrides: Vec<(String, String)>,
+    pub rest: Vec<String>,
+}
+
+impl CliArgs {
+    pub fn parse(args: &[String]) -> Result<CliArgs, ConfigError> {
+        let mut cli = CliArgs::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| ConfigError(vec![format!("{} requires a value", name)]))
+            };
+            match arg.as_str() {
+                "--config" => cli.config_file = Some(value("--config")?),
+                "--bind" => cli.bind.push(value("--bind")?),
+                "--workers" => cli.overrides.push(("server.workers".into(), value("--workers")?)),
+                "--set" => {
+                    let pair = value("--set")?;
+                    let (key, value) = pair
+                        .split_once('=')
+                        .ok_or_else(|| ConfigError(vec![format!("--set expects key=value, got `{}`", pair)]))?;
+                    cli.overrides.push((key.trim().to_string(), value.to_string()));
+                }
+                _ => cli.rest.push(arg.clone()),
+            }
+        }
+        Ok(cli)
+    }
+}
+
+// 原来使用的环境变量, 和配置项的对应关系
+// ? 优先级在配置文件之后, TEACHER_ 开头的环境变量之前, 两个都设置时以 TEACHER_ 为准
+const LEGACY_ENV: [(&str, &str); 7] = [
+    ("DATABASE_URL", "database.url"),
+    ("DB_BACKEND", "database.backend"),
+    ("AUTO_MIGRATE", "database.auto_migrate"),
+    ("DB_MAX_CONNECTIONS", "database.max_connections"),
+    ("LOG_FORMAT", "log.format"),
+    ("LOG_LEVEL", "log.level"),
+    ("TRASH_RETENTION_DAYS", "trash.retention_days"),
+];
+
+// 列表类型的配置, 环境变量中用逗号分隔
+const LIST_KEYS: [&str; 3] = ["server.bind", "cors.allowed_origins", "cors.allowed_methods"];
+
+impl Settings {
+    // 从进程的环境变量和命令行参数加载
+    pub fn load(cli: &CliArgs) -> Result<Settings, ConfigError> {
+        let vars: HashMap<String, String> = env::vars().collect();
+        let file = cli.config_file.clone().or_else(|| vars.get("TEACHER_CONFIG").cloned());
+        let contents = match &file {
+            Some(path) => Some(
+                std::fs::read_to_string(path)
+                    .map_err(|err| ConfigError(vec![format!("Unable to read config file {}: {}", path, err)]))?,
+            ),
+            None => std::fs::read_to_string(DEFAULT_CONFIG_FILE).ok(),
+        };
+        Settings::build(contents.as_deref(), &vars, cli)
+    }
+
+    // 各层的来源都作为参数传入, 测试时不需要修改真实的环境变量
+    pub fn build(file: Option<&str>, vars: &HashMap<String, String>, cli: &CliArgs) -> Result<Settings, ConfigError> {
+        let mut builder = Config::builder()
+            .set_default("server.bind", vec!["127.0.0.1:3000"])?
+            .set_default("server.json_limit", 2 * 1024 * 1024)?
+            .set_default("server.shutdown_delay_secs", 5)?
+            .set_default("server.shutdown_timeout_secs", 30)?
+            .set_default("cors.allowed_origins", vec!["http://localhost:8080"])?
+            .set_default("cors.allow_localhost", true)?
+            .set_default("cors.allowed_methods", vec!["GET", "POST", "PUT", "PATCH", "DELETE"])?
+            .set_default("cors.max_age", 3600)?
+            .set_default("database.backend", "postgres")?
+            .set_default("database.auto_migrate", true)?
+            .set_default("database.min_connections", 0)?
+            .set_default("database.max_connections", 10)?
+            .set_default("database.acquire_timeout_secs", 30)?
+            .set_default("database.idle_timeout_secs", 600)?
+            .set_default("database.max_lifetime_secs", 1800)?
+            .set_default("database.timezone", "Asia/Shanghai")?
+            .set_default("rate_limit.enabled", true)?
+            .set_default("rate_limit.trust_proxy_headers", false)?
+            .set_default("rate_limit.courses.burst", 120)?
+            .set_default("rate_limit.courses.per_second", 2.0)?
+            .set_default("rate_limit.teachers.burst", 120)?
+            .set_default("rate_limit.teachers.per_second", 2.0)?
+            .set_default("cache.course_detail", DEFAULT_CACHE_CONTROL)?
+            .set_default("cache.course_list", DEFAULT_CACHE_CONTROL)?
+            .set_default("cache.teacher_detail", DEFAULT_CACHE_CONTROL)?
+            .set_default("cache.teacher_list", DEFAULT_CACHE_CONTROL)?
+            .set_default("webhooks.enabled", true)?
+            .set_default("webhooks.max_attempts", 8)?
+            .set_default("webhooks.initial_backoff_secs", 10)?
+            .set_default("webhooks.max_backoff_secs", 3600)?
+            .set_default("webhooks.timeout_secs", 10)?
+            .set_default("webhooks.poll_interval_secs", 5)?
+            .set_default("webhooks.batch_size", 20)?
+            .set_default("webhooks.allow_private_targets", false)?
+            .set_default("trash.retention_days", 30)?
+            .set_default("log.format", "pretty")?
+            .set_default("log.level", DEFAULT_LOG_LEVEL)?;
+        if let Some(contents) = file {
+            builder = builder.add_source(File::from_str(contents, FileFormat::Toml));
+        }
+        // 原来的环境变量改写成 database__url 这样的名字, 作为单独的一层
+        let legacy = LEGACY_ENV
+            .iter()
+            .filter_map(|(name, key)| vars.get(*name).map(|value| (key.replace('.', "__"), value.clone())))
+            .collect();
+        builder = builder.add_source(Environment::default().separator("__").try_parsing(true).source(Some(legacy)));
+        let mut environment = Environment::with_prefix("TEACHER")
+            .prefix_separator("_")
+            .separator("__")
+            .list_separator(",")
+            .try_parsing(true)
+            .source(Some(vars.iter().map(|(key, value)| (key.clone(), value.clone())).collect()));
+        for key in LIST_KEYS {
+            environment = environment.with_list_parse_key(key);
+        }
+        builder = builder.add_source(environment);
+        // 命令行参数最后设置, 优先级最高
+        if !cli.bind.is_empty() {
+            builder = builder.set_override("server.bind", cli.bind.clone())?;
+        }
+        for (key, value) in &cli.overrides {
+            let value = if LIST_KEYS.contains(&key.as_str()) {
+                config::Value::from(value.split(',').map(|item| item.trim().to_string()).collect::<Vec<_>>())
+            } else {
+                config::Value::from(value.as_str())
+            };
+            builder = builder.set_override(key.as_str(), value)?;
+        }
+        let settings: Settings = builder.build()?.try_deserialize()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    // 启动时检查配置, 列出所有的问题, 而不是等到用到的时候才出错
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        if self.server.bind.is_empty() {
+            problems.push("server.bind must contain at least one address".to_string());
+        }
+        for address in &self.server.bind {
+            if address.to_socket_addrs().is_err() {
+                problems.push(format!("server.bind: invalid address `{}`", address));
+            }
+        }
+        if self.server.workers == Some(0) {
+            problems.push("server.workers must be at least 1".to_string());
+        }
+        if self.server.json_limit == 0 {
+            problems.push("server.json_limit must be greater than 0".to_string());
+        }
+        for origin in &self.cors.allowed_origins {
+            let valid = (origin.starts_with("http://") || origin.starts_with("https://"))
+                && !origin.ends_with('/')
+                && origin.split("://").nth(1).is_some_and(|host| !host.is_empty() && !host.contains('/'));
+            if !valid {
+                problems.push(format!("cors.allowed_origins: invalid origin `{}`, expected scheme://host[:port]", origin));
+            }
+        }
+        for method in &self.cors.allowed_methods {
+            if method.parse::<Method>().is_err() {
+                problems.push(format!("cors.allowed_methods: invalid method `{}`", method));
+            }
+        }
+        let database = &self.database;
+        if database.backend == Backend::Postgres && database.url.as_deref().unwrap_or_default().is_empty() {
+            problems.push("database.url (or DATABASE_URL) is required when database.backend is postgres".to_string());
+        }
+        if database.max_connections == 0 {
+            problems.push("database.max_connections must be at least 1".to_string());
+        }
+        if database.min_connections > database.max_connections {
+            problems.push(format!(
+                "database.min_connections ({}) must not be greater than database.max_connections ({})",
+                database.min_connections, database.max_connections
+            ));
+        }
+        if database.acquire_timeout_secs == 0 {
+            problems.push("database.acquire_timeout_secs must be at least 1".to_string());
+        }
+        // 时区以参数的形式传给数据库, 这里只做简单的检查, 数据库不认识的时区在建立连接时报错
+        let timezone_chars = |c: char| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '+' | '-' | ':');
+        if database.timezone.is_empty() || !database.timezone.chars().all(timezone_chars) {
+            problems.push(format!("database.timezone: invalid time zone `{}`", database.timezone));
+        }
+        for (group, policy) in [("courses", self.rate_limit.courses), ("teachers", self.rate_limit.teachers)] {
+            if policy.burst == 0 {
+                problems.push(format!("rate_limit.{}.burst must be at least 1", group));
+            }
+            if !(policy.per_second.is_finite() && policy.per_second > 0.0) {
+                problems.push(format!("rate_limit.{}.per_second must be greater than 0", group));
+            }
+        }
+        for (route, value) in self.cache.entries() {
+            if value.trim().is_empty() || HeaderValue::from_str(value).is_err() {
+                problems.push(format!("cache.{}: invalid Cache-Control value `{}`", route, value));
+            }
+        }
+        let webhooks = &self.webhooks;
+        if webhooks.max_attempts < 1 {
+            problems.push("webhooks.max_attempts must be at least 1".to_string());
+        }
+        if webhooks.initial_backoff_secs > webhooks.max_backoff_secs {
+            problems.push(format!(
+                "webhooks.initial_backoff_secs ({}) must not be greater than webhooks.max_backoff_secs ({})",
+                webhooks.initial_backoff_secs, webhooks.max_backoff_secs
+            ));
+        }
+        if webhooks.timeout_secs == 0 || webhooks.poll_interval_secs == 0 {
+            problems.push("webhooks.timeout_secs and webhooks.poll_interval_secs must be at least 1".to_string());
+        }
+        if webhooks.batch_size < 1 {
+            problems.push("webhooks.batch_size must be at least 1".to_string());
+        }
+        if self.trash.retention_days < 1 {
+            problems.push("trash.retention_days must be at least 1".to_string());
+        }
+        if let Err(err) = EnvFilter::try_new(&self.log.level) {
+            problems.push(format!("log.level: invalid filter `{}`: {}", self.log.level, err));
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+}
+
+// * 测试
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> CliArgs {
+        CliArgs::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn layers_override_each_other() {
+        let file = r#"
+            [server]
+            bind = ["0.0.0.0:8000"]
+            workers = 2
+
+            [database]
+            url = "postgres://file"
+            timezone = "UTC"
+            max_connections = 20
+        "#;
+        let env = vars(&[
+            ("TEACHER_SERVER__WORKERS", "4"),
+            ("TEACHER_CORS__ALLOWED_ORIGINS", "https://a.example.com,https://b.example.com"),
+            ("DATABASE_URL", "postgres://env"),
+            ("TRASH_RETENTION_DAYS", "7"),
+        ]);
+        let cli = args(&["--workers", "8", "--set", "database.timezone=Europe/Berlin", "migrate", "up"]);
+        let settings = Settings::build(Some(file), &env, &cli).unwrap();
+
+        assert_eq!(cli.rest, vec!["migrate", "up"]);
+        // 只在配置文件中的值
+        assert_eq!(settings.server.bind, vec!["0.0.0.0:8000"]);
+        assert_eq!(settings.database.max_connections, 20);
+        // 环境变量覆盖配置文件, 命令行覆盖环境变量
+        assert_eq!(settings.database.url.as_deref(), Some("postgres://env"));
+        assert_eq!(settings.cors.allowed_origins, vec!["https://a.example.com", "https://b.example.com"]);
+        assert_eq!(settings.server.workers, Some(8));
+        assert_eq!(settings.database.timezone, "Europe/Berlin");
+        assert_eq!(settings.trash.retention(), chrono::Duration::days(7));
+        // 都没有配置时使用默认值
+        assert_eq!(settings.server.json_limit, 2 * 1024 * 1024);
+        assert_eq!(settings.log.format, LogFormat::Pretty);
+    }
+
+    #[test]
+    fn teacher_variables_beat_legacy_variables() {
+        let file = r#"
+            [log]
+            level = "warn"
+        "#;
+        let env = vars(&[
+            ("DATABASE_URL", "postgres://legacy"),
+            ("TEACHER_DATABASE__URL", "postgres://teacher"),
+            ("LOG_LEVEL", "debug"),
+            ("TEACHER_LOG__LEVEL", "error"),
+            ("DB_MAX_CONNECTIONS", "7"),
+        ]);
+        let settings = Settings::build(Some(file), &env, &args(&[])).unwrap();
+        assert_eq!(settings.database.url.as_deref(), Some("postgres://teacher"));
+        assert_eq!(settings.log.level, "error");
+        // 只设置了原来的环境变量时仍然生效, 并且覆盖配置文件
+        assert_eq!(settings.database.max_connections, 7);
+        let env = vars(&[("DATABASE_URL", "postgres://legacy"), ("LOG_LEVEL", "debug")]);
+        assert_eq!(Settings::build(Some(file), &env, &args(&[])).unwrap().log.level, "debug");
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected_at_startup() {
+        let env = vars(&[("DATABASE_URL", "postgres://env")]);
+        let cli = args(&[
+            "--bind",
+            "not an address",
+            "--set",
+            "database.min_connections=30",
+            "--set",
+            "cors.allowed_origins=http://localhost:8080/",
+            "--set",
+            "database.timezone=UTC'; DROP TABLE course; --",
+            "--set",
+            "cache.course_list=",
+            "--set",
+            "trash.retention_days=0",
+        ]);
+        let err = Settings::build(None, &env, &cli).unwrap_err();
+        assert_eq!(err.0.len(), 6, "{}", err);
+
+        // 使用内存存储时不需要数据库地址
+        let settings = Settings::build(None, &vars(&[("DB_BACKEND", "memory")]), &args(&[])).unwrap();
+        assert_eq!(settings.database.backend, Backend::Memory);
+        assert!(Settings::build(None, &vars(&[]), &args(&[])).is_err());
+        assert!(CliArgs::parse(&["--bind".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use crate::errors::MyError;
+use crate::models::audit::{AuditEntry, AuditRecord, EventPosition};
+use crate::models::pagination::{Page, PageParams};
+use sqlx::postgres::{PgPool, Postgres};
+use sqlx::Transaction;
+use tracing::instrument;
+
+/**
+ * 写入一条变更记录
+ * ? 传入的是事务, 和课程/老师的修改一起提交或者一起回滚
+ */
+#[instrument(level = "debug", skip_all, fields(entity_type = %record.entity_type, entity_id = record.entity_id, action = ?record.action))]
+pub async fn insert_audit_db(tx: &mut Transaction<'_, Postgres>, record: AuditRecord) -> Result<(), MyError> {
+    sqlx::query!(
+        r#"INSERT INTO audit_log (entity_type, entity_id, teacher_id, action, actor, before, after)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+        record.entity_type,
+        record.entity_id,
+        record.teacher_id,
+        record.action.as_str(),
+        record.actor,
+        record.before,
+        record.after
+    )
+    .execute(tx)
+    .await?;
+    Ok(())
+}
+
+/**
+ * 某一条课程或老师的变更历史, 按发生的先后顺序分页
+ * ? 不按老师过滤, 课程转给其他老师之后, 新的老师也能看到之前的记录
+ * ? teacher_id 必须是现在的所属老师, 也就是最后一条记录的老师, 否则返回空的一页
+ * ? move 只用于原来的老师的 SSE 和 webhook, 和同一次修改的 update 重复, 不显示
+ */
+#[instrument(level = "debug", skip_all, fields(entity_type = %entity_type, entity_id = entity_id, teacher_id = teacher_id))]
+pub async fn get_audit_history_db(
+    pool: &PgPool,
+    entity_type: &str,
+    entity_id: i32,
+    teacher_id: i32,
+    page: &PageParams,
+) -> Result<Page<AuditEntry>, MyError> {
+    let rows = sqlx::query_as!(
+        AuditEntry,
+        r#"
+        SELECT * FROM audit_log
+        WHERE entity_type = $1 AND entity_id = $2 AND action <> 'move' AND id > $4
+            AND $3 = (
+                SELECT teacher_id FROM audit_log
+                WHERE entity_type = $1 AND entity_id = $2 AND action <> 'move'
+                ORDER BY id DESC LIMIT 1
+            )
+        ORDER BY id
+        LIMIT $5 OFFSET $6"#,
+        entity_type,
+        entity_id,
+        teacher_id,
+        page.after_id,
+        page.limit + 1,
+        page.offset
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "total!" FROM audit_log
+        WHERE entity_type = $1 AND entity_id = $2 AND action <> 'move'
+            AND $3 = (
+                SELECT teacher_id FROM audit_log
+                WHERE entity_type = $1 AND entity_id = $2 AND action <> 'move'
+                ORDER BY id DESC LIMIT 1
+            )"#,
+        entity_type,
+        entity_id,
+        teacher_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Page::from_rows(rows, total, page, |entry| entry.id))
+}
+
+/**
+ * 老师的课程在 after 之后的变更, 按提交的顺序 (commit_seq), 作为 SSE 推送的事件
+ * ? 彻底删除的记录不推送, 课程在软删除时已经推送过 deleted
+ * ? commit_seq 在提交时串行分配, 读到某条记录时, commit_seq 更小的记录都已经可见, 推送过的位置之前不会再出现新的记录
+ * ? 只等待其他写入 audit_log 的事务提交, 和课程无关的长事务不会影响推送
+ */
+#[instrument(level = "debug", skip(pool))]
+pub async fn get_course_events_db(
+    pool: &PgPool,
+    teacher_id: i32,
+    after: EventPosition,
+    limit: i64,
+) -> Result<Vec<AuditEntry>, MyError> {
+    let rows = sqlx::query_as!(
+        AuditEntry,
+        r#"
+        SELECT * FROM audit_log
+        WHERE entity_type = 'course' AND teacher_id = $1 AND commit_seq > $2 AND action <> 'purge'
+        ORDER BY commit_seq
+        LIMIT $3"#,
+        teacher_id,
+        after.seq,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/**
+ * SSE 开始推送的位置
+ * ? 重连时 last_event_id 是客户端收到的最后一个事件, 从这个事件之后继续, 0 表示从头开始
+ * ? 新的连接, 或者事件已经不存在时, 从最后一条记录之后开始
+ */
+#[instrument(level = "debug", skip(pool))]
+pub async fn course_event_position_db(
+    pool: &PgPool,
+    teacher_id: i32,
+    last_event_id: Option<i32>,
+) -> Result<EventPosition, MyError> {
+    match last_event_id {
+        Some(0) => return Ok(EventPosition::default()),
+        Some(id) => {
+            let seq = sqlx::query_scalar!("SELECT commit_seq FROM audit_log WHERE id = $1", id).fetch_optional(pool).await?;
+            if let Some(Some(seq)) = seq {
+                return Ok(EventPosition { seq });
+            }
+        }
+        None => {}
+    }
+    let seq = sqlx::query_scalar!(
+        "SELECT MAX(commit_seq) FROM audit_log WHERE entity_type = 'course' AND teacher_id = $1",
+        teacher_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(EventPosition { seq: seq.unwrap_or_default() })
+}
+
+// * 测试, 需要 DATABASE_URL 指向的数据库, 并且已经执行过迁移, 默认忽略
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::audit::AuditAction;
+    use dotenv::dotenv;
+    use sqlx::postgres::PgPoolOptions;
+    use std::env;
+
+    // 测试写入的记录属于这位不存在的老师, 开始和结束时清理
+    const TEACHER: i32 = -24;
+
+    async fn write_course_event(tx: &mut Transaction<'_, Postgres>, course_id: i32) {
+        let record = AuditRecord {
+            entity_type: "course",
+            entity_id: course_id,
+            teacher_id: TEACHER,
+            action: AuditAction::Update,
+            actor: "test".into(),
+            before: None,
+            after: None,
+        };
+        insert_audit_db(tx, record).await.unwrap();
+    }
+
+    async fn read_course_events(pool: &PgPool, after: EventPosition) -> (Vec<i32>, EventPosition) {
+        let entries = get_course_events_db(pool, TEACHER, after, 100).await.unwrap();
+        let position = entries.last().map(EventPosition::from).unwrap_or(after);
+        (entries.iter().map(|entry| entry.entity_id).collect(), position)
+    }
+
+    async fn clean_up(pool: &PgPool) {
+        sqlx::query!(
+            "DELETE FROM webhook_delivery WHERE event_id IN (SELECT id FROM audit_log WHERE teacher_id = $1)",
+            TEACHER
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query!("DELETE FROM audit_log WHERE teacher_id = $1", TEACHER).execute(pool).await.unwrap();
+    }
+
+    // 会连接 DATABASE_URL 并删除其中的测试记录, 默认不运行, 需要时用 cargo test -- --ignored
+    #[ignore]
+    #[actix_rt::test]
+    async fn course_events_follow_commit_order() {
+        dotenv().ok();
+        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
+        let pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
+        clean_up(&pool).await;
+        let start = course_event_position_db(&pool, TEACHER, None).await.unwrap();
+        assert_eq!(start, EventPosition::default());
+
+        // 两个交错的事务, 较小的 id 后提交: 先提交的记录先推送, 后提交的较小 id 不会被跳过
+        let mut first = pool.begin().await.unwrap();
+        write_course_event(&mut first, 1).await;
+        let mut second = pool.begin().await.unwrap();
+        write_course_event(&mut second, 2).await;
+        second.commit().await.unwrap();
+        let (courses, position) = read_course_events(&pool, start).await;
+        assert_eq!(courses, vec![2]);
+        first.commit().await.unwrap();
+        assert_eq!(read_course_events(&pool, position).await.0, vec![1]);
+        // 重连时从 Last-Event-ID 对应的位置继续, 同样不会漏掉
+        let last_event_id = get_course_events_db(&pool, TEACHER, start, 1).await.unwrap()[0].id;
+        let resumed = course_event_position_db(&pool, TEACHER, Some(last_event_id)).await.unwrap();
+        assert_eq!(resumed, position);
+        assert_eq!(read_course_events(&pool, resumed).await.0, vec![1]);
+
+        // 和 audit_log 无关的长事务不影响推送
+        let mut idle = pool.begin().await.unwrap();
+        sqlx::query!("SELECT pg_current_xact_id()::text").fetch_one(&mut idle).await.unwrap();
+        let position = course_event_position_db(&pool, TEACHER, None).await.unwrap();
+        let mut tx = pool.begin().await.unwrap();
+        write_course_event(&mut tx, 3).await;
+        tx.commit().await.unwrap();
+        assert_eq!(read_course_events(&pool, position).await.0, vec![3]);
+        idle.rollback().await.unwrap();
+
+        clean_up(&pool).await;
+    }
+}
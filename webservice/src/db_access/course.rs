@@ -1,12 +1,53 @@
+use super::audit::insert_audit_db;
 use crate::errors::MyError;
-use crate::models::course::{Course, UpdateCourse, CreateCourse};
+use crate::models::audit::{Actor, AuditAction, AuditRecord};
+use crate::models::course::{Course, CourseFilter, CourseSearchHit, CoursePatch, CreateCourse};
+use crate::models::course_options::{CourseFormat, CourseLanguage, CourseLevel};
+use crate::models::pagination::{Page, PageParams};
+use crate::models::version::VersionCheck;
 // use chrono::NaiveDateTime;
-use sqlx::postgres::PgPool;
+use chrono::Duration;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use sqlx::postgres::{PgPool, Postgres};
+use sqlx::{QueryBuilder, Transaction};
+use tracing::{debug_span, instrument, Instrument};
 
+// 把筛选条件拼接到 WHERE 后面, 列表查询和总数查询共用
+// ? 所有的值都通过 push_bind 作为参数传递, 不会直接拼进 SQL
+fn push_course_filter<'a>(builder: &mut QueryBuilder<'a, Postgres>, teacher_id: i32, filter: &'a CourseFilter) {
+    // 已经软删除的课程不返回
+    builder.push(" WHERE deleted_at IS NULL AND teacher_id = ").push_bind(teacher_id);
+    // 等级, 格式和语言在数据库中已经统一了写法, 直接比较即可
+    if let Some(language) = filter.language {
+        builder.push(" AND language = ").push_bind(language);
+    }
+    if let Some(level) = filter.level {
+        builder.push(" AND level = ").push_bind(level);
+    }
+    if let Some(format) = filter.format {
+        builder.push(" AND format = ").push_bind(format);
+    }
+    if let Some(min_price) = filter.min_price {
+        builder.push(" AND price >= ").push_bind(min_price);
+    }
+    if let Some(max_price) = filter.max_price {
+        builder.push(" AND price <= ").push_bind(max_price);
+    }
+    if let Some(from) = filter.from {
+        builder.push(" AND time >= ").push_bind(from);
+    }
+    if let Some(to) = filter.to {
+        builder.push(" AND time <= ").push_bind(to);
+    }
+}
+
+#[instrument(level = "debug", skip_all, fields(teacher_id = teacher_id))]
 pub async fn get_courses_for_teacher_db(
     pool: &PgPool,
     teacher_id: i32,
-) -> Result<Vec<Course>, MyError> {
+    filter: &CourseFilter,
+    page: &PageParams,
+) -> Result<Page<Course>, MyError> {
     // let rows = sqlx::query!(
     //     r#"SELECT id, teacher_id, name, time FROM course WHERE teacher_id = $1"#,
     //     teacher_id
@@ -15,17 +56,24 @@ pub async fn get_courses_for_teacher_db(
     // 这个 ? 会在发生错误的时候将错误传递到 handler 中进行处理
     // 但是需要指明错误类型, 指明的方式就是返回值, 修改为 Result<T, E>
     // .await?;
-    // ? 这里直接使用 query_as, 将结果转换为Vec<Course> 类型, 因为在声明Course时, 添加了 FromRow, 用于数据库读取后直接映射
-    // ? query_as 第一个参数是我们需要的类型
-    let rows: Vec<Course> = sqlx::query_as!(
-        Course,
-        r#"
-        SELECT * FROM course
-        WHERE teacher_id = $1"#,
-        teacher_id
-    )
-    .fetch_all(pool)
-    .await?;
+    // ? 筛选条件是动态的, query_as! 宏没法处理, 这里用 QueryBuilder 拼接
+    // ? 排序字段来自 CourseSortField 这个枚举, 不会是用户传入的任意字符串
+    let mut builder = QueryBuilder::new("SELECT * FROM course");
+    push_course_filter(&mut builder, teacher_id, filter);
+    match filter.sort {
+        Some((field, order)) => {
+            builder.push(format!(" ORDER BY {} {} NULLS LAST, id", field.column(), order.keyword()));
+        }
+        None => {
+            // 默认按 id 排序, 此时才支持 cursor
+            builder.push(" AND id > ").push_bind(page.after_id);
+            builder.push(" ORDER BY id");
+        }
+    }
+    // 多查一条用于判断是否还有下一页
+    builder.push(" LIMIT ").push_bind(page.limit + 1);
+    builder.push(" OFFSET ").push_bind(page.offset);
+    let rows: Vec<Course> = builder.build_query_as::<Course>().fetch_all(pool).await?;
     // ? 无需在遍历创建courses了, 上面可以直接从数据库中取出 Course 类型的 Vector
     // let courses: Vec<Course> = rows
     //     .iter()
@@ -37,13 +85,84 @@ pub async fn get_courses_for_teacher_db(
     //     })
     //     .collect();
 
-    // 集合长度为0 , 其实就应该是 Not Found, 大于0, 就是正常返回
-    match rows.len() {
-        0 => Err(MyError::NotFound("Course not found for teacher".into())),
-        _ => Ok(rows),
-    }
+    // 总数只受筛选条件影响, 不受分页影响, 没有课程时返回空列表而不是 Not Found
+    let mut builder = QueryBuilder::new("SELECT COUNT(*) FROM course");
+    push_course_filter(&mut builder, teacher_id, filter);
+    let (total,): (i64,) = builder.build_query_as().fetch_one(pool).await?;
+
+    let page = Page::from_rows(rows, total, page, |course| course.id);
+    // 自定义排序时 id 不是单调的, 不能作为 cursor, 客户端需要使用 offset 翻页
+    Ok(match filter.sort {
+        Some(_) => page.without_cursor(),
+        None => page,
+    })
 }
 
+/**
+ * 全文检索所有老师的课程
+ * 按相关度排序, 使用 offset 分页
+ */
+#[instrument(level = "debug", skip_all)]
+pub async fn search_courses_db(pool: &PgPool, query: &str, page: &PageParams) -> Result<Page<CourseSearchHit>, MyError> {
+    // ? websearch_to_tsquery 支持 "带引号的短语", or, -排除 等搜索引擎语法, 不会因为用户输入的特殊字符报错
+    // ? course_search_vector 和迁移里面建索引用的是同一个函数, 这样才能用上 GIN 索引
+    let rows = sqlx::query!(
+        r#"
+        SELECT c.id, c.teacher_id, c.name, c.time, c.description, c.format AS "format: CourseFormat", c.structure,
+            c.duration, c.price, c.language AS "language: CourseLanguage", c.level AS "level: CourseLevel",
+            c.deleted_at, c.version, c.updated_at,
+            ts_rank(course_search_vector(c.name, c.description, c.structure), q) AS "rank!",
+            ts_headline('simple', concat_ws(' ', c.name, c.description, c.structure), q,
+                'StartSel=<mark>, StopSel=</mark>, MaxWords=35, MinWords=15') AS "snippet!"
+        FROM course c, websearch_to_tsquery('simple', $1) q
+        WHERE course_search_vector(c.name, c.description, c.structure) @@ q AND c.deleted_at IS NULL
+        ORDER BY "rank!" DESC, c.id
+        LIMIT $2 OFFSET $3"#,
+        query,
+        page.limit + 1,
+        page.offset
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let hits: Vec<CourseSearchHit> = rows
+        .into_iter()
+        .map(|row| CourseSearchHit {
+            course: Course {
+                teacher_id: row.teacher_id,
+                id: row.id,
+                name: row.name,
+                time: row.time,
+                description: row.description,
+                format: row.format,
+                structure: row.structure,
+                duration: row.duration,
+                price: row.price,
+                language: row.language,
+                level: row.level,
+                deleted_at: row.deleted_at,
+                version: row.version,
+                updated_at: row.updated_at,
+            },
+            rank: row.rank,
+            snippet: row.snippet,
+        })
+        .collect();
+
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "total!" FROM course
+        WHERE course_search_vector(name, description, structure) @@ websearch_to_tsquery('simple', $1)
+            AND deleted_at IS NULL"#,
+        query
+    )
+    .fetch_one(pool)
+    .await?;
+
+    // 按相关度排序, id 不是单调的, 只能用 offset 翻页
+    Ok(Page::from_rows(hits, total, page, |hit| hit.course.id).without_cursor())
+}
+
+#[instrument(level = "debug", skip_all, fields(teacher_id = teacher_id, id = id))]
 pub async fn get_course_details_db(
     pool: &PgPool,
     teacher_id: i32,
@@ -51,7 +170,9 @@ pub async fn get_course_details_db(
 ) -> Result<Course, MyError> {
     let row: Option<Course> = sqlx::query_as!(
         Course,
-        r#"SELECT * FROM course WHERE teacher_id = $1 AND id = $2"#,
+        r#"SELECT id, teacher_id, name, time, description, format AS "format: CourseFormat", structure, duration, price,
+               language AS "language: CourseLanguage", level AS "level: CourseLevel", deleted_at, version, updated_at
+           FROM course WHERE teacher_id = $1 AND id = $2 AND deleted_at IS NULL"#,
         teacher_id,
         id
     )
@@ -71,132 +192,304 @@ pub async fn get_course_details_db(
  * 新增course
  * @param pool 数据库连接池
  * @param new_course 新增的课程
+ * @param actor 操作人, 写入变更历史
  * @return course 新增的课程
  */
-pub async fn post_new_course_db(pool: &PgPool, new_course: CreateCourse) -> Result<Course, MyError> {
+#[instrument(level = "debug", skip_all, fields(teacher_id = new_course.teacher_id, actor = %actor.0))]
+pub async fn post_new_course_db(pool: &PgPool, new_course: CreateCourse, actor: &Actor) -> Result<Course, MyError> {
+    // 新增课程和变更记录在同一个事务里, 要么都成功要么都失败
+    let mut tx = pool.begin().await?;
+    let row = insert_course(&mut tx, new_course, actor).await?;
+    tx.commit().await?;
+    Ok(row)
+}
+
+/**
+ * 批量导入课程
+ * 所有课程在同一个事务中插入, 任何一条失败都会整体回滚
+ */
+#[instrument(level = "debug", skip_all, fields(rows = new_courses.len(), actor = %actor.0))]
+pub async fn import_courses_db(pool: &PgPool, new_courses: Vec<CreateCourse>, actor: &Actor) -> Result<Vec<Course>, MyError> {
+    let mut tx = pool.begin().await?;
+    let mut rows = Vec::with_capacity(new_courses.len());
+    for new_course in new_courses {
+        rows.push(insert_course(&mut tx, new_course, actor).await?);
+    }
+    tx.commit().await?;
+    Ok(rows)
+}
+
+// 在事务中插入一门课程并写入变更历史, 新增和导入共用
+async fn insert_course(tx: &mut Transaction<'_, Postgres>, new_course: CreateCourse, actor: &Actor) -> Result<Course, MyError> {
     // ? 通过 INSERT 插入到 course中, 插入 id, teacher_id, name, time自己生成, 然后通过 RETURNING 返回 id, teacher_id, name, time
     let row = sqlx::query_as!(
         Course,
         r#"INSERT INTO course (teacher_id, name, description, format, structure, duration, price, language, level)
     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
-    RETURNING id, teacher_id, name, time, description, format, structure, duration, price, language, level"#,
-        new_course.teacher_id, new_course.name, new_course.description, new_course.format,
-        new_course.structure, new_course.duration, new_course.price, new_course.language, new_course.level
+    RETURNING id, teacher_id, name, time, description, format AS "format: CourseFormat", structure, duration, price,
+        language AS "language: CourseLanguage", level AS "level: CourseLevel", deleted_at, version, updated_at"#,
+        // ? 枚举类型的参数用 as _ 跳过宏的类型检查, 按字符串写入
+        new_course.teacher_id, new_course.name, new_course.description, new_course.format as _,
+        new_course.structure, new_course.duration, new_course.price, new_course.language as _, new_course.level as _
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     // 这里直接跟 ? 即可, 如果有错误会直接返回 Result<Error> 信息
     .await?;
 
+    insert_audit_db(tx, AuditRecord::course(AuditAction::Create, actor, None, Some(&row))).await?;
+    Ok(row)
+}
+
+// 导出时每批读取的条数
+const EXPORT_BATCH_SIZE: i64 = 500;
+
+/**
+ * 按 id 分批读取老师的所有课程, 用于导出
+ * ? 每一批都是一次独立的查询, 不会长时间占用连接, 也不用一次把所有课程读进内存
+ */
+pub fn export_courses_db(pool: PgPool, teacher_id: i32) -> BoxStream<'static, Result<Vec<Course>, MyError>> {
+    // 状态是上一批最后一条的 id, None 表示已经读完
+    stream::try_unfold(Some(0), move |after_id| {
+        let pool = pool.clone();
+        // 每一批单独一个 span, 流是在 handler 返回之后才被读取的
+        let span = debug_span!("export_courses_db", teacher_id, after_id = ?after_id);
+        async move {
+            let after_id = match after_id {
+                Some(after_id) => after_id,
+                None => return Ok(None),
+            };
+            let rows = sqlx::query_as!(
+                Course,
+                r#"
+                SELECT id, teacher_id, name, time, description, format AS "format: CourseFormat", structure, duration, price,
+                    language AS "language: CourseLanguage", level AS "level: CourseLevel", deleted_at, version, updated_at
+                FROM course
+                WHERE teacher_id = $1 AND deleted_at IS NULL AND id > $2
+                ORDER BY id
+                LIMIT $3"#,
+                teacher_id,
+                after_id,
+                EXPORT_BATCH_SIZE
+            )
+            .fetch_all(&pool)
+            .await?;
+            if rows.is_empty() {
+                return Ok(None);
+            }
+            let next = if (rows.len() as i64) < EXPORT_BATCH_SIZE { None } else { rows.last().map(|course| course.id) };
+            Ok(Some((rows, next)))
+        }
+        .instrument(span)
+    })
+    .boxed()
+}
+
+// 在事务中查出并锁住一门课程, 修改之前的值会写入变更历史
+// ? FOR UPDATE 保证读到的 before 和随后的 UPDATE 之间不会被别的请求改掉
+async fn lock_course(
+    tx: &mut Transaction<'_, Postgres>,
+    teacher_id: i32,
+    id: i32,
+    deleted: bool,
+) -> Result<Option<Course>, MyError> {
+    let row = sqlx::query_as!(
+        Course,
+        r#"
+        SELECT id, teacher_id, name, time, description, format AS "format: CourseFormat", structure, duration, price,
+            language AS "language: CourseLanguage", level AS "level: CourseLevel", deleted_at, version, updated_at
+        FROM course
+        WHERE id = $1 AND teacher_id = $2 AND (deleted_at IS NOT NULL) = $3
+        FOR UPDATE"#,
+        id,
+        teacher_id,
+        deleted
+    )
+    .fetch_optional(tx)
+    .await?;
     Ok(row)
 }
 
 /**
  * 删除 course
+ * 软删除, 只记录删除时间, 可以通过 restore_course_db 恢复
  */
+#[instrument(level = "debug", skip_all, fields(teacher_id = teacher_id, id = id, actor = %actor.0))]
+pub async fn delete_course_db(pool: &PgPool, teacher_id: i32, id: i32, actor: &Actor) -> Result<String, MyError> {
+    let mut tx = pool.begin().await?;
+    // 查不到, 说明课程不存在或者已经删除了
+    let current = lock_course(&mut tx, teacher_id, id, false)
+        .await?
+        .ok_or_else(|| MyError::NotFound("Course is not found".into()))?;
 
-pub async fn delete_course_db(pool: &PgPool, teacher_id: i32, id: i32) -> Result<String, MyError> {
-    let course_row = sqlx::query!( 
-    "DELETE FROM course where id = $1 and teacher_id = $2",
-    id, teacher_id
+    let course_row = sqlx::query_as!(
+        Course,
+        r#"
+        UPDATE course SET deleted_at = now(), version = version + 1 WHERE id = $1 AND teacher_id = $2
+        RETURNING id, teacher_id, name, time, description, format AS "format: CourseFormat", structure, duration, price,
+            language AS "language: CourseLanguage", level AS "level: CourseLevel", deleted_at, version, updated_at"#,
+        id,
+        teacher_id
     )
     // 执行删除
-    .execute(pool)
+    .fetch_one(&mut tx)
     .await?;
 
-    Ok(format!("Delete {:?} record", course_row))
+    insert_audit_db(&mut tx, AuditRecord::course(AuditAction::Delete, actor, Some(&current), Some(&course_row))).await?;
+    tx.commit().await?;
+    Ok("Delete 1 record".into())
 }
 
+#[instrument(level = "debug", skip_all, fields(teacher_id = teacher_id, id = id, actor = %actor.0))]
 pub async fn update_course_details_db(
     pool: &PgPool,
     teacher_id: i32,
     id: i32,
-    update_course: UpdateCourse
+    patch: CoursePatch,
+    check: &VersionCheck,
+    actor: &Actor,
 ) -> Result<Course, MyError> {
-    let current_course_row = sqlx::query_as!(Course, r#"
-        SELECT * FROM course where id = $1 and teacher_id = $2
-    "#,
-    id, teacher_id)
-    .fetch_one(pool)
-    .await
-    // 如果没有查到就返回一个错误 not found
-    .map_err(|_err| MyError::NotFound("Course id not found".into()))?;
-
-    // 如果 update_course.name 没有值, 那么说明name没有进行更新, 此时直接获取 current_course_row.name 即可
-    let name: String = if let Some(name) = update_course.name {
-        name
-    } else {
-        current_course_row.name
-    };
+    let mut tx = pool.begin().await?;
+    let current_course_row = lock_course(&mut tx, teacher_id, id, false)
+        .await?
+        // 如果没有查到就返回一个错误 not found
+        .ok_or_else(|| MyError::NotFound("Course id not found".into()))?;
+    // ? 行已经被 FOR UPDATE 锁住了, 检查通过之后到 UPDATE 之间版本不会再变
+    check.check(current_course_row.version)?;
+    // 空的 patch 不修改, 版本也不变
+    if patch.is_empty() {
+        return Ok(current_course_row);
+    }
 
-    // ? 其余同上
-    let description: String = if let Some(description) = update_course.description {
-        description
-    } else {
-        // ? 这里要解除 Option, 转换为 String
-        current_course_row.description.unwrap_or_default()
-    };
+    // ? 在一条 UPDATE 中完成, 每个字段带一个是否修改的标记, 没有传的字段保持数据库中的原值 (包括 NULL)
+    // ? 传了 null 时标记为 true, 值为 NULL, 也就是清空
+    let course_row = sqlx::query_as!(
+        Course,
+        r#"
+            UPDATE course SET
+                name = COALESCE($1, name),
+                description = CASE WHEN $2 THEN $3 ELSE description END,
+                format = CASE WHEN $4 THEN $5 ELSE format END,
+                structure = CASE WHEN $6 THEN $7 ELSE structure END,
+                duration = CASE WHEN $8 THEN $9 ELSE duration END,
+                price = CASE WHEN $10 THEN $11 ELSE price END,
+                language = CASE WHEN $12 THEN $13 ELSE language END,
+                level = CASE WHEN $14 THEN $15 ELSE level END,
+                version = version + 1
+            where id = $16 and teacher_id = $17 and deleted_at IS NULL
+            RETURNING id, teacher_id, name, time, description, format AS "format: CourseFormat", structure, duration, price,
+                language AS "language: CourseLanguage", level AS "level: CourseLevel", deleted_at, version, updated_at
+        "#,
+        patch.name,
+        patch.description.is_some(),
+        patch.description.flatten(),
+        patch.format.is_some(),
+        patch.format.flatten() as _,
+        patch.structure.is_some(),
+        patch.structure.flatten(),
+        patch.duration.is_some(),
+        patch.duration.flatten(),
+        patch.price.is_some(),
+        patch.price.flatten(),
+        patch.language.is_some(),
+        patch.language.flatten() as _,
+        patch.level.is_some(),
+        patch.level.flatten() as _,
+        id,
+        teacher_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
 
-    let format: String = if let Some(format) = update_course.format {
-        format
-    } else {
-        current_course_row.format.unwrap_or_default()
-    };
+    insert_audit_db(
+        &mut tx,
+        AuditRecord::course(AuditAction::Update, actor, Some(&current_course_row), Some(&course_row)),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(course_row)
+}
 
-    let structure: String = if let Some(structure) = update_course.structure {
-        structure
-    } else {
-        current_course_row.structure.unwrap_or_default()
-    };
+/**
+ * 回收站, 某个老师已经软删除的课程, 按 id 排序分页
+ */
+#[instrument(level = "debug", skip_all, fields(teacher_id = teacher_id))]
+pub async fn get_deleted_courses_db(pool: &PgPool, teacher_id: i32, page: &PageParams) -> Result<Page<Course>, MyError> {
+    let rows = sqlx::query_as!(
+        Course,
+        r#"
+        SELECT id, teacher_id, name, time, description, format AS "format: CourseFormat", structure, duration, price,
+            language AS "language: CourseLanguage", level AS "level: CourseLevel", deleted_at, version, updated_at
+        FROM course
+        WHERE teacher_id = $1 AND deleted_at IS NOT NULL AND id > $2
+        ORDER BY id
+        LIMIT $3 OFFSET $4"#,
+        teacher_id,
+        page.after_id,
+        page.limit + 1,
+        page.offset
+    )
+    .fetch_all(pool)
+    .await?;
 
-    let duration: String = if let Some(duration) = update_course.duration {
-        duration
-    } else {
-        current_course_row.duration.unwrap_or_default()
-    };
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "total!" FROM course WHERE teacher_id = $1 AND deleted_at IS NOT NULL"#,
+        teacher_id
+    )
+    .fetch_one(pool)
+    .await?;
 
-    let price: i32 = if let Some(price) = update_course.price {
-        price
-    } else {
-        current_course_row.price.unwrap_or_default()
-    };
+    Ok(Page::from_rows(rows, total, page, |course| course.id))
+}
 
-    let language: String = if let Some(language) = update_course.language {
-        language
-    } else {
-        current_course_row.language.unwrap_or_default()
-    };
+/**
+ * 从回收站恢复课程
+ */
+#[instrument(level = "debug", skip_all, fields(teacher_id = teacher_id, id = id, actor = %actor.0))]
+pub async fn restore_course_db(pool: &PgPool, teacher_id: i32, id: i32, actor: &Actor) -> Result<Course, MyError> {
+    let mut tx = pool.begin().await?;
+    let current = lock_course(&mut tx, teacher_id, id, true)
+        .await?
+        .ok_or_else(|| MyError::NotFound("Deleted course is not found".into()))?;
 
-    let level: String = if let Some(level) = update_course.level {
-        level
-    } else {
-        current_course_row.level.unwrap_or_default()
-    };
-    
-    let course_row = sqlx::query_as!(
+    let row = sqlx::query_as!(
         Course,
         r#"
-            UPDATE course SET name = $1, description = $2, format = $3, 
-            structure = $4, duration = $5, price = $6, language = $7, level = $8
-            where id = $9 and teacher_id = $10
-            RETURNING id, teacher_id, name, time, description, format, structure, duration, price, language, level
-        "#,
-        name,
-        description,
-        format,
-        structure,
-        duration,
-        price,
-        language,
-        level,
+        UPDATE course SET deleted_at = NULL, version = version + 1
+        WHERE id = $1 AND teacher_id = $2
+        RETURNING id, teacher_id, name, time, description, format AS "format: CourseFormat", structure, duration, price,
+            language AS "language: CourseLanguage", level AS "level: CourseLevel", deleted_at, version, updated_at"#,
         id,
         teacher_id
     )
-    .fetch_one(pool)
-    .await;
+    .fetch_one(&mut tx)
+    .await?;
 
-    if let Ok(course) = course_row{
-        Ok(course)
-    } else {
-        Err(MyError::NotFound("Course is not found".into()))
+    insert_audit_db(&mut tx, AuditRecord::course(AuditAction::Restore, actor, Some(&current), Some(&row))).await?;
+    tx.commit().await?;
+    Ok(row)
+}
+
+/**
+ * 彻底删除软删除时间早于 older_than 之前的课程, 返回删除的条数
+ * 每一门被清理的课程都会以 system 的身份记一条 purge 历史
+ */
+#[instrument(level = "debug", skip_all)]
+pub async fn purge_deleted_courses_db(pool: &PgPool, older_than: Duration) -> Result<u64, MyError> {
+    let mut tx = pool.begin().await?;
+    let rows = sqlx::query_as!(
+        Course,
+        r#"
+        DELETE FROM course WHERE deleted_at < now() - make_interval(secs => $1)
+        RETURNING id, teacher_id, name, time, description, format AS "format: CourseFormat", structure, duration, price,
+            language AS "language: CourseLanguage", level AS "level: CourseLevel", deleted_at, version, updated_at"#,
+        older_than.num_seconds() as f64
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    for course in &rows {
+        insert_audit_db(&mut tx, AuditRecord::course(AuditAction::Purge, &Actor::system(), Some(course), None)).await?;
     }
+    tx.commit().await?;
+    Ok(rows.len() as u64)
 }
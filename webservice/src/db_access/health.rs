@@ -0,0 +1,40 @@
+// 就绪检查, 在连接池上执行一条最简单的查询
+use crate::models::health::{HealthStatus, PoolStats, StorageCheck};
+use sqlx::postgres::PgPool;
+use std::time::{Duration, Instant};
+use tracing::{instrument, warn};
+
+// 连接池满了的时候获取连接会一直等待, 就绪检查不能跟着卡住
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+// 连接池的使用情况, 不需要访问数据库
+// ? sqlx 0.6 的连接池不能读取最大连接数, 由创建连接池的地方传进来
+pub fn pool_stats_db(pool: &PgPool, max_connections: u32) -> PoolStats {
+    PoolStats::new(pool.size(), pool.num_idle() as u32, max_connections)
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn check_storage_db(pool: &PgPool, max_connections: u32) -> StorageCheck {
+    // 在检查之前统计, 不把检查本身占用的连接算进去
+    let pool_stats = pool_stats_db(pool, max_connections);
+    let started = Instant::now();
+    let result = actix_rt::time::timeout(PING_TIMEOUT, sqlx::query!("SELECT 1 AS one").fetch_one(pool)).await;
+    let error = match result {
+        Ok(Ok(_row)) => None,
+        Ok(Err(err)) => {
+            warn!(error = %err, "readiness check failed");
+            Some("Database is unreachable".to_string())
+        }
+        Err(_elapsed) => {
+            warn!(timeout = ?PING_TIMEOUT, "readiness check timed out");
+            Some("Database did not respond in time".to_string())
+        }
+    };
+    StorageCheck {
+        backend: "postgres",
+        status: if error.is_none() { HealthStatus::Ok } else { HealthStatus::Unavailable },
+        latency_ms: started.elapsed().as_millis() as u64,
+        pool: Some(pool_stats),
+        error,
+    }
+}
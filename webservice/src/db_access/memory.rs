@@ -0,0 +1,921 @@
+// Repository 的内存实现
+// 不需要 postgres, 用于 handler 测试以及前端本地联调
+// 行为尽量和 postgres 实现保持一致 (包括各种 NotFound 的错误信息)
+use super::{CourseRepository, TeacherRepository, WebhookRepository};
+use crate::errors::MyError;
+use crate::events::{CourseChange, CourseChanges};
+use crate::models::audit::{Actor, AuditAction, AuditEntry, AuditRecord, EventPosition};
+use crate::models::auth::{Account, Role};
+use crate::models::course::{Course, CourseFilter, CourseSearchHit, CoursePatch, CreateCourse};
+use crate::models::health::{HealthStatus, PoolStats, StorageCheck};
+use crate::models::pagination::{Page, PageParams};
+use crate::models::patch::apply;
+use crate::models::teacher::{CoursePolicy, CreateTeacher, Teacher, TeacherPatch};
+use crate::models::version::VersionCheck;
+use crate::models::webhook::{
+    event_type, payload, AttemptOutcome, DeliveryDetail, DeliveryStatus, DueDelivery, NewWebhook, NextAttempt, Webhook,
+    WebhookAttempt, WebhookDelivery,
+};
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+// 模拟 postgres 中的 WHERE id > after_id ... LIMIT limit + 1 OFFSET offset
+// rows 需要已经排好序, 自定义排序时 after_id 总是 0
+fn paginate<T>(rows: Vec<T>, page: &PageParams, id_of: impl Fn(&T) -> i32) -> Vec<T> {
+    rows.into_iter()
+        .filter(|row| id_of(row) > page.after_id)
+        .skip(page.offset as usize)
+        .take(page.limit as usize + 1)
+        .collect()
+}
+
+// 把文本按非字母数字字符切分成词, 和 postgres simple 配置的分词大致相同
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty())
+}
+
+// 模拟 ts_headline, 命中的词用 <mark></mark> 包裹
+fn highlight(course: &Course, terms: &[String]) -> String {
+    [Some(&course.name), course.description.as_ref(), course.structure.as_ref()]
+        .into_iter()
+        .flatten()
+        .flat_map(|text| text.split_whitespace())
+        .map(|token| {
+            if words(token).any(|word| terms.contains(&word.to_lowercase())) {
+                format!("<mark>{}</mark>", token)
+            } else {
+                token.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// 模拟 audit_log 表, id 自增, created_at 取当前时间
+// 写入课程记录时广播通知, 模拟 postgres 中的 NOTIFY 触发器
+#[derive(Default)]
+struct AuditLog {
+    entries: Mutex<Vec<AuditEntry>>,
+    changes: CourseChanges,
+}
+
+impl AuditLog {
+    fn record(&self, record: AuditRecord) {
+        let change = (record.entity_type == "course").then_some(CourseChange::Teacher(record.teacher_id));
+        let mut entries = self.entries.lock().unwrap();
+        let id = entries.len() as i32 + 1;
+        entries.push(AuditEntry {
+            id,
+            entity_type: record.entity_type.into(),
+            entity_id: record.entity_id,
+            teacher_id: record.teacher_id,
+            action: record.action.as_str().into(),
+            actor: record.actor,
+            before: record.before,
+            after: record.after,
+            created_at: Utc::now().naive_utc(),
+            // 写入时就是提交, 提交的顺序和 id 的顺序一致
+            commit_seq: Some(id as i64),
+        });
+        drop(entries);
+        if let Some(change) = change {
+            self.changes.notify(change);
+        }
+    }
+
+    fn course_events(&self, teacher_id: i32, after: EventPosition) -> impl Iterator<Item = AuditEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| {
+                entry.entity_type == "course" && entry.teacher_id == teacher_id && EventPosition::from(*entry) > after
+            })
+            .cloned()
+            .collect::<Vec<AuditEntry>>()
+            .into_iter()
+    }
+
+    // after_id 之后的所有记录, 按 id 顺序
+    fn since(&self, after_id: i32) -> Vec<AuditEntry> {
+        self.entries.lock().unwrap().iter().filter(|entry| entry.id > after_id).cloned().collect()
+    }
+
+    fn get(&self, id: i32) -> Option<AuditEntry> {
+        self.entries.lock().unwrap().iter().find(|entry| entry.id == id).cloned()
+    }
+
+    // 和 get_audit_history_db 一致: 不区分老师, 但只有现在的所属老师可以看到
+    fn history(&self, entity_type: &str, entity_id: i32, teacher_id: i32, page: &PageParams) -> Page<AuditEntry> {
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| {
+                entry.entity_type == entity_type && entry.entity_id == entity_id && entry.action != AuditAction::Move.as_str()
+            })
+            .cloned()
+            .collect::<Vec<AuditEntry>>();
+        if entries.last().is_some_and(|entry| entry.teacher_id != teacher_id) {
+            entries.clear();
+        }
+        let total = entries.len() as i64;
+        Page::from_rows(paginate(entries, page, |entry| entry.id), total, page, |entry| entry.id)
+    }
+}
+
+// 和最早版本的 AppState.courses 一样, 用 Mutex 包裹一个 Vec
+// next_id 模拟数据库的自增主键
+#[derive(Default)]
+pub struct MemoryCourseRepository {
+    courses: Mutex<Vec<Course>>,
+    next_id: Mutex<i32>,
+    history: AuditLog,
+}
+
+impl MemoryCourseRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 用已有的数据初始化, 自增 id 从现有最大 id 之后开始, 目前只有测试用到
+    #[cfg(test)]
+    pub fn with_courses(courses: Vec<Course>) -> Self {
+        let next_id = courses.iter().map(|course| course.id).max().unwrap_or(0);
+        MemoryCourseRepository {
+            courses: Mutex::new(courses),
+            next_id: Mutex::new(next_id),
+            history: AuditLog::default(),
+        }
+    }
+
+    // 新增和导入共用, 内存中插入不会失败
+    fn insert_course(&self, new_course: CreateCourse, actor: &Actor) -> Course {
+        let mut next_id = self.next_id.lock().unwrap();
+        *next_id += 1;
+        let course = Course {
+            teacher_id: new_course.teacher_id,
+            id: *next_id,
+            name: new_course.name,
+            time: Some(Utc::now().naive_utc()),
+            description: new_course.description,
+            format: new_course.format,
+            structure: new_course.structure,
+            duration: new_course.duration,
+            price: new_course.price,
+            language: new_course.language,
+            level: new_course.level,
+            deleted_at: None,
+            version: 1,
+            updated_at: Utc::now(),
+        };
+        self.courses.lock().unwrap().push(course.clone());
+        self.history.record(AuditRecord::course(AuditAction::Create, actor, None, Some(&course)));
+        course
+    }
+}
+
+#[async_trait]
+impl CourseRepository for MemoryCourseRepository {
+    async fn get_courses_for_teacher(
+        &self,
+        teacher_id: i32,
+        filter: CourseFilter,
+        page: PageParams,
+    ) -> Result<Page<Course>, MyError> {
+        let mut courses = self
+            .courses
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|course| course.deleted_at.is_none() && course.teacher_id == teacher_id && filter.matches(course))
+            .cloned()
+            .collect::<Vec<Course>>();
+        courses.sort_by(|a, b| filter.compare(a, b));
+
+        let total = courses.len() as i64;
+        let page = Page::from_rows(paginate(courses, &page, |course| course.id), total, &page, |course| course.id);
+        Ok(match filter.sort {
+            Some(_) => page.without_cursor(),
+            None => page,
+        })
+    }
+
+    async fn search_courses(&self, query: String, page: PageParams) -> Result<Page<CourseSearchHit>, MyError> {
+        let terms: Vec<String> = words(&query).map(|word| word.to_lowercase()).collect();
+        let mut hits: Vec<CourseSearchHit> = self
+            .courses
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|course| course.deleted_at.is_none())
+            .filter_map(|course| {
+                // 和 ts_rank 的默认权重一致: name 为 A(1.0), description 为 B(0.4), structure 为 C(0.2)
+                let fields = [
+                    (Some(&course.name), 1.0),
+                    (course.description.as_ref(), 0.4),
+                    (course.structure.as_ref(), 0.2),
+                ];
+                let mut rank = 0.0;
+                for term in &terms {
+                    let weight: f32 = fields
+                        .iter()
+                        .filter(|(text, _)| text.is_some_and(|text| words(text).any(|word| word.to_lowercase() == *term)))
+                        .map(|(_, weight)| *weight)
+                        .sum();
+                    // 和 websearch_to_tsquery 一样, 多个词之间是 AND 的关系
+                    if weight == 0.0 {
+                        return None;
+                    }
+                    rank += weight;
+                }
+                Some(CourseSearchHit {
+                    course: course.clone(),
+                    rank,
+                    snippet: highlight(course, &terms),
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| b.rank.total_cmp(&a.rank).then(a.course.id.cmp(&b.course.id)));
+
+        let total = hits.len() as i64;
+        let page = PageParams { after_id: 0, ..page };
+        Ok(Page::from_rows(paginate(hits, &page, |hit| hit.course.id), total, &page, |hit| hit.course.id).without_cursor())
+    }
+
+    async fn get_course_details(&self, teacher_id: i32, id: i32) -> Result<Course, MyError> {
+        self.courses
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|course| course.deleted_at.is_none() && course.teacher_id == teacher_id && course.id == id)
+            .cloned()
+            .ok_or_else(|| MyError::NotFound("Course Id or Teacher Id is not found".into()))
+    }
+
+    async fn post_new_course(&self, new_course: CreateCourse, actor: &Actor) -> Result<Course, MyError> {
+        Ok(self.insert_course(new_course, actor))
+    }
+
+    async fn import_courses(&self, new_courses: Vec<CreateCourse>, actor: &Actor) -> Result<Vec<Course>, MyError> {
+        // 内存中插入不会失败, 不需要回滚
+        Ok(new_courses.into_iter().map(|new_course| self.insert_course(new_course, actor)).collect())
+    }
+
+    fn export_courses(&self, teacher_id: i32) -> BoxStream<'static, Result<Vec<Course>, MyError>> {
+        let mut courses = self
+            .courses
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|course| course.deleted_at.is_none() && course.teacher_id == teacher_id)
+            .cloned()
+            .collect::<Vec<Course>>();
+        courses.sort_by_key(|course| course.id);
+        stream::iter(Some(Ok(courses))).boxed()
+    }
+
+    async fn delete_course(&self, teacher_id: i32, id: i32, actor: &Actor) -> Result<String, MyError> {
+        let mut courses = self.courses.lock().unwrap();
+        // 软删除, 只记录删除时间
+        match courses
+            .iter_mut()
+            .find(|course| course.deleted_at.is_none() && course.teacher_id == teacher_id && course.id == id)
+        {
+            Some(course) => {
+                let before = course.clone();
+                course.deleted_at = Some(Utc::now().naive_utc());
+                course.version += 1;
+                course.updated_at = Utc::now();
+                self.history.record(AuditRecord::course(AuditAction::Delete, actor, Some(&before), Some(course)));
+                Ok("Delete 1 record".into())
+            }
+            None => Err(MyError::NotFound("Course is not found".into())),
+        }
+    }
+
+    async fn update_course_details(
+        &self,
+        teacher_id: i32,
+        id: i32,
+        patch: CoursePatch,
+        check: &VersionCheck,
+        actor: &Actor,
+    ) -> Result<Course, MyError> {
+        let mut courses = self.courses.lock().unwrap();
+        let current = courses
+            .iter_mut()
+            .find(|course| course.deleted_at.is_none() && course.teacher_id == teacher_id && course.id == id)
+            .ok_or_else(|| MyError::NotFound("Course id not found".into()))?;
+        check.check(current.version)?;
+        let before = current.clone();
+
+        // 和 postgres 实现一样, 空的 patch 不修改, 版本也不变
+        if patch.is_empty() {
+            return Ok(current.clone());
+        }
+        if let Some(name) = patch.name {
+            current.name = name;
+        }
+        apply(patch.description, &mut current.description);
+        apply(patch.format, &mut current.format);
+        apply(patch.structure, &mut current.structure);
+        apply(patch.duration, &mut current.duration);
+        apply(patch.price, &mut current.price);
+        apply(patch.language, &mut current.language);
+        apply(patch.level, &mut current.level);
+        current.version += 1;
+        current.updated_at = Utc::now();
+
+        self.history.record(AuditRecord::course(AuditAction::Update, actor, Some(&before), Some(current)));
+        Ok(current.clone())
+    }
+
+    async fn get_deleted_courses(&self, teacher_id: i32, page: PageParams) -> Result<Page<Course>, MyError> {
+        let mut courses = self
+            .courses
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|course| course.deleted_at.is_some() && course.teacher_id == teacher_id)
+            .cloned()
+            .collect::<Vec<Course>>();
+        courses.sort_by_key(|course| course.id);
+
+        let total = courses.len() as i64;
+        Ok(Page::from_rows(paginate(courses, &page, |course| course.id), total, &page, |course| course.id))
+    }
+
+    async fn restore_course(&self, teacher_id: i32, id: i32, actor: &Actor) -> Result<Course, MyError> {
+        let mut courses = self.courses.lock().unwrap();
+        let course = courses
+            .iter_mut()
+            .find(|course| course.deleted_at.is_some() && course.teacher_id == teacher_id && course.id == id)
+            .ok_or_else(|| MyError::NotFound("Deleted course is not found".into()))?;
+        let before = course.clone();
+        course.deleted_at = None;
+        course.version += 1;
+        course.updated_at = Utc::now();
+        self.history.record(AuditRecord::course(AuditAction::Restore, actor, Some(&before), Some(course)));
+        Ok(course.clone())
+    }
+
+    async fn purge_deleted_courses(&self, older_than: Duration) -> Result<u64, MyError> {
+        let cutoff = Utc::now().naive_utc() - older_than;
+        let mut courses = self.courses.lock().unwrap();
+        let (purged, kept): (Vec<Course>, Vec<Course>) = courses
+            .drain(..)
+            .partition(|course| course.deleted_at.is_some_and(|deleted_at| deleted_at < cutoff));
+        *courses = kept;
+        for course in &purged {
+            self.history.record(AuditRecord::course(AuditAction::Purge, &Actor::system(), Some(course), None));
+        }
+        Ok(purged.len() as u64)
+    }
+
+    async fn get_course_history(&self, teacher_id: i32, id: i32, page: PageParams) -> Result<Page<AuditEntry>, MyError> {
+        Ok(self.history.history("course", id, teacher_id, &page))
+    }
+
+    async fn get_course_events(&self, teacher_id: i32, after: EventPosition, limit: i64) -> Result<Vec<AuditEntry>, MyError> {
+        Ok(self
+            .history
+            .course_events(teacher_id, after)
+            .filter(|entry| entry.action != AuditAction::Purge.as_str())
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn course_event_position(&self, teacher_id: i32, last_event_id: Option<i32>) -> Result<EventPosition, MyError> {
+        let position = match last_event_id {
+            Some(0) => Some(EventPosition::default()),
+            Some(id) => self.history.get(id).as_ref().map(EventPosition::from),
+            None => None,
+        };
+        let latest = || self.history.course_events(teacher_id, EventPosition::default()).last();
+        Ok(position.unwrap_or_else(|| latest().as_ref().map(EventPosition::from).unwrap_or_default()))
+    }
+
+    fn subscribe_course_changes(&self) -> broadcast::Receiver<CourseChange> {
+        self.history.changes.subscribe()
+    }
+
+    // 内存中的数据总是可用的
+    async fn check_storage(&self) -> StorageCheck {
+        StorageCheck { backend: "memory", status: HealthStatus::Ok, latency_ms: 0, pool: None, error: None }
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+}
+
+// 删除老师时需要处理老师名下的课程, 所以持有课程仓库
+// ? 和 postgres 中两个仓库共用一个连接池是一样的道理, 变更历史也共用课程仓库中的一份, 和 audit_log 一样 id 不重复
+pub struct MemoryTeacherRepository {
+    teachers: Mutex<Vec<Teacher>>,
+    next_id: Mutex<i32>,
+    courses: Arc<MemoryCourseRepository>,
+    accounts: Mutex<Vec<Account>>,
+}
+
+impl MemoryTeacherRepository {
+    pub fn new(courses: Arc<MemoryCourseRepository>) -> Self {
+        MemoryTeacherRepository {
+            teachers: Mutex::default(),
+            next_id: Mutex::default(),
+            courses,
+            accounts: Mutex::default(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_teachers(teachers: Vec<Teacher>, courses: Arc<MemoryCourseRepository>) -> Self {
+        let next_id = teachers.iter().map(|teacher| teacher.id).max().unwrap_or(0);
+        MemoryTeacherRepository {
+            teachers: Mutex::new(teachers),
+            next_id: Mutex::new(next_id),
+            courses,
+            accounts: Mutex::default(),
+        }
+    }
+
+    // 新增和注册共用
+    fn insert_teacher(&self, new_teacher: CreateTeacher, actor: &Actor) -> Teacher {
+        let mut next_id = self.next_id.lock().unwrap();
+        *next_id += 1;
+        let teacher = Teacher {
+            id: *next_id,
+            name: Some(new_teacher.name),
+            picture_url: Some(new_teacher.picture_url),
+            profile: Some(new_teacher.profile),
+            deleted_at: None,
+            version: 1,
+            updated_at: Utc::now(),
+        };
+        self.teachers.lock().unwrap().push(teacher.clone());
+        self.courses.history.record(AuditRecord::teacher(AuditAction::Create, actor, None, Some(&teacher)));
+        teacher
+    }
+}
+
+#[async_trait]
+impl TeacherRepository for MemoryTeacherRepository {
+    async fn get_all_teachers(&self, page: PageParams) -> Result<Page<Teacher>, MyError> {
+        let mut teachers = self
+            .teachers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|teacher| teacher.deleted_at.is_none())
+            .cloned()
+            .collect::<Vec<Teacher>>();
+        teachers.sort_by_key(|teacher| teacher.id);
+
+        let total = teachers.len() as i64;
+        Ok(Page::from_rows(paginate(teachers, &page, |teacher| teacher.id), total, &page, |teacher| teacher.id))
+    }
+
+    async fn get_teacher_details(&self, teacher_id: i32) -> Result<Teacher, MyError> {
+        self.teachers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|teacher| teacher.deleted_at.is_none() && teacher.id == teacher_id)
+            .cloned()
+            .ok_or_else(|| MyError::NotFound("Teacher is not found".into()))
+    }
+
+    async fn post_new_teacher(&self, new_teacher: CreateTeacher, actor: &Actor) -> Result<Teacher, MyError> {
+        Ok(self.insert_teacher(new_teacher, actor))
+    }
+
+    async fn register_teacher(
+        &self,
+        new_teacher: CreateTeacher,
+        username: String,
+        password_hash: String,
+        actor: &Actor,
+    ) -> Result<Teacher, MyError> {
+        // 先占住用户名再创建老师, 用户名冲突时什么都不创建
+        let mut accounts = self.accounts.lock().unwrap();
+        if accounts.iter().any(|account| account.username == username) {
+            return Err(MyError::Conflict("Username is already taken".into()));
+        }
+        let teacher = self.insert_teacher(new_teacher, actor);
+        accounts.push(Account {
+            teacher_id: teacher.id,
+            username,
+            password_hash,
+            role: Role::Teacher.as_str().into(),
+        });
+        Ok(teacher)
+    }
+
+    async fn find_account(&self, username: String) -> Result<Option<Account>, MyError> {
+        let account = self
+            .accounts
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|account| account.username == username)
+            .cloned();
+        // 老师已经删除的账号不能再登录
+        Ok(match account {
+            Some(account) if self.get_teacher_details(account.teacher_id).await.is_ok() => Some(account),
+            _ => None,
+        })
+    }
+
+    async fn get_account(&self, teacher_id: i32) -> Result<Option<Account>, MyError> {
+        let account = self
+            .accounts
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|account| account.teacher_id == teacher_id)
+            .cloned();
+        Ok(match account {
+            Some(account) if self.get_teacher_details(account.teacher_id).await.is_ok() => Some(account),
+            _ => None,
+        })
+    }
+
+    async fn update_teacher_details(
+        &self,
+        teacher_id: i32,
+        patch: TeacherPatch,
+        check: &VersionCheck,
+        actor: &Actor,
+    ) -> Result<Teacher, MyError> {
+        let mut teachers = self.teachers.lock().unwrap();
+        let current = teachers
+            .iter_mut()
+            .find(|teacher| teacher.deleted_at.is_none() && teacher.id == teacher_id)
+            .ok_or_else(|| MyError::NotFound("Teacher is not found".into()))?;
+        check.check(current.version)?;
+        let before = current.clone();
+
+        if patch.is_empty() {
+            return Ok(current.clone());
+        }
+        if let Some(name) = patch.name {
+            current.name = Some(name);
+        }
+        apply(patch.picture_url, &mut current.picture_url);
+        apply(patch.profile, &mut current.profile);
+        current.version += 1;
+        current.updated_at = Utc::now();
+
+        self.courses.history.record(AuditRecord::teacher(AuditAction::Update, actor, Some(&before), Some(current)));
+        Ok(current.clone())
+    }
+
+    async fn delete_teacher(&self, teacher_id: i32, policy: CoursePolicy, actor: &Actor) -> Result<(), MyError> {
+        // 先锁老师再锁课程, 和 postgres 的事务一样, 检查不通过时什么都不修改
+        let mut teachers = self.teachers.lock().unwrap();
+        if !teachers.iter().any(|teacher| teacher.deleted_at.is_none() && teacher.id == teacher_id) {
+            return Err(MyError::NotFound("Teacher is not found".into()));
+        }
+        let mut courses = self.courses.courses.lock().unwrap();
+        let owned = courses
+            .iter()
+            .filter(|course| course.deleted_at.is_none() && course.teacher_id == teacher_id)
+            .count();
+        match policy {
+            CoursePolicy::Reject if owned > 0 => {
+                return Err(MyError::Conflict(format!(
+                    "Teacher still has {} courses, delete them or use courses=cascade or courses=reassign",
+                    owned
+                )));
+            }
+            CoursePolicy::Reassign(new_teacher_id) if new_teacher_id == teacher_id => {
+                return Err(MyError::InvalidInput("Can not reassign courses to the teacher being deleted".into()));
+            }
+            CoursePolicy::Reassign(new_teacher_id)
+                if !teachers.iter().any(|teacher| teacher.deleted_at.is_none() && teacher.id == new_teacher_id) =>
+            {
+                return Err(MyError::InvalidInput("Teacher to reassign courses to is not found".into()));
+            }
+            _ => {}
+        }
+
+        let now = Utc::now().naive_utc();
+        for course in courses
+            .iter_mut()
+            .filter(|course| course.deleted_at.is_none() && course.teacher_id == teacher_id)
+        {
+            let before = course.clone();
+            let action = match policy {
+                CoursePolicy::Cascade => {
+                    course.deleted_at = Some(now);
+                    AuditAction::Delete
+                }
+                CoursePolicy::Reassign(new_teacher_id) => {
+                    course.teacher_id = new_teacher_id;
+                    AuditAction::Update
+                }
+                CoursePolicy::Reject => unreachable!("teacher with courses is rejected above"),
+            };
+            course.version += 1;
+            course.updated_at = Utc::now();
+            self.courses.history.record(AuditRecord::course(action, actor, Some(&before), Some(course)));
+            if before.teacher_id != course.teacher_id {
+                self.courses.history.record(AuditRecord::course_moved(actor, &before, course));
+            }
+        }
+
+        let teacher = teachers
+            .iter_mut()
+            .find(|teacher| teacher.deleted_at.is_none() && teacher.id == teacher_id)
+            .expect("teacher exists, checked above");
+        let before = teacher.clone();
+        teacher.deleted_at = Some(now);
+        teacher.version += 1;
+        teacher.updated_at = Utc::now();
+        self.courses.history.record(AuditRecord::teacher(AuditAction::Delete, actor, Some(&before), Some(teacher)));
+        Ok(())
+    }
+
+    async fn restore_teacher(&self, teacher_id: i32, actor: &Actor) -> Result<Teacher, MyError> {
+        let mut teachers = self.teachers.lock().unwrap();
+        let teacher = teachers
+            .iter_mut()
+            .find(|teacher| teacher.deleted_at.is_some() && teacher.id == teacher_id)
+            .ok_or_else(|| MyError::NotFound("Deleted teacher is not found".into()))?;
+        let before = teacher.clone();
+        teacher.deleted_at = None;
+        teacher.version += 1;
+        teacher.updated_at = Utc::now();
+        self.courses.history.record(AuditRecord::teacher(AuditAction::Restore, actor, Some(&before), Some(teacher)));
+        Ok(teacher.clone())
+    }
+
+    async fn purge_deleted_teachers(&self, older_than: Duration) -> Result<u64, MyError> {
+        let cutoff = Utc::now().naive_utc() - older_than;
+        let mut teachers = self.teachers.lock().unwrap();
+        let (purged, kept): (Vec<Teacher>, Vec<Teacher>) = teachers
+            .drain(..)
+            .partition(|teacher| teacher.deleted_at.is_some_and(|deleted_at| deleted_at < cutoff));
+        *teachers = kept;
+        for teacher in &purged {
+            self.courses.history.record(AuditRecord::teacher(AuditAction::Purge, &Actor::system(), Some(teacher), None));
+        }
+        Ok(purged.len() as u64)
+    }
+
+    async fn get_teacher_history(&self, teacher_id: i32, page: PageParams) -> Result<Page<AuditEntry>, MyError> {
+        Ok(self.courses.history.history("teacher", teacher_id, teacher_id, &page))
+    }
+}
+
+// 内存中的 webhook 和投递记录, 密钥不放在 Webhook 中, 和 postgres 一样不会被查询接口返回
+#[derive(Default)]
+struct WebhookStore {
+    webhooks: Vec<(Webhook, String)>,
+    deliveries: Vec<WebhookDelivery>,
+    attempts: Vec<WebhookAttempt>,
+    next_webhook_id: i32,
+    next_delivery_id: i32,
+    next_attempt_id: i32,
+    // 已经创建过投递的最后一条变更记录
+    synced_event_id: i32,
+}
+
+impl WebhookStore {
+    // 模拟 audit_log 上的 enqueue_webhook_deliveries 触发器, 为新的变更记录创建投递
+    // ? 在每次访问时补上, 而不是写入变更历史时, 这样课程和老师仓库不需要知道 webhook
+    fn sync(&mut self, history: &AuditLog) {
+        for entry in history.since(self.synced_event_id) {
+            self.synced_event_id = entry.id;
+            let kind = match event_type(&entry) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let subscribed: Vec<i32> = self
+                .webhooks
+                .iter()
+                .filter(|(webhook, _secret)| {
+                    let owner = match webhook.teacher_id {
+                        Some(teacher_id) => teacher_id == entry.teacher_id,
+                        None => entry.action != AuditAction::Move.as_str(),
+                    };
+                    owner && webhook.events.contains(&kind)
+                })
+                .map(|(webhook, _secret)| webhook.id)
+                .collect();
+            for webhook_id in subscribed {
+                self.next_delivery_id += 1;
+                self.deliveries.push(WebhookDelivery {
+                    id: self.next_delivery_id,
+                    webhook_id,
+                    event_id: entry.id,
+                    event_type: kind.clone(),
+                    status: DeliveryStatus::Pending.as_str().into(),
+                    attempts: 0,
+                    next_attempt_at: Utc::now(),
+                    last_status_code: None,
+                    last_error: None,
+                    created_at: Utc::now(),
+                    delivered_at: None,
+                });
+            }
+        }
+    }
+
+    fn delivery_mut(&mut self, webhook_id: i32, delivery_id: i32) -> Result<&mut WebhookDelivery, MyError> {
+        self.deliveries
+            .iter_mut()
+            .find(|delivery| delivery.id == delivery_id && delivery.webhook_id == webhook_id)
+            .ok_or_else(|| MyError::NotFound("Webhook delivery is not found".into()))
+    }
+}
+
+// 投递由变更历史生成, 所以持有课程仓库 (变更历史在其中)
+pub struct MemoryWebhookRepository {
+    courses: Arc<MemoryCourseRepository>,
+    store: Mutex<WebhookStore>,
+}
+
+impl MemoryWebhookRepository {
+    pub fn new(courses: Arc<MemoryCourseRepository>) -> Self {
+        MemoryWebhookRepository { courses, store: Mutex::default() }
+    }
+
+    fn store(&self) -> std::sync::MutexGuard<'_, WebhookStore> {
+        let mut store = self.store.lock().unwrap();
+        store.sync(&self.courses.history);
+        store
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for MemoryWebhookRepository {
+    async fn create_webhook(&self, new_webhook: NewWebhook, actor: &Actor) -> Result<Webhook, MyError> {
+        // 先为已有的变更创建投递, 新的 webhook 只接收之后的事件
+        let mut store = self.store();
+        store.next_webhook_id += 1;
+        let webhook = Webhook {
+            id: store.next_webhook_id,
+            teacher_id: new_webhook.teacher_id,
+            url: new_webhook.url,
+            events: new_webhook.events,
+            created_by: actor.0.clone(),
+            created_at: Utc::now(),
+        };
+        store.webhooks.push((webhook.clone(), new_webhook.secret));
+        Ok(webhook)
+    }
+
+    async fn list_webhooks(&self, teacher_id: Option<i32>, page: PageParams) -> Result<Page<Webhook>, MyError> {
+        let webhooks: Vec<Webhook> = self
+            .store()
+            .webhooks
+            .iter()
+            .map(|(webhook, _secret)| webhook)
+            .filter(|webhook| teacher_id.is_none() || webhook.teacher_id == teacher_id)
+            .cloned()
+            .collect();
+        let total = webhooks.len() as i64;
+        Ok(Page::from_rows(paginate(webhooks, &page, |webhook| webhook.id), total, &page, |webhook| webhook.id))
+    }
+
+    async fn get_webhook(&self, id: i32) -> Result<Webhook, MyError> {
+        self.store()
+            .webhooks
+            .iter()
+            .find(|(webhook, _secret)| webhook.id == id)
+            .map(|(webhook, _secret)| webhook.clone())
+            .ok_or_else(|| MyError::NotFound("Webhook is not found".into()))
+    }
+
+    async fn delete_webhook(&self, id: i32) -> Result<(), MyError> {
+        let mut store = self.store();
+        let before = store.webhooks.len();
+        store.webhooks.retain(|(webhook, _secret)| webhook.id != id);
+        if store.webhooks.len() == before {
+            return Err(MyError::NotFound("Webhook is not found".into()));
+        }
+        // 模拟 ON DELETE CASCADE
+        let WebhookStore { deliveries, attempts, .. } = &mut *store;
+        let removed: Vec<i32> = deliveries.iter().filter(|delivery| delivery.webhook_id == id).map(|delivery| delivery.id).collect();
+        deliveries.retain(|delivery| delivery.webhook_id != id);
+        attempts.retain(|attempt| !removed.contains(&attempt.delivery_id));
+        Ok(())
+    }
+
+    async fn list_deliveries(
+        &self,
+        webhook_id: i32,
+        status: Option<DeliveryStatus>,
+        page: PageParams,
+    ) -> Result<Page<WebhookDelivery>, MyError> {
+        let deliveries: Vec<WebhookDelivery> = self
+            .store()
+            .deliveries
+            .iter()
+            .filter(|delivery| delivery.webhook_id == webhook_id)
+            .filter(|delivery| status.is_none_or(|status| delivery.status == status.as_str()))
+            .cloned()
+            .collect();
+        let total = deliveries.len() as i64;
+        Ok(Page::from_rows(paginate(deliveries, &page, |delivery| delivery.id), total, &page, |delivery| delivery.id))
+    }
+
+    async fn get_delivery(&self, webhook_id: i32, delivery_id: i32) -> Result<DeliveryDetail, MyError> {
+        let mut store = self.store();
+        let delivery = store.delivery_mut(webhook_id, delivery_id)?.clone();
+        let event = self
+            .courses
+            .history
+            .get(delivery.event_id)
+            .ok_or_else(|| MyError::DBError(format!("audit entry {} is missing", delivery.event_id)))?;
+        let attempts = store.attempts.iter().filter(|attempt| attempt.delivery_id == delivery_id).cloned().collect();
+        Ok(DeliveryDetail {
+            payload: payload(&delivery.event_type, &event),
+            delivery,
+            attempts,
+        })
+    }
+
+    async fn redeliver(&self, webhook_id: i32, delivery_id: i32) -> Result<WebhookDelivery, MyError> {
+        let mut store = self.store();
+        let delivery = store.delivery_mut(webhook_id, delivery_id)?;
+        delivery.status = DeliveryStatus::Pending.as_str().into();
+        delivery.attempts = 0;
+        delivery.next_attempt_at = Utc::now();
+        delivery.delivered_at = None;
+        Ok(delivery.clone())
+    }
+
+    async fn claim_due_deliveries(&self, limit: i64, lease: Duration) -> Result<Vec<DueDelivery>, MyError> {
+        let mut store = self.store();
+        let now = Utc::now();
+        let WebhookStore { webhooks, deliveries, .. } = &mut *store;
+        let mut due: Vec<&mut WebhookDelivery> = deliveries
+            .iter_mut()
+            .filter(|delivery| delivery.status == DeliveryStatus::Pending.as_str() && delivery.next_attempt_at <= now)
+            .collect();
+        due.sort_by_key(|delivery| delivery.next_attempt_at);
+        let mut claimed = Vec::new();
+        for delivery in due.into_iter().take(limit as usize) {
+            delivery.next_attempt_at = now + lease;
+            let (webhook, secret) = webhooks
+                .iter()
+                .find(|(webhook, _secret)| webhook.id == delivery.webhook_id)
+                .expect("delivery without webhook");
+            let event = self.courses.history.get(delivery.event_id).expect("delivery without audit entry");
+            claimed.push(DueDelivery {
+                id: delivery.id,
+                url: webhook.url.clone(),
+                secret: secret.clone(),
+                attempts: delivery.attempts,
+                event_type: delivery.event_type.clone(),
+                event,
+            });
+        }
+        claimed.sort_by_key(|delivery| delivery.id);
+        Ok(claimed)
+    }
+
+    async fn record_attempt(&self, delivery_id: i32, outcome: AttemptOutcome, next: NextAttempt) -> Result<(), MyError> {
+        let mut store = self.store();
+        let delivery = match store.deliveries.iter_mut().find(|delivery| delivery.id == delivery_id) {
+            Some(delivery) => delivery,
+            // 发送期间 webhook 被删除了, 不需要再记录
+            None => return Ok(()),
+        };
+        delivery.attempts += 1;
+        delivery.last_status_code = outcome.status_code;
+        delivery.last_error = outcome.error.clone();
+        let status = match next {
+            NextAttempt::Delivered => {
+                delivery.delivered_at = Some(Utc::now());
+                DeliveryStatus::Delivered
+            }
+            NextAttempt::RetryAt(at) => {
+                delivery.next_attempt_at = at;
+                DeliveryStatus::Pending
+            }
+            NextAttempt::Dead => DeliveryStatus::Dead,
+        };
+        delivery.status = status.as_str().into();
+        let attempt = delivery.attempts;
+        store.next_attempt_id += 1;
+        let id = store.next_attempt_id;
+        store.attempts.push(WebhookAttempt {
+            id,
+            delivery_id,
+            attempt,
+            status_code: outcome.status_code,
+            error: outcome.error,
+            duration_ms: outcome.duration_ms,
+            created_at: Utc::now(),
+        });
+        Ok(())
+    }
+}
@@ -1,2 +1,130 @@
+pub mod audit; // 变更历史
 pub mod course;
-pub mod teacher;
\ No newline at end of file
+pub mod health; // 就绪检查
+pub mod memory; // 内存实现, 不依赖 postgres
+pub mod postgres; // postgres 实现, 内部调用 course.rs 和 teacher.rs 中的 sqlx 代码
+pub mod teacher;
+pub mod webhook; // webhook 和投递记录, 投递任务见 webhooks.rs
+
+use crate::errors::MyError;
+use crate::events::CourseChange;
+use crate::models::audit::{Actor, AuditEntry, EventPosition};
+use crate::models::auth::Account;
+use crate::models::course::{Course, CourseFilter, CourseSearchHit, CoursePatch, CreateCourse};
+use crate::models::health::{PoolStats, StorageCheck};
+use crate::models::pagination::{Page, PageParams};
+use crate::models::teacher::{CoursePolicy, CreateTeacher, Teacher, TeacherPatch};
+use crate::models::version::VersionCheck;
+use crate::models::webhook::{
+    AttemptOutcome, DeliveryDetail, DeliveryStatus, DueDelivery, NewWebhook, NextAttempt, Webhook, WebhookDelivery,
+};
+use async_trait::async_trait;
+use chrono::Duration;
+use futures_util::stream::BoxStream;
+use tokio::sync::broadcast;
+
+// 课程数据访问的抽象, AppState 持有的是 trait 对象, 而不是具体的 PgPool
+// 这样 handler 就不关心数据到底存在哪里, 测试时可以直接换成内存实现
+// ? Send + Sync 是因为 AppState 会被 actix 的多个 worker 线程共享
+#[async_trait]
+pub trait CourseRepository: Send + Sync {
+    async fn get_courses_for_teacher(
+        &self,
+        teacher_id: i32,
+        filter: CourseFilter,
+        page: PageParams,
+    ) -> Result<Page<Course>, MyError>;
+    // 跨老师的全文检索, query 是校验过的关键字
+    async fn search_courses(&self, query: String, page: PageParams) -> Result<Page<CourseSearchHit>, MyError>;
+    async fn get_course_details(&self, teacher_id: i32, id: i32) -> Result<Course, MyError>;
+    // 以下修改操作都会以 actor 的身份写入一条变更历史
+    async fn post_new_course(&self, new_course: CreateCourse, actor: &Actor) -> Result<Course, MyError>;
+    // 批量导入, 要么全部成功, 要么一条都不导入
+    async fn import_courses(&self, new_courses: Vec<CreateCourse>, actor: &Actor) -> Result<Vec<Course>, MyError>;
+    // 导出老师的所有课程, 按 id 升序分批返回, 不需要一次读入内存
+    fn export_courses(&self, teacher_id: i32) -> BoxStream<'static, Result<Vec<Course>, MyError>>;
+    async fn delete_course(&self, teacher_id: i32, id: i32, actor: &Actor) -> Result<String, MyError>;
+    async fn update_course_details(
+        &self,
+        teacher_id: i32,
+        id: i32,
+        // PUT 和 PATCH 都转换为 patch, 没有传的字段不修改, null 清空
+        patch: CoursePatch,
+        // If-Match 的条件, 版本不满足时返回 PreconditionFailed
+        check: &VersionCheck,
+        actor: &Actor,
+    ) -> Result<Course, MyError>;
+    // 回收站, 已软删除的课程
+    async fn get_deleted_courses(&self, teacher_id: i32, page: PageParams) -> Result<Page<Course>, MyError>;
+    async fn restore_course(&self, teacher_id: i32, id: i32, actor: &Actor) -> Result<Course, MyError>;
+    // 彻底删除软删除时间超过 older_than 的课程, 返回删除的条数
+    async fn purge_deleted_courses(&self, older_than: Duration) -> Result<u64, MyError>;
+    // 课程的变更历史, 按发生的先后顺序
+    async fn get_course_history(&self, teacher_id: i32, id: i32, page: PageParams) -> Result<Page<AuditEntry>, MyError>;
+    // 老师的课程在 after 之后的变更, 按提交的顺序, SSE 推送使用 (见 events.rs)
+    async fn get_course_events(&self, teacher_id: i32, after: EventPosition, limit: i64) -> Result<Vec<AuditEntry>, MyError>;
+    // SSE 开始推送的位置, last_event_id 是重连时客户端带回来的事件 id, 没有时从最新的变更之后开始
+    async fn course_event_position(&self, teacher_id: i32, last_event_id: Option<i32>) -> Result<EventPosition, MyError>;
+    // 订阅课程变更的通知, 多个实例时也能收到其他实例上的变更
+    fn subscribe_course_changes(&self) -> broadcast::Receiver<CourseChange>;
+    // 就绪检查, 课程和老师共用同一个存储, 所以只放在这里
+    // ? 不返回错误, 不可用的原因记录在 StorageCheck 中
+    async fn check_storage(&self) -> StorageCheck;
+    // 连接池的使用情况, 没有连接池时返回 None
+    fn pool_stats(&self) -> Option<PoolStats>;
+}
+
+// 教师数据访问的抽象, 同上
+#[async_trait]
+pub trait TeacherRepository: Send + Sync {
+    async fn get_all_teachers(&self, page: PageParams) -> Result<Page<Teacher>, MyError>;
+    async fn get_teacher_details(&self, teacher_id: i32) -> Result<Teacher, MyError>;
+    async fn post_new_teacher(&self, new_teacher: CreateTeacher, actor: &Actor) -> Result<Teacher, MyError>;
+    // 注册老师并创建登录账号, password_hash 是已经哈希过的密码
+    async fn register_teacher(
+        &self,
+        new_teacher: CreateTeacher,
+        username: String,
+        password_hash: String,
+        actor: &Actor,
+    ) -> Result<Teacher, MyError>;
+    // 登录时按用户名查找账号, 老师已经删除时返回 None
+    async fn find_account(&self, username: String) -> Result<Option<Account>, MyError>;
+    // 刷新 token 时按老师查找账号, 角色可能已经变了
+    async fn get_account(&self, teacher_id: i32) -> Result<Option<Account>, MyError>;
+    async fn update_teacher_details(
+        &self,
+        teacher_id: i32,
+        patch: TeacherPatch,
+        check: &VersionCheck,
+        actor: &Actor,
+    ) -> Result<Teacher, MyError>;
+    // 老师名下的课程按 policy 处理, 老师不存在时返回 NotFound
+    async fn delete_teacher(&self, teacher_id: i32, policy: CoursePolicy, actor: &Actor) -> Result<(), MyError>;
+    async fn restore_teacher(&self, teacher_id: i32, actor: &Actor) -> Result<Teacher, MyError>;
+    async fn purge_deleted_teachers(&self, older_than: Duration) -> Result<u64, MyError>;
+    async fn get_teacher_history(&self, teacher_id: i32, page: PageParams) -> Result<Page<AuditEntry>, MyError>;
+}
+
+// webhook 的订阅和投递记录, 同上
+// ? 投递记录在写入变更历史时创建 (postgres 中是 audit_log 上的触发器), 所以这里没有新增投递的方法
+#[async_trait]
+pub trait WebhookRepository: Send + Sync {
+    async fn create_webhook(&self, new_webhook: NewWebhook, actor: &Actor) -> Result<Webhook, MyError>;
+    // teacher_id 为 None 时返回所有的 webhook
+    async fn list_webhooks(&self, teacher_id: Option<i32>, page: PageParams) -> Result<Page<Webhook>, MyError>;
+    async fn get_webhook(&self, id: i32) -> Result<Webhook, MyError>;
+    async fn delete_webhook(&self, id: i32) -> Result<(), MyError>;
+    async fn list_deliveries(
+        &self,
+        webhook_id: i32,
+        status: Option<DeliveryStatus>,
+        page: PageParams,
+    ) -> Result<Page<WebhookDelivery>, MyError>;
+    async fn get_delivery(&self, webhook_id: i32, delivery_id: i32) -> Result<DeliveryDetail, MyError>;
+    // 重新投递, 状态改回 pending, 重试次数清零
+    async fn redeliver(&self, webhook_id: i32, delivery_id: i32) -> Result<WebhookDelivery, MyError>;
+    // 领取最多 limit 条到期的投递, lease 之内不会被再次领取
+    async fn claim_due_deliveries(&self, limit: i64, lease: Duration) -> Result<Vec<DueDelivery>, MyError>;
+    async fn record_attempt(&self, delivery_id: i32, outcome: AttemptOutcome, next: NextAttempt) -> Result<(), MyError>;
+}
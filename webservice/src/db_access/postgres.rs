@@ -0,0 +1,257 @@
+// Repository 的 postgres 实现
+// 只是把 course.rs / teacher.rs 里面的 sqlx 函数包装一层, 真正的 SQL 还在原来的地方
+use super::audit::{course_event_position_db, get_audit_history_db, get_course_events_db};
+use super::course::*;
+use super::health::{check_storage_db, pool_stats_db};
+use super::teacher::*;
+use super::webhook::*;
+use super::{CourseRepository, TeacherRepository, WebhookRepository};
+use crate::errors::MyError;
+use crate::events::{CourseChange, CourseChanges};
+use crate::models::audit::{Actor, AuditEntry, EventPosition};
+use crate::models::auth::Account;
+use crate::models::course::{Course, CourseFilter, CourseSearchHit, CoursePatch, CreateCourse};
+use crate::models::health::{PoolStats, StorageCheck};
+use crate::models::pagination::{Page, PageParams};
+use crate::models::teacher::{CoursePolicy, CreateTeacher, Teacher, TeacherPatch};
+use crate::models::version::VersionCheck;
+use crate::models::webhook::{
+    AttemptOutcome, DeliveryDetail, DeliveryStatus, DueDelivery, NewWebhook, NextAttempt, Webhook, WebhookDelivery,
+};
+use async_trait::async_trait;
+use chrono::Duration;
+use futures_util::stream::BoxStream;
+use sqlx::postgres::PgPool;
+use tokio::sync::broadcast;
+
+// PgPool 内部本身就是 Arc, clone 的代价很低, 所以课程和教师可以共用同一个连接池
+#[derive(Clone)]
+pub struct PgCourseRepository {
+    pool: PgPool,
+    // 连接池的最大连接数, 就绪检查中计算连接池的饱和度
+    max_connections: u32,
+    // 由 events::listen_for_course_changes 把 NOTIFY 转发过来
+    changes: CourseChanges,
+}
+
+impl PgCourseRepository {
+    pub fn new(pool: PgPool, max_connections: u32) -> Self {
+        PgCourseRepository {
+            pool,
+            max_connections,
+            changes: CourseChanges::new(),
+        }
+    }
+
+    pub fn changes(&self) -> CourseChanges {
+        self.changes.clone()
+    }
+}
+
+#[async_trait]
+impl CourseRepository for PgCourseRepository {
+    async fn get_courses_for_teacher(
+        &self,
+        teacher_id: i32,
+        filter: CourseFilter,
+        page: PageParams,
+    ) -> Result<Page<Course>, MyError> {
+        get_courses_for_teacher_db(&self.pool, teacher_id, &filter, &page).await
+    }
+
+    async fn search_courses(&self, query: String, page: PageParams) -> Result<Page<CourseSearchHit>, MyError> {
+        search_courses_db(&self.pool, &query, &page).await
+    }
+
+    async fn get_course_details(&self, teacher_id: i32, id: i32) -> Result<Course, MyError> {
+        get_course_details_db(&self.pool, teacher_id, id).await
+    }
+
+    async fn post_new_course(&self, new_course: CreateCourse, actor: &Actor) -> Result<Course, MyError> {
+        post_new_course_db(&self.pool, new_course, actor).await
+    }
+
+    async fn import_courses(&self, new_courses: Vec<CreateCourse>, actor: &Actor) -> Result<Vec<Course>, MyError> {
+        import_courses_db(&self.pool, new_courses, actor).await
+    }
+
+    fn export_courses(&self, teacher_id: i32) -> BoxStream<'static, Result<Vec<Course>, MyError>> {
+        export_courses_db(self.pool.clone(), teacher_id)
+    }
+
+    async fn delete_course(&self, teacher_id: i32, id: i32, actor: &Actor) -> Result<String, MyError> {
+        delete_course_db(&self.pool, teacher_id, id, actor).await
+    }
+
+    async fn update_course_details(
+        &self,
+        teacher_id: i32,
+        id: i32,
+        patch: CoursePatch,
+        check: &VersionCheck,
+        actor: &Actor,
+    ) -> Result<Course, MyError> {
+        update_course_details_db(&self.pool, teacher_id, id, patch, check, actor).await
+    }
+
+    async fn get_deleted_courses(&self, teacher_id: i32, page: PageParams) -> Result<Page<Course>, MyError> {
+        get_deleted_courses_db(&self.pool, teacher_id, &page).await
+    }
+
+    async fn restore_course(&self, teacher_id: i32, id: i32, actor: &Actor) -> Result<Course, MyError> {
+        restore_course_db(&self.pool, teacher_id, id, actor).await
+    }
+
+    async fn purge_deleted_courses(&self, older_than: Duration) -> Result<u64, MyError> {
+        purge_deleted_courses_db(&self.pool, older_than).await
+    }
+
+    async fn get_course_history(&self, teacher_id: i32, id: i32, page: PageParams) -> Result<Page<AuditEntry>, MyError> {
+        get_audit_history_db(&self.pool, "course", id, teacher_id, &page).await
+    }
+
+    async fn get_course_events(&self, teacher_id: i32, after: EventPosition, limit: i64) -> Result<Vec<AuditEntry>, MyError> {
+        get_course_events_db(&self.pool, teacher_id, after, limit).await
+    }
+
+    async fn course_event_position(&self, teacher_id: i32, last_event_id: Option<i32>) -> Result<EventPosition, MyError> {
+        course_event_position_db(&self.pool, teacher_id, last_event_id).await
+    }
+
+    fn subscribe_course_changes(&self) -> broadcast::Receiver<CourseChange> {
+        self.changes.subscribe()
+    }
+
+    async fn check_storage(&self) -> StorageCheck {
+        check_storage_db(&self.pool, self.max_connections).await
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(pool_stats_db(&self.pool, self.max_connections))
+    }
+}
+
+#[derive(Clone)]
+pub struct PgTeacherRepository {
+    pool: PgPool,
+}
+
+impl PgTeacherRepository {
+    pub fn new(pool: PgPool) -> Self {
+        PgTeacherRepository { pool }
+    }
+}
+
+#[async_trait]
+impl TeacherRepository for PgTeacherRepository {
+    async fn get_all_teachers(&self, page: PageParams) -> Result<Page<Teacher>, MyError> {
+        get_all_teachers_db(&self.pool, &page).await
+    }
+
+    async fn get_teacher_details(&self, teacher_id: i32) -> Result<Teacher, MyError> {
+        get_teacher_details_db(&self.pool, teacher_id).await
+    }
+
+    async fn post_new_teacher(&self, new_teacher: CreateTeacher, actor: &Actor) -> Result<Teacher, MyError> {
+        post_new_teacher_db(&self.pool, new_teacher, actor).await
+    }
+
+    async fn register_teacher(
+        &self,
+        new_teacher: CreateTeacher,
+        username: String,
+        password_hash: String,
+        actor: &Actor,
+    ) -> Result<Teacher, MyError> {
+        register_teacher_db(&self.pool, new_teacher, username, password_hash, actor).await
+    }
+
+    async fn find_account(&self, username: String) -> Result<Option<Account>, MyError> {
+        find_account_db(&self.pool, &username).await
+    }
+
+    async fn get_account(&self, teacher_id: i32) -> Result<Option<Account>, MyError> {
+        get_account_db(&self.pool, teacher_id).await
+    }
+
+    async fn update_teacher_details(
+        &self,
+        teacher_id: i32,
+        patch: TeacherPatch,
+        check: &VersionCheck,
+        actor: &Actor,
+    ) -> Result<Teacher, MyError> {
+        update_teacher_details_db(&self.pool, teacher_id, patch, check, actor).await
+    }
+
+    async fn delete_teacher(&self, teacher_id: i32, policy: CoursePolicy, actor: &Actor) -> Result<(), MyError> {
+        delete_teacher_db(&self.pool, teacher_id, policy, actor).await
+    }
+
+    async fn restore_teacher(&self, teacher_id: i32, actor: &Actor) -> Result<Teacher, MyError> {
+        restore_teacher_db(&self.pool, teacher_id, actor).await
+    }
+
+    async fn purge_deleted_teachers(&self, older_than: Duration) -> Result<u64, MyError> {
+        purge_deleted_teachers_db(&self.pool, older_than).await
+    }
+
+    async fn get_teacher_history(&self, teacher_id: i32, page: PageParams) -> Result<Page<AuditEntry>, MyError> {
+        get_audit_history_db(&self.pool, "teacher", teacher_id, teacher_id, &page).await
+    }
+}
+
+#[derive(Clone)]
+pub struct PgWebhookRepository {
+    pool: PgPool,
+}
+
+impl PgWebhookRepository {
+    pub fn new(pool: PgPool) -> Self {
+        PgWebhookRepository { pool }
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for PgWebhookRepository {
+    async fn create_webhook(&self, new_webhook: NewWebhook, actor: &Actor) -> Result<Webhook, MyError> {
+        create_webhook_db(&self.pool, new_webhook, actor).await
+    }
+
+    async fn list_webhooks(&self, teacher_id: Option<i32>, page: PageParams) -> Result<Page<Webhook>, MyError> {
+        list_webhooks_db(&self.pool, teacher_id, &page).await
+    }
+
+    async fn get_webhook(&self, id: i32) -> Result<Webhook, MyError> {
+        get_webhook_db(&self.pool, id).await
+    }
+
+    async fn delete_webhook(&self, id: i32) -> Result<(), MyError> {
+        delete_webhook_db(&self.pool, id).await
+    }
+
+    async fn list_deliveries(
+        &self,
+        webhook_id: i32,
+        status: Option<DeliveryStatus>,
+        page: PageParams,
+    ) -> Result<Page<WebhookDelivery>, MyError> {
+        list_deliveries_db(&self.pool, webhook_id, status, &page).await
+    }
+
+    async fn get_delivery(&self, webhook_id: i32, delivery_id: i32) -> Result<DeliveryDetail, MyError> {
+        get_delivery_db(&self.pool, webhook_id, delivery_id).await
+    }
+
+    async fn redeliver(&self, webhook_id: i32, delivery_id: i32) -> Result<WebhookDelivery, MyError> {
+        redeliver_db(&self.pool, webhook_id, delivery_id).await
+    }
+
+    async fn claim_due_deliveries(&self, limit: i64, lease: Duration) -> Result<Vec<DueDelivery>, MyError> {
+        claim_due_deliveries_db(&self.pool, limit, lease).await
+    }
+
+    async fn record_attempt(&self, delivery_id: i32, outcome: AttemptOutcome, next: NextAttempt) -> Result<(), MyError> {
+        record_attempt_db(&self.pool, delivery_id, outcome, next).await
+    }
+}
@@ -1,11 +1,30 @@
+use super::audit::insert_audit_db;
 use crate::errors::MyError;
-use crate::models::teacher::{CreateTeacher, Teacher, UpdateTeacher};
-use sqlx::postgres::PgPool;
+use crate::models::audit::{Actor, AuditAction, AuditRecord};
+use crate::models::auth::Account;
+use chrono::Duration;
+use crate::models::pagination::{Page, PageParams};
+use crate::models::course::Course;
+use crate::models::course_options::{CourseFormat, CourseLanguage, CourseLevel};
+use crate::models::teacher::{CoursePolicy, CreateTeacher, Teacher, TeacherPatch};
+use crate::models::version::VersionCheck;
+use sqlx::postgres::{PgPool, Postgres};
+use sqlx::Transaction;
+use tracing::instrument;
 
-pub async fn get_all_teachers_db(pool: &PgPool) -> Result<Vec<Teacher>, MyError> {
-    let rows = sqlx::query!(r#"SELECT id, name, picture_url, profile FROM teacher"#)
-        .fetch_all(pool)
-        .await?;
+#[instrument(level = "debug", skip_all)]
+pub async fn get_all_teachers_db(pool: &PgPool, page: &PageParams) -> Result<Page<Teacher>, MyError> {
+    let rows = sqlx::query!(
+        r#"SELECT id, name, picture_url, profile, deleted_at, version, updated_at FROM teacher
+        WHERE deleted_at IS NULL AND id > $1
+        ORDER BY id
+        LIMIT $2 OFFSET $3"#,
+        page.after_id,
+        page.limit + 1,
+        page.offset
+    )
+    .fetch_all(pool)
+    .await?;
 
     let teachers: Vec<Teacher> = rows
         .iter()
@@ -14,21 +33,26 @@ pub async fn get_all_teachers_db(pool: &PgPool) -> Result<Vec<Teacher>, MyError>
             name: row.name.clone(),
             picture_url: row.picture_url.clone(),
             profile: row.profile.clone(),
+            deleted_at: row.deleted_at,
+            version: row.version,
+            updated_at: row.updated_at,
         })
         .collect();
 
-    match teachers.len() {
-        0 => Err(MyError::NotFound("No teachers found".into())),
-        _ => Ok(teachers),
-    }
+    let total = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "total!" FROM teacher WHERE deleted_at IS NULL"#)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(Page::from_rows(teachers, total, page, |teacher| teacher.id))
 }
 
+#[instrument(level = "debug", skip_all, fields(teacher_id = teacher_id))]
 pub async fn get_teacher_details_db(pool: &PgPool, teacher_id: i32) -> Result<Teacher, MyError> {
     let row: Option<Teacher> = sqlx::query_as!(
         Teacher,
         r#"
-        SELECT id, name, picture_url, profile FROM teacher
-        WHERE id = $1"#,
+        SELECT id, name, picture_url, profile, deleted_at, version, updated_at FROM teacher
+        WHERE id = $1 AND deleted_at IS NULL"#,
         teacher_id
     )
     .fetch_optional(pool)
@@ -41,63 +65,263 @@ pub async fn get_teacher_details_db(pool: &PgPool, teacher_id: i32) -> Result<Te
     }
 }
 
-pub async fn post_new_course_db(pool: &PgPool, new_teacher: CreateTeacher) -> Result<Teacher, MyError> {
+#[instrument(level = "debug", skip_all, fields(actor = %actor.0))]
+pub async fn post_new_teacher_db(pool: &PgPool, new_teacher: CreateTeacher, actor: &Actor) -> Result<Teacher, MyError> {
+    let mut tx = pool.begin().await?;
+    let row = insert_teacher(&mut tx, new_teacher, actor).await?;
+    tx.commit().await?;
+    Ok(row)
+}
+
+// 在事务中插入老师并写入变更历史, 新增和注册共用
+async fn insert_teacher(tx: &mut Transaction<'_, Postgres>, new_teacher: CreateTeacher, actor: &Actor) -> Result<Teacher, MyError> {
     let row: Teacher = sqlx::query_as!(Teacher, r#"
         INSERT INTO teacher (name, picture_url, profile) VALUES ($1, $2, $3)
-        RETURNING id, name, picture_url, profile
+        RETURNING id, name, picture_url, profile, deleted_at, version, updated_at
     "#, new_teacher.name, new_teacher.picture_url, new_teacher.profile)
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await?;
+    insert_audit_db(tx, AuditRecord::teacher(AuditAction::Create, actor, None, Some(&row))).await?;
     Ok(row)
 }
 
-pub async fn update_teacher_details_db(pool: &PgPool, teacher_id: i32, update_teacher: UpdateTeacher) -> Result<Teacher, MyError> {
-    let current_teacher = sqlx::query_as!(Teacher, r#"
-        SELECT * FROM teacher WHERE id = $1
-    "#, teacher_id)
-    .fetch_one(pool)
+/**
+ * 注册, 老师和登录账号在同一个事务中创建
+ * 用户名已经被占用时返回 Conflict
+ */
+#[instrument(level = "debug", skip_all, fields(username = %username))]
+pub async fn register_teacher_db(
+    pool: &PgPool,
+    new_teacher: CreateTeacher,
+    username: String,
+    password_hash: String,
+    actor: &Actor,
+) -> Result<Teacher, MyError> {
+    let mut tx = pool.begin().await?;
+    let row = insert_teacher(&mut tx, new_teacher, actor).await?;
+    sqlx::query!(
+        "INSERT INTO teacher_account (teacher_id, username, password_hash) VALUES ($1, $2, $3)",
+        row.id,
+        username,
+        password_hash
+    )
+    .execute(&mut tx)
     .await
-    .map_err(|_err| MyError::NotFound("Course id not found".into()))?;
+    .map_err(|err| match err {
+        // 23505 是唯一约束冲突
+        sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some("23505") => {
+            MyError::Conflict("Username is already taken".into())
+        }
+        err => err.into(),
+    })?;
+    tx.commit().await?;
+    Ok(row)
+}
 
-    let name: String = if let Some(name) = update_teacher.name {
-        name
-    } else {
-        current_teacher.name.unwrap_or_default()
-    };
+// 按用户名查找账号, 老师已经删除的账号不能再登录
+#[instrument(level = "debug", skip_all, fields(username = %username))]
+pub async fn find_account_db(pool: &PgPool, username: &str) -> Result<Option<Account>, MyError> {
+    let row = sqlx::query_as!(
+        Account,
+        r#"
+        SELECT a.teacher_id, a.username, a.password_hash, a.role FROM teacher_account a
+        JOIN teacher t ON t.id = a.teacher_id
+        WHERE a.username = $1 AND t.deleted_at IS NULL"#,
+        username
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
 
-    let picture_url: String = if let Some(picture_url) = update_teacher.picture_url {
-        picture_url
-    } else {
-        current_teacher.picture_url.unwrap_or_default()
-    };
+// 按老师查找账号, 刷新 token 时用来取最新的角色
+#[instrument(level = "debug", skip_all, fields(teacher_id = teacher_id))]
+pub async fn get_account_db(pool: &PgPool, teacher_id: i32) -> Result<Option<Account>, MyError> {
+    let row = sqlx::query_as!(
+        Account,
+        r#"
+        SELECT a.teacher_id, a.username, a.password_hash, a.role FROM teacher_account a
+        JOIN teacher t ON t.id = a.teacher_id
+        WHERE a.teacher_id = $1 AND t.deleted_at IS NULL"#,
+        teacher_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
 
-    let profile: String = if let Some(profile) = update_teacher.profile {
-        profile
-    } else {
-        current_teacher.profile.unwrap_or_default()
-    };
+// 在事务中查出并锁住老师, 修改之前的值会写入变更历史, 同 course.rs 中的 lock_course
+async fn lock_teacher(tx: &mut Transaction<'_, Postgres>, teacher_id: i32, deleted: bool) -> Result<Option<Teacher>, MyError> {
+    let row = sqlx::query_as!(Teacher, r#"
+        SELECT id, name, picture_url, profile, deleted_at, version, updated_at FROM teacher
+        WHERE id = $1 AND (deleted_at IS NOT NULL) = $2
+        FOR UPDATE
+    "#, teacher_id, deleted)
+    .fetch_optional(tx)
+    .await?;
+    Ok(row)
+}
+
+#[instrument(level = "debug", skip_all, fields(teacher_id = teacher_id, actor = %actor.0))]
+pub async fn update_teacher_details_db(pool: &PgPool, teacher_id: i32, patch: TeacherPatch, check: &VersionCheck, actor: &Actor) -> Result<Teacher, MyError> {
+    let mut tx = pool.begin().await?;
+    let current_teacher = lock_teacher(&mut tx, teacher_id, false)
+        .await?
+        .ok_or_else(|| MyError::NotFound("Teacher is not found".into()))?;
+    check.check(current_teacher.version)?;
+    if patch.is_empty() {
+        return Ok(current_teacher);
+    }
 
+    // 和课程一样在一条 UPDATE 中完成, 没有传的字段不修改, 传了 null 时清空
     let current_row = sqlx::query_as!(Teacher, r#"
-        UPDATE teacher SET name = $1, picture_url = $2, profile = $3
-        WHERE id = $4
-        RETURNING id, name, picture_url, profile
-    "#, name, picture_url, profile, teacher_id)
-    .fetch_one(pool)
-    .await;
-
-    if let Ok(teacher) = current_row {
-        Ok(teacher)
-    } else {
-        Err(MyError::NotFound("Teacher is not found".into()))
+        UPDATE teacher SET
+            name = COALESCE($1, name),
+            picture_url = CASE WHEN $2 THEN $3 ELSE picture_url END,
+            profile = CASE WHEN $4 THEN $5 ELSE profile END,
+            version = version + 1
+        WHERE id = $6 AND deleted_at IS NULL
+        RETURNING id, name, picture_url, profile, deleted_at, version, updated_at
+    "#,
+        patch.name,
+        patch.picture_url.is_some(),
+        patch.picture_url.flatten(),
+        patch.profile.is_some(),
+        patch.profile.flatten(),
+        teacher_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    insert_audit_db(
+        &mut tx,
+        AuditRecord::teacher(AuditAction::Update, actor, Some(&current_teacher), Some(&current_row)),
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(current_row)
+}
+
+/**
+ * 删除老师, 软删除, 只记录删除时间
+ * 老师名下还没删除的课程按 policy 处理, 课程和老师的修改在同一个事务里
+ */
+#[instrument(level = "debug", skip_all, fields(teacher_id = teacher_id, policy = ?policy, actor = %actor.0))]
+pub async fn delete_teacher_db(pool: &PgPool, teacher_id: i32, policy: CoursePolicy, actor: &Actor) -> Result<(), MyError> {
+    let mut tx = pool.begin().await?;
+    let current = lock_teacher(&mut tx, teacher_id, false)
+        .await?
+        .ok_or_else(|| MyError::NotFound("Teacher is not found".into()))?;
+    // 锁住老师名下的课程, 防止处理期间又有课程被修改
+    let courses = sqlx::query_as!(
+        Course,
+        r#"SELECT id, teacher_id, name, time, description, format AS "format: CourseFormat", structure, duration, price,
+               language AS "language: CourseLanguage", level AS "level: CourseLevel", deleted_at, version, updated_at
+           FROM course WHERE teacher_id = $1 AND deleted_at IS NULL ORDER BY id FOR UPDATE"#,
+        teacher_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    let mut changed = match policy {
+        CoursePolicy::Reject if !courses.is_empty() => {
+            return Err(MyError::Conflict(format!(
+                "Teacher still has {} courses, delete them or use courses=cascade or courses=reassign",
+                courses.len()
+            )));
+        }
+        CoursePolicy::Reject => vec![],
+        CoursePolicy::Cascade => sqlx::query_as!(
+            Course,
+            r#"
+            UPDATE course SET deleted_at = now(), version = version + 1
+            WHERE teacher_id = $1 AND deleted_at IS NULL
+            RETURNING id, teacher_id, name, time, description, format AS "format: CourseFormat", structure, duration, price,
+                language AS "language: CourseLanguage", level AS "level: CourseLevel", deleted_at, version, updated_at"#,
+            teacher_id
+        )
+        .fetch_all(&mut tx)
+        .await?,
+        CoursePolicy::Reassign(new_teacher_id) => {
+            if new_teacher_id == teacher_id {
+                return Err(MyError::InvalidInput("Can not reassign courses to the teacher being deleted".into()));
+            }
+            lock_teacher(&mut tx, new_teacher_id, false)
+                .await?
+                .ok_or_else(|| MyError::InvalidInput("Teacher to reassign courses to is not found".into()))?;
+            sqlx::query_as!(
+                Course,
+                r#"
+                UPDATE course SET teacher_id = $2, version = version + 1
+                WHERE teacher_id = $1 AND deleted_at IS NULL
+                RETURNING id, teacher_id, name, time, description, format AS "format: CourseFormat", structure, duration, price,
+                    language AS "language: CourseLanguage", level AS "level: CourseLevel", deleted_at, version, updated_at"#,
+                teacher_id,
+                new_teacher_id
+            )
+            .fetch_all(&mut tx)
+            .await?
+        }
+    };
+    // RETURNING 不保证顺序, 按 id 排序之后和 courses 一一对应
+    changed.sort_by_key(|course| course.id);
+    // 课程的变化同样记入变更历史, 级联删除记为 delete, 转给其他老师记为 update, 原来的老师名下再记一条 move
+    let action = match policy {
+        CoursePolicy::Cascade => AuditAction::Delete,
+        _ => AuditAction::Update,
+    };
+    for (before, after) in courses.iter().zip(changed.iter()) {
+        insert_audit_db(&mut tx, AuditRecord::course(action, actor, Some(before), Some(after))).await?;
+        if before.teacher_id != after.teacher_id {
+            insert_audit_db(&mut tx, AuditRecord::course_moved(actor, before, after)).await?;
+        }
     }
+
+    let teacher_row = sqlx::query_as!(Teacher, r#"
+        UPDATE teacher SET deleted_at = now(), version = version + 1 WHERE id = $1
+        RETURNING id, name, picture_url, profile, deleted_at, version, updated_at
+    "#, teacher_id)
+    .fetch_one(&mut tx)
+    .await?;
+    insert_audit_db(&mut tx, AuditRecord::teacher(AuditAction::Delete, actor, Some(&current), Some(&teacher_row))).await?;
+    tx.commit().await?;
+    Ok(())
 }
 
-pub async fn delete_teacher_db(pool: &PgPool, teacher_id: i32) -> Result<String, MyError> {
-    let teacher_row = sqlx::query!(r#"
-        DELETE FROM teacher WHERE id = $1
+#[instrument(level = "debug", skip_all, fields(teacher_id = teacher_id, actor = %actor.0))]
+pub async fn restore_teacher_db(pool: &PgPool, teacher_id: i32, actor: &Actor) -> Result<Teacher, MyError> {
+    let mut tx = pool.begin().await?;
+    let current = lock_teacher(&mut tx, teacher_id, true)
+        .await?
+        .ok_or_else(|| MyError::NotFound("Deleted teacher is not found".into()))?;
+    let row = sqlx::query_as!(Teacher, r#"
+        UPDATE teacher SET deleted_at = NULL, version = version + 1
+        WHERE id = $1
+        RETURNING id, name, picture_url, profile, deleted_at, version, updated_at
     "#, teacher_id)
-    .execute(pool)
-    .await
-    .map(|_err| MyError::DBError("Unable to delete teacher".into()));
-    Ok(format!("Delete {:?} record", teacher_row))
+    .fetch_one(&mut tx)
+    .await?;
+    insert_audit_db(&mut tx, AuditRecord::teacher(AuditAction::Restore, actor, Some(&current), Some(&row))).await?;
+    tx.commit().await?;
+    Ok(row)
+}
+
+// 彻底删除软删除时间早于 older_than 之前的老师, 返回删除的条数
+#[instrument(level = "debug", skip_all)]
+pub async fn purge_deleted_teachers_db(pool: &PgPool, older_than: Duration) -> Result<u64, MyError> {
+    let mut tx = pool.begin().await?;
+    let rows = sqlx::query_as!(
+        Teacher,
+        r#"
+        DELETE FROM teacher WHERE deleted_at < now() - make_interval(secs => $1)
+        RETURNING id, name, picture_url, profile, deleted_at, version, updated_at"#,
+        older_than.num_seconds() as f64
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    for teacher in &rows {
+        insert_audit_db(&mut tx, AuditRecord::teacher(AuditAction::Purge, &Actor::system(), Some(teacher), None)).await?;
+    }
+    tx.commit().await?;
+    Ok(rows.len() as u64)
 }
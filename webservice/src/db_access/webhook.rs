@@ -0,0 +1,279 @@
+use crate::errors::MyError;
+use crate::models::audit::{Actor, AuditEntry};
+use crate::models::pagination::{Page, PageParams};
+use crate::models::webhook::{
+    AttemptOutcome, DeliveryDetail, DeliveryStatus, DueDelivery, NewWebhook, NextAttempt, Webhook, WebhookAttempt,
+    WebhookDelivery,
+};
+use chrono::Duration;
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+#[instrument(level = "debug", skip_all, fields(teacher_id = ?new_webhook.teacher_id))]
+pub async fn create_webhook_db(pool: &PgPool, new_webhook: NewWebhook, actor: &Actor) -> Result<Webhook, MyError> {
+    let row = sqlx::query_as!(
+        Webhook,
+        r#"
+        INSERT INTO webhook (teacher_id, url, secret, events, created_by)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, teacher_id, url, events, created_by, created_at"#,
+        new_webhook.teacher_id,
+        new_webhook.url,
+        new_webhook.secret,
+        &new_webhook.events,
+        actor.0
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row)
+}
+
+/**
+ * webhook 列表, teacher_id 为 None 时返回所有的 (管理员), 否则只返回这位老师的
+ */
+#[instrument(level = "debug", skip(pool))]
+pub async fn list_webhooks_db(pool: &PgPool, teacher_id: Option<i32>, page: &PageParams) -> Result<Page<Webhook>, MyError> {
+    let rows = sqlx::query_as!(
+        Webhook,
+        r#"
+        SELECT id, teacher_id, url, events, created_by, created_at FROM webhook
+        WHERE ($1::INT IS NULL OR teacher_id = $1) AND id > $2
+        ORDER BY id
+        LIMIT $3 OFFSET $4"#,
+        teacher_id,
+        page.after_id,
+        page.limit + 1,
+        page.offset
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "total!" FROM webhook WHERE ($1::INT IS NULL OR teacher_id = $1)"#,
+        teacher_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Page::from_rows(rows, total, page, |webhook| webhook.id))
+}
+
+#[instrument(level = "debug", skip(pool))]
+pub async fn get_webhook_db(pool: &PgPool, id: i32) -> Result<Webhook, MyError> {
+    sqlx::query_as!(
+        Webhook,
+        r#"SELECT id, teacher_id, url, events, created_by, created_at FROM webhook WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| MyError::NotFound("Webhook is not found".into()))
+}
+
+// 删除 webhook 时投递记录一起删除 (ON DELETE CASCADE)
+#[instrument(level = "debug", skip(pool))]
+pub async fn delete_webhook_db(pool: &PgPool, id: i32) -> Result<(), MyError> {
+    let result = sqlx::query!("DELETE FROM webhook WHERE id = $1", id).execute(pool).await?;
+    match result.rows_affected() {
+        0 => Err(MyError::NotFound("Webhook is not found".into())),
+        _ => Ok(()),
+    }
+}
+
+#[instrument(level = "debug", skip(pool))]
+pub async fn list_deliveries_db(
+    pool: &PgPool,
+    webhook_id: i32,
+    status: Option<DeliveryStatus>,
+    page: &PageParams,
+) -> Result<Page<WebhookDelivery>, MyError> {
+    let status = status.map(|status| status.as_str());
+    let rows = sqlx::query_as!(
+        WebhookDelivery,
+        r#"
+        SELECT * FROM webhook_delivery
+        WHERE webhook_id = $1 AND ($2::TEXT IS NULL OR status = $2) AND id > $3
+        ORDER BY id
+        LIMIT $4 OFFSET $5"#,
+        webhook_id,
+        status,
+        page.after_id,
+        page.limit + 1,
+        page.offset
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "total!" FROM webhook_delivery WHERE webhook_id = $1 AND ($2::TEXT IS NULL OR status = $2)"#,
+        webhook_id,
+        status
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Page::from_rows(rows, total, page, |delivery| delivery.id))
+}
+
+// 一次投递的详情, 包括请求体和每一次请求的结果
+#[instrument(level = "debug", skip(pool))]
+pub async fn get_delivery_db(pool: &PgPool, webhook_id: i32, delivery_id: i32) -> Result<DeliveryDetail, MyError> {
+    let delivery = sqlx::query_as!(
+        WebhookDelivery,
+        r#"SELECT * FROM webhook_delivery WHERE id = $1 AND webhook_id = $2"#,
+        delivery_id,
+        webhook_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| MyError::NotFound("Webhook delivery is not found".into()))?;
+
+    let event = sqlx::query_as!(AuditEntry, r#"SELECT * FROM audit_log WHERE id = $1"#, delivery.event_id)
+        .fetch_one(pool)
+        .await?;
+
+    let attempts = sqlx::query_as!(
+        WebhookAttempt,
+        r#"SELECT * FROM webhook_attempt WHERE delivery_id = $1 ORDER BY id"#,
+        delivery_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(DeliveryDetail {
+        payload: crate::models::webhook::payload(&delivery.event_type, &event),
+        delivery,
+        attempts,
+    })
+}
+
+/**
+ * 重新投递, 死信或者已经投递成功的都可以, 重试次数从头开始计算
+ * ? 请求体中的事件 id 不变, 接收方可以据此去重
+ */
+#[instrument(level = "debug", skip(pool))]
+pub async fn redeliver_db(pool: &PgPool, webhook_id: i32, delivery_id: i32) -> Result<WebhookDelivery, MyError> {
+    sqlx::query_as!(
+        WebhookDelivery,
+        r#"
+        UPDATE webhook_delivery SET status = 'pending', attempts = 0, next_attempt_at = now(), delivered_at = NULL
+        WHERE id = $1 AND webhook_id = $2
+        RETURNING *"#,
+        delivery_id,
+        webhook_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| MyError::NotFound("Webhook delivery is not found".into()))
+}
+
+/**
+ * 领取到期的投递, 同时把 next_attempt_at 推迟 lease, 其他实例在这段时间内不会重复领取
+ * ? FOR UPDATE SKIP LOCKED: 多个实例同时领取时互不等待, 也不会领到同一条
+ * ? 发送之后由 record_attempt_db 更新状态, 实例在发送中途退出时, lease 到期之后会被重新领取
+ */
+#[instrument(level = "debug", skip(pool))]
+pub async fn claim_due_deliveries_db(pool: &PgPool, limit: i64, lease: Duration) -> Result<Vec<DueDelivery>, MyError> {
+    let rows = sqlx::query!(
+        r#"
+        WITH due AS (
+            UPDATE webhook_delivery SET next_attempt_at = now() + make_interval(secs => $2)
+            WHERE id IN (
+                SELECT id FROM webhook_delivery
+                WHERE status = 'pending' AND next_attempt_at <= now()
+                ORDER BY next_attempt_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, webhook_id, event_id, event_type, attempts
+        )
+        SELECT due.id, due.attempts, due.event_type, w.url, w.secret,
+            a.id AS event_id, a.entity_type, a.entity_id, a.teacher_id, a.action, a.actor, a.before, a.after, a.created_at, a.commit_seq
+        FROM due
+        JOIN webhook w ON w.id = due.webhook_id
+        JOIN audit_log a ON a.id = due.event_id
+        ORDER BY due.id"#,
+        limit,
+        lease.num_seconds() as f64
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| DueDelivery {
+            id: row.id,
+            url: row.url,
+            secret: row.secret,
+            attempts: row.attempts,
+            event_type: row.event_type,
+            event: AuditEntry {
+                id: row.event_id,
+                entity_type: row.entity_type,
+                entity_id: row.entity_id,
+                teacher_id: row.teacher_id,
+                action: row.action,
+                actor: row.actor,
+                before: row.before,
+                after: row.after,
+                created_at: row.created_at,
+                commit_seq: row.commit_seq,
+            },
+        })
+        .collect())
+}
+
+// 记录一次请求的结果, 并按 next 更新投递的状态
+#[instrument(level = "debug", skip(pool, outcome))]
+pub async fn record_attempt_db(
+    pool: &PgPool,
+    delivery_id: i32,
+    outcome: AttemptOutcome,
+    next: NextAttempt,
+) -> Result<(), MyError> {
+    let (status, next_attempt_at) = match next {
+        NextAttempt::Delivered => (DeliveryStatus::Delivered, None),
+        NextAttempt::RetryAt(at) => (DeliveryStatus::Pending, Some(at)),
+        NextAttempt::Dead => (DeliveryStatus::Dead, None),
+    };
+    let mut tx = pool.begin().await?;
+    let attempt = sqlx::query_scalar!(
+        r#"
+        UPDATE webhook_delivery SET
+            status = $2::VARCHAR,
+            attempts = attempts + 1,
+            next_attempt_at = COALESCE($3, next_attempt_at),
+            last_status_code = $4,
+            last_error = $5,
+            delivered_at = CASE WHEN $2::VARCHAR = 'delivered' THEN now() END
+        WHERE id = $1
+        RETURNING attempts"#,
+        delivery_id,
+        status.as_str(),
+        next_attempt_at,
+        outcome.status_code,
+        outcome.error
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    // 发送期间 webhook 被删除了, 不需要再记录
+    let attempt = match attempt {
+        Some(attempt) => attempt,
+        None => return Ok(()),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_attempt (delivery_id, attempt, status_code, error, duration_ms)
+        VALUES ($1, $2, $3, $4, $5)"#,
+        delivery_id,
+        attempt,
+        outcome.status_code,
+        outcome.error,
+        outcome.duration_ms
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
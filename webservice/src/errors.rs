@@ -6,50 +6,177 @@
 // 4. 在 handler 里返回自定义错误类型
 // 5. Actix 会把错误转换为 HTTP 响应
 
-use actix_web::{error, http::StatusCode, HttpResponse, Result};
-use serde::Serialize;
+use actix_web::{error, http::header, http::StatusCode, HttpResponse, Result};
+use crate::models::catalog::RowError;
+use serde::{Deserialize, Serialize};
 use sqlx::error::Error as SQLxError;
+use std::collections::BTreeMap;
 use std::fmt;
+use validator::{ValidationError, ValidationErrors};
+
+// 每个字段的校验错误, 字段名 -> 错误信息列表, 按字段名排序
+pub type FieldErrors = BTreeMap<String, Vec<String>>;
+
+// 错误响应的 Content-Type, 见 RFC 7807
+pub const PROBLEM_JSON: &str = "application/problem+json";
 
 // 默认为 MyError 实现 Debug 和 Serialize
 #[derive(Debug, Serialize)]
 // 自定义错误类型
 pub enum MyError {
     // 下面三个变体都可以存一个字符串
+    // ? DBError 和 ActixError 中的字符串只写日志, 不会返回给前端
     DBError(String),
     ActixError(String),
     NotFound(String),
     InvalidInput(String), // 前端非法传递
+    PreconditionFailed(String), // If-Match 中的版本已经过期, 记录被别人修改过
+    Conflict(String), // 和现有数据冲突, 例如删除还有课程的老师
+    Unauthorized(String), // 没有登录, 或者 token 不合法
+    Forbidden(String), // 已经登录, 但是没有权限修改别的老师的数据
+    ValidationFailed(FieldErrors), // 请求体的字段校验失败, 按字段列出错误
+    InvalidRows(Vec<RowError>), // 批量导入时有不合法的行, 按行列出错误
+    RateLimited(u64), // 请求太频繁, 需要等待的秒数, 见 rate_limit.rs
 }
 
-#[derive(Debug, Serialize)]
-pub struct MyErrorResponse {
-    // 传递给用户的错误响应内容, 包含具体的错误消息
-    error_message: String,
+// 字段或者行的错误明细, 放在 problem 的 errors 中
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ErrorDetails {
+    Fields(FieldErrors),
+    Rows(Vec<RowError>),
+}
+
+/**
+ * 传递给用户的错误响应内容, 格式是 RFC 7807 的 problem+json
+ * ? code 是稳定的错误码, 前端按 code 判断错误类型, 不要去匹配 detail 中的文字
+ * ? request_id 和响应头 X-Request-Id 相同, 排查问题时用它在日志中找到对应的请求
+ */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub code: String,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    // 只有字段校验失败或者导入的行不合法时才有
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<ErrorDetails>,
+    // 被限流时多少秒之后可以重试, 和响应头 Retry-After 相同
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+impl ProblemDetails {
+    pub fn new(status: StatusCode, code: &str, detail: String) -> Self {
+        ProblemDetails {
+            problem_type: format!("urn:problem-type:{}", code),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            code: code.to_string(),
+            detail,
+            instance: None,
+            request_id: None,
+            errors: None,
+            retry_after: None,
+        }
+    }
+
+    // 请求的路径和 id 由中间件填上, 直接调用 handler 时 (例如测试中) 没有
+    pub fn with_request(mut self, request_id: Option<&str>, instance: Option<&str>) -> Self {
+        self.request_id = request_id.map(str::to_string);
+        self.instance = instance.map(str::to_string);
+        self
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    pub fn to_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let mut response = HttpResponse::build(status);
+        // 401 需要告诉客户端使用哪种认证方式
+        if status == StatusCode::UNAUTHORIZED {
+            response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        if let Some(seconds) = self.retry_after {
+            response.insert_header((header::RETRY_AFTER, seconds));
+        }
+        response.content_type(PROBLEM_JSON).json(self)
+    }
 }
 
-// 为 MyError实现一些方法, 永远将 MyError 转化为 MyErrorResponse
+// 为 MyError实现一些方法, 永远将 MyError 转化为 ProblemDetails
 impl MyError {
-    fn error_response(&self) -> String {
+    // 稳定的错误码, 已经发布的值不要修改, 前端和调用方依赖它们
+    pub fn code(&self) -> &'static str {
         match self {
-            MyError::DBError(msg) => {
-                println!("Database error occurred: {:?}", msg);
-                "Database error".into()
-            },
-            MyError::ActixError(msg) => {
-                println!("Server error occurred: {:?}", msg);
-                "Internal Server error".into()
-            },
-            MyError::NotFound(msg) => {
-                println!("Not found error occurred: {:?}", msg);
-                msg.into()
-            },
-            MyError::InvalidInput(msg) => {
-                println!("Invalid parameters received: {:?}", msg);
-                msg.into()
-            }
+            MyError::DBError(_msg) => "database_error",
+            MyError::ActixError(_msg) => "internal_error",
+            MyError::NotFound(_msg) => "not_found",
+            MyError::InvalidInput(_msg) => "invalid_input",
+            MyError::PreconditionFailed(_msg) => "precondition_failed",
+            MyError::Conflict(_msg) => "conflict",
+            MyError::Unauthorized(_msg) => "unauthorized",
+            MyError::Forbidden(_msg) => "forbidden",
+            MyError::ValidationFailed(_errors) => "validation_failed",
+            MyError::InvalidRows(_rows) => "invalid_rows",
+            MyError::RateLimited(_seconds) => "rate_limited",
+        }
+    }
+
+    // 返回给用户的说明, 服务器内部的错误只返回笼统的说明, 具体原因见日志
+    pub fn detail(&self) -> String {
+        match self {
+            MyError::DBError(_msg) => "A database error occurred".into(),
+            MyError::ActixError(_msg) => "An internal server error occurred".into(),
+            MyError::ValidationFailed(_errors) => "Validation failed".into(),
+            MyError::InvalidRows(rows) => format!("{} rows are invalid, nothing was imported", rows.len()),
+            MyError::RateLimited(seconds) => format!("Too many requests, retry after {} seconds", seconds),
+            MyError::NotFound(msg)
+            | MyError::InvalidInput(msg)
+            | MyError::PreconditionFailed(msg)
+            | MyError::Conflict(msg)
+            | MyError::Unauthorized(msg)
+            | MyError::Forbidden(msg) => msg.clone(),
         }
     }
+
+    pub fn problem(&self) -> ProblemDetails {
+        let mut problem = ProblemDetails::new(error::ResponseError::status_code(self), self.code(), self.detail());
+        problem.errors = match self {
+            MyError::ValidationFailed(errors) => Some(ErrorDetails::Fields(errors.clone())),
+            MyError::InvalidRows(rows) => Some(ErrorDetails::Rows(rows.clone())),
+            _ => None,
+        };
+        if let MyError::RateLimited(seconds) = self {
+            problem.retry_after = Some(*seconds);
+        }
+        problem
+    }
+}
+
+// 任意 actix 错误的错误码, 不是 MyError 的错误 (例如 actix 解析路径参数失败) 按状态码给一个
+pub fn error_code(err: &error::Error) -> &'static str {
+    if let Some(err) = err.as_error::<MyError>() {
+        return err.code();
+    }
+    match err.as_response_error().status_code() {
+        StatusCode::BAD_REQUEST => "invalid_input",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        status if status.is_server_error() => "internal_error",
+        _ => "http_error",
+    }
 }
 
 // 为 MyError 实现 ResponseError 这个trait, 这个 trait 就两个方法, 一个是 status_code, 另一个是 error_response
@@ -57,6 +184,7 @@ impl MyError {
 // 只要发生了错误, actix就可以将错误信息转换为 http响应发送给客户端
 // 但在实现  error::ResponseError 这个 trait 时候, 要求必须实现 Debug 和 Display 这两个 trait
 // Debug 针对 MyError有一个默认的实现
+// ? 日志和 request_id 由 request_id.rs 中的中间件处理, 它能拿到请求的信息
 impl error::ResponseError for MyError {
     fn status_code(&self) -> StatusCode {
         match self {
@@ -64,23 +192,52 @@ impl error::ResponseError for MyError {
             MyError::DBError(_msg) | MyError::ActixError(_msg) => StatusCode::INTERNAL_SERVER_ERROR,
             MyError::NotFound(_msg) => StatusCode::NOT_FOUND,
             MyError::InvalidInput(_msg) => StatusCode::BAD_REQUEST,
+            MyError::PreconditionFailed(_msg) => StatusCode::PRECONDITION_FAILED,
+            MyError::Conflict(_msg) => StatusCode::CONFLICT,
+            MyError::Unauthorized(_msg) => StatusCode::UNAUTHORIZED,
+            MyError::Forbidden(_msg) => StatusCode::FORBIDDEN,
+            MyError::ValidationFailed(_errors) => StatusCode::BAD_REQUEST,
+            MyError::InvalidRows(_rows) => StatusCode::BAD_REQUEST,
+            MyError::RateLimited(_seconds) => StatusCode::TOO_MANY_REQUESTS,
         }
     }
     fn error_response(&self) -> HttpResponse {
-        // build方法传入status_code() 调用结果, 返回一个 HttpResponse, 然后继续调用 json 方法, 形成一段自定义的错误
-        HttpResponse::build(self.status_code()).json(MyErrorResponse {
-            error_message: self.error_response(),
-        })
+        self.problem().to_response()
     }
 }
 
 // 手动实现 display
 impl fmt::Display for  MyError {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{}", self)
+        // ? 直接 write!(f, "{}", self) 会再次调用 fmt, 无限递归, 这里输出内部的错误信息
+        match self {
+            MyError::DBError(msg)
+            | MyError::ActixError(msg)
+            | MyError::NotFound(msg)
+            | MyError::InvalidInput(msg)
+            | MyError::PreconditionFailed(msg)
+            | MyError::Conflict(msg)
+            | MyError::Unauthorized(msg)
+            | MyError::Forbidden(msg) => write!(f, "{}", msg),
+            MyError::RateLimited(seconds) => write!(f, "rate limited for {} seconds", seconds),
+            MyError::InvalidRows(rows) => {
+                let lines: Vec<String> = rows.iter().map(|row| format!("line {}: {}", row.line, row.message)).collect();
+                write!(f, "{}", lines.join("; "))
+            }
+            MyError::ValidationFailed(errors) => {
+                let fields: Vec<String> = errors
+                    .iter()
+                    .map(|(field, messages)| format!("{}: {}", field, messages.join(", ")))
+                    .collect();
+                write!(f, "{}", fields.join("; "))
+            }
+        }
     }
 }
 
+// 导出课程时, 流里的错误需要实现 std::error::Error
+impl std::error::Error for MyError {}
+
 // 转换错误信息, 使用 ? 即可
 impl From<actix_web::error::Error> for MyError {
     fn from(err: actix_web::error::Error) -> Self {
@@ -88,6 +245,41 @@ impl From<actix_web::error::Error> for MyError {
     }
 }
 
+// 校验规则上没有写 message 时, 根据规则和参数生成错误信息
+fn describe(err: &ValidationError) -> String {
+    if let Some(message) = &err.message {
+        return message.to_string();
+    }
+    // range 的参数是浮点数, 整数时去掉小数部分
+    let param = |name: &str| {
+        err.params.get(name).map(|value| match value.as_f64() {
+            Some(number) if number.fract() == 0.0 => (number as i64).to_string(),
+            _ => value.to_string(),
+        })
+    };
+    match (err.code.as_ref(), param("min"), param("max")) {
+        ("length", Some(min), Some(max)) => format!("length must be between {} and {} characters", min, max),
+        ("length", None, Some(max)) => format!("must not be longer than {} characters", max),
+        ("length", Some(min), None) => format!("must be at least {} characters", min),
+        ("range", Some(min), Some(max)) => format!("must be between {} and {}", min, max),
+        ("range", None, Some(max)) => format!("must not be greater than {}", max),
+        ("range", Some(min), None) => format!("must not be less than {}", min),
+        (code, _, _) => format!("is invalid ({})", code),
+    }
+}
+
+impl From<ValidationErrors> for MyError {
+    fn from(errors: ValidationErrors) -> Self {
+        MyError::ValidationFailed(
+            errors
+                .field_errors()
+                .into_iter()
+                .map(|(field, errors)| (field.to_string(), errors.iter().map(describe).collect()))
+                .collect(),
+        )
+    }
+}
+
 impl From<SQLxError> for MyError {
     fn from(err: SQLxError) -> Self {
         MyError::DBError(err.to_string())
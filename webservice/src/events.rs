@@ -0,0 +1,238 @@
+// 课程变更事件, GET /courses/{teacher_id}/events 以 SSE (text/event-stream) 推送
+// 事件来自变更历史 (audit_log), 事件 id 就是变更记录的 id, 事件类型是 created, updated 或者 deleted
+// ? 事件中有完整的课程字段和操作人, 只有老师自己和管理员可以订阅, 和变更历史一样
+// ? 每个 SSE 连接记住已经推送到的位置, 收到通知之后查询这个位置之后的变更, 所以通知丢失或者重复都不会影响推送的内容
+// ? postgres: audit_log 上的触发器 NOTIFY course_events, 每个实例用一个 LISTEN 连接接收, 再在进程内广播给所有 SSE 连接
+// ? 内存实现: 写入变更历史时直接在进程内广播
+// ? 客户端断开之后 EventSource 会带着 Last-Event-ID 自动重连, 从断开的地方继续推送
+// ? audit_log.id 按写入的顺序分配, 但事务提交的顺序可能不同, 所以按提交时分配的 commit_seq 推送 (见 get_course_events_db)
+use crate::models::audit::{AuditEntry, EventPosition};
+use crate::state::AppState;
+use actix_web::web::{self, Bytes};
+use futures_util::future::{select, Either};
+use futures_util::stream::{self, Stream};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::postgres::{PgListener, PgPool};
+use std::collections::VecDeque;
+use std::pin::pin;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// 和迁移中 notify_course_event() 使用的通道名称一致
+pub const COURSE_EVENTS_CHANNEL: &str = "course_events";
+// 一次从变更历史中读取的条数
+const EVENT_BATCH: i64 = 100;
+// 没有事件时定时发送注释行, 避免代理因为空闲断开连接
+const HEARTBEAT: Duration = Duration::from_secs(15);
+// 告诉 EventSource 断开之后等待多久重连, 单位毫秒
+const RETRY_MILLIS: u64 = 3000;
+// 进程内广播的缓冲, 接收方落后太多时会收到 Lagged, 按所有老师都有变更处理
+const CHANNEL_CAPACITY: usize = 256;
+
+// 进程内广播的通知, 只说明哪位老师的课程有变更, 具体内容由每个连接自己查询
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CourseChange {
+    Teacher(i32),
+    // LISTEN 连接断开过, 期间的通知已经丢失, 所有连接都需要重新查询
+    All,
+}
+
+#[derive(Debug, Clone)]
+pub struct CourseChanges {
+    sender: broadcast::Sender<CourseChange>,
+}
+
+impl Default for CourseChanges {
+    fn default() -> Self {
+        let (sender, _receiver) = broadcast::channel(CHANNEL_CAPACITY);
+        CourseChanges { sender }
+    }
+}
+
+impl CourseChanges {
+    pub fn new() -> Self {
+        CourseChanges::default()
+    }
+
+    // 没有订阅者时 send 返回错误, 可以忽略
+    pub fn notify(&self, change: CourseChange) {
+        let _ = self.sender.send(change);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<CourseChange> {
+        self.sender.subscribe()
+    }
+}
+
+/**
+ * 接收 postgres 的 NOTIFY, 转发给本实例的所有 SSE 连接, 在 main 中 spawn
+ * ? 连接断开时 sqlx 会在下一次接收时自动重连, 断开期间的通知会丢失, 所以重连后通知所有连接重新查询
+ * ? 连接池关闭 (停机) 时退出
+ */
+pub async fn listen_for_course_changes(pool: PgPool, changes: CourseChanges) {
+    loop {
+        let mut listener = match PgListener::connect_with(&pool).await {
+            Ok(listener) => listener,
+            Err(sqlx::Error::PoolClosed) => return,
+            Err(err) => {
+                tracing::warn!(error = %err, "unable to connect course event listener, retrying");
+                actix_rt::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        if let Err(err) = listener.listen(COURSE_EVENTS_CHANNEL).await {
+            tracing::warn!(error = %err, "unable to LISTEN {}, retrying", COURSE_EVENTS_CHANNEL);
+            actix_rt::time::sleep(Duration::from_secs(5)).await;
+            continue;
+        }
+        // 开始监听之前的变更同样可能没有收到
+        changes.notify(CourseChange::All);
+        loop {
+            match listener.try_recv().await {
+                Ok(Some(notification)) => match notification.payload().parse() {
+                    Ok(teacher_id) => changes.notify(CourseChange::Teacher(teacher_id)),
+                    Err(_err) => tracing::warn!(payload = notification.payload(), "ignoring malformed course event notification"),
+                },
+                Ok(None) => {
+                    tracing::warn!("course event listener lost its connection, reconnecting");
+                    changes.notify(CourseChange::All);
+                }
+                Err(sqlx::Error::PoolClosed) => return,
+                Err(err) => {
+                    tracing::warn!(error = %err, "course event listener failed, reconnecting");
+                    break;
+                }
+            }
+        }
+        actix_rt::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+// 事件的 data 部分
+#[derive(Serialize, Debug)]
+struct CourseEvent<'a> {
+    id: i32,
+    course_id: i32,
+    teacher_id: i32,
+    actor: &'a str,
+    // 变更之后的课程, 删除时是删除之前的课程 (带有 deleted_at)
+    course: Option<&'a Value>,
+    created_at: chrono::NaiveDateTime,
+}
+
+// 变更历史中的操作对应的事件类型
+// ? 从回收站恢复的课程对客户端来说是新出现的课程, 所以也是 created
+// ? 课程转给其他老师时, 原来的老师收到 move, 对这位老师来说课程不在了, 所以是 deleted
+fn event_type(action: &str) -> &'static str {
+    match action {
+        "create" | "restore" => "created",
+        "delete" | "move" => "deleted",
+        _ => "updated",
+    }
+}
+
+// 一条 SSE 事件, 由 id, event 和 data 三行组成, 空行结束
+// ? serde_json 输出的 JSON 没有换行, 可以直接放在一行 data 中
+pub fn format_event(entry: &AuditEntry) -> Bytes {
+    let data = CourseEvent {
+        id: entry.id,
+        course_id: entry.entity_id,
+        teacher_id: entry.teacher_id,
+        actor: &entry.actor,
+        course: entry.after.as_ref().or(entry.before.as_ref()),
+        created_at: entry.created_at,
+    };
+    let data = serde_json::to_string(&data).unwrap_or_else(|_err| "{}".into());
+    Bytes::from(format!("id: {}\nevent: {}\ndata: {}\n\n", entry.id, event_type(&entry.action), data))
+}
+
+// 一个 SSE 连接的状态
+struct EventCursor {
+    app_state: web::Data<AppState>,
+    teacher_id: i32,
+    // 已经推送到的位置
+    position: EventPosition,
+    changes: broadcast::Receiver<CourseChange>,
+    pending: VecDeque<AuditEntry>,
+    started: bool,
+}
+
+impl EventCursor {
+    // 返回 None 时结束响应, 客户端会自动重连
+    async fn next_chunk(&mut self) -> Option<Bytes> {
+        if !self.started {
+            self.started = true;
+            return Some(Bytes::from(format!("retry: {}\n\n", RETRY_MILLIS)));
+        }
+        loop {
+            // 停机时主动断开, 客户端会重连到其他实例, 而不是等到停机超时被强制断开
+            if self.app_state.shutdown.is_draining() {
+                return None;
+            }
+            if let Some(entry) = self.pending.pop_front() {
+                self.position = EventPosition::from(&entry);
+                return Some(format_event(&entry));
+            }
+            match self.app_state.courses.get_course_events(self.teacher_id, self.position, EVENT_BATCH).await {
+                Ok(entries) if !entries.is_empty() => {
+                    self.pending.extend(entries);
+                    continue;
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    tracing::warn!(teacher_id = self.teacher_id, error = ?err, "unable to read course events, closing stream");
+                    return None;
+                }
+            }
+            // 等待变更, 心跳或者开始停机
+            let app_state = self.app_state.clone();
+            let draining = pin!(app_state.shutdown.draining());
+            let heartbeat = pin!(actix_rt::time::sleep(HEARTBEAT));
+            let change = pin!(self.wait_for_change());
+            let changed = match select(select(change, draining), heartbeat).await {
+                Either::Left((Either::Left((changed, _)), _)) => changed,
+                Either::Left(_) => false,
+                Either::Right(_) => return Some(Bytes::from_static(b": keep-alive\n\n")),
+            };
+            if !changed {
+                return None;
+            }
+        }
+    }
+
+    // 等到这位老师的课程有变更, 广播关闭时返回 false
+    async fn wait_for_change(&mut self) -> bool {
+        loop {
+            match self.changes.recv().await {
+                Ok(CourseChange::Teacher(teacher_id)) if teacher_id != self.teacher_id => continue,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => return true,
+                Err(broadcast::error::RecvError::Closed) => return false,
+            }
+        }
+    }
+}
+
+/**
+ * 老师的课程变更事件流, 从 position 之后开始推送
+ * ? changes 需要在查询 position 之前订阅, 否则两者之间的变更不会收到通知
+ */
+pub fn course_event_stream(
+    app_state: web::Data<AppState>,
+    teacher_id: i32,
+    position: EventPosition,
+    changes: broadcast::Receiver<CourseChange>,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let cursor = EventCursor {
+        app_state,
+        teacher_id,
+        position,
+        changes,
+        pending: VecDeque::new(),
+        started: false,
+    };
+    stream::unfold(cursor, |mut cursor| async move {
+        let chunk = cursor.next_chunk().await?;
+        Some((Ok(chunk), cursor))
+    })
+}
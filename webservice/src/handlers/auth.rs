@@ -0,0 +1,169 @@
+use crate::auth::{hash_password, verify_password, Principal};
+use crate::errors::MyError;
+use crate::models::audit::Actor;
+use crate::models::auth::{LoginRequest, NewAccount, RefreshRequest, RegisterTeacher};
+use crate::models::version::etag;
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+use std::sync::OnceLock;
+
+// 用户名不存在时也校验一次密码, 让两种失败的耗时差不多, 不能据此猜出哪些用户名已经注册
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password("dummy password").unwrap_or_default())
+}
+
+// 哈希比较耗 CPU, 放到线程池中执行, 不阻塞 actix 的 worker
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Result<T, MyError> {
+    web::block(f).await.map_err(|err| MyError::ActixError(err.to_string()))
+}
+
+// * 注册老师和登录账号, 不需要登录
+pub async fn register(
+    app_state: web::Data<AppState>,
+    register: web::Json<RegisterTeacher>,
+    actor: Actor,
+) -> Result<HttpResponse, MyError> {
+    let account = NewAccount::try_from(register.into_inner())?;
+    let password = account.password;
+    let password_hash = blocking(move || hash_password(&password)).await??;
+    app_state
+        .teachers
+        .register_teacher(account.teacher, account.username, password_hash, &actor)
+        .await
+        .map(|teacher| HttpResponse::Ok().insert_header(etag(teacher.version)).json(teacher))
+}
+
+// * 登录, 用户名和密码正确时签发 access token 和 refresh token
+pub async fn login(
+    app_state: web::Data<AppState>,
+    login: web::Json<LoginRequest>,
+) -> Result<HttpResponse, MyError> {
+    let LoginRequest { username, password } = login.into_inner();
+    let account = app_state.teachers.find_account(username.trim().to_string()).await?;
+    let (principal, password_hash) = match account {
+        Some(account) => {
+            let principal = Principal { teacher_id: account.teacher_id, role: account.role() };
+            (Some(principal), account.password_hash)
+        }
+        None => (None, dummy_hash().to_string()),
+    };
+    let verified = blocking(move || verify_password(&password, &password_hash)).await?;
+    match principal {
+        Some(principal) if verified => Ok(HttpResponse::Ok().json(app_state.tokens.issue(principal)?)),
+        // 不区分用户名不存在和密码错误
+        _ => Err(MyError::Unauthorized("Invalid username or password".into())),
+    }
+}
+
+// * 用 refresh token 换一对新的 token
+pub async fn refresh(
+    app_state: web::Data<AppState>,
+    refresh: web::Json<RefreshRequest>,
+) -> Result<HttpResponse, MyError> {
+    let principal = app_state.tokens.verify_refresh(&refresh.refresh_token)?;
+    // 老师已经被删除时不能再续期, 角色以账号当前的为准
+    let account = app_state
+        .teachers
+        .get_account(principal.teacher_id)
+        .await?
+        .ok_or_else(|| MyError::Unauthorized("Invalid or expired token".into()))?;
+    let principal = Principal { teacher_id: account.teacher_id, role: account.role() };
+    Ok(HttpResponse::Ok().json(app_state.tokens.issue(principal)?))
+}
+
+// * 测试
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::auth::TokenResponse;
+    use crate::routers::{auth_routes, course_routes};
+    use actix_web::http::{header, StatusCode};
+    use actix_web::{test, App, ResponseError};
+
+    fn register_json(username: &str, password: &str) -> web::Json<RegisterTeacher> {
+        web::Json(RegisterTeacher {
+            name: "张三".into(),
+            picture_url: "".into(),
+            profile: "高级教师".into(),
+            username: username.into(),
+            password: password.into(),
+        })
+    }
+
+    async fn login_tokens(app_state: web::Data<AppState>, password: &str) -> Result<TokenResponse, MyError> {
+        let res = login(app_state, web::Json(LoginRequest { username: "zhangsan".into(), password: password.into() })).await?;
+        let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+        Ok(serde_json::from_slice(&body).unwrap())
+    }
+
+    #[actix_rt::test]
+    async fn register_login_and_refresh() {
+        let app_state = AppState::for_tests().build();
+        let res = register(app_state.clone(), register_json("zhangsan", "correct horse"), Actor::anonymous()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        // 用户名重复
+        let err = register(app_state.clone(), register_json("zhangsan", "another one"), Actor::anonymous()).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::CONFLICT);
+        // 密码太短
+        let err = register(app_state.clone(), register_json("lisi", "short"), Actor::anonymous()).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+
+        let err = login_tokens(app_state.clone(), "wrong password").await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::UNAUTHORIZED);
+        let tokens = login_tokens(app_state.clone(), "correct horse").await.unwrap();
+        assert_eq!(app_state.tokens.verify_access(&tokens.access_token).unwrap().teacher_id, 1);
+        // access token 不能用来刷新
+        let err = refresh(app_state.clone(), web::Json(RefreshRequest { refresh_token: tokens.access_token })).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::UNAUTHORIZED);
+        let res = refresh(app_state, web::Json(RefreshRequest { refresh_token: tokens.refresh_token })).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn mutating_requests_require_login() {
+        let app_state = AppState::for_tests().build();
+        let app = test::init_service(
+            App::new().app_data(app_state.clone()).configure(auth_routes).configure(course_routes),
+        )
+        .await;
+        let new_course = serde_json::json!({ "teacher_id": 1, "name": "Rust" });
+
+        // 读取不需要登录
+        let req = test::TestRequest::get().uri("/courses/1").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        // 没有登录不能新增课程
+        let req = test::TestRequest::post().uri("/courses/").set_json(&new_course).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers().get(header::WWW_AUTHENTICATE).unwrap(), "Bearer");
+        // token 不合法时, 读取也会被拒绝
+        let req = test::TestRequest::get()
+            .uri("/courses/1")
+            .insert_header((header::AUTHORIZATION, "Bearer not-a-token"))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+
+        // 注册并登录之后就可以新增课程了
+        let req = test::TestRequest::post()
+            .uri("/auth/register")
+            .set_json(serde_json::json!({
+                "name": "张三", "picture_url": "", "profile": "", "username": "zhangsan", "password": "correct horse"
+            }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(serde_json::json!({ "username": "zhangsan", "password": "correct horse" }))
+            .to_request();
+        let tokens: TokenResponse = test::call_and_read_body_json(&app, req).await;
+        let req = test::TestRequest::post()
+            .uri("/courses/")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", tokens.access_token)))
+            .set_json(&new_course)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let history = app_state.courses.get_course_history(1, 1, Default::default()).await.unwrap();
+        assert_eq!(history.items[0].actor, "teacher:1");
+    }
+}
@@ -1,15 +1,29 @@
-use crate::db_access::course::*;
 use crate::state::AppState;
 use crate::errors::MyError;
-use actix_web::{web, HttpResponse};
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::StreamExt;
 
-use crate::models::course::{CreateCourse, UpdateCourse};
+use crate::auth::Principal;
+use crate::cache::{CacheRoute, ConditionalGet};
+use crate::events::course_event_stream;
+use crate::models::audit::Actor;
+use crate::models::catalog::{
+    encode_export, parse_import, CatalogFormat, ExportQuery, ImportResponse,
+};
+use crate::models::course::{CourseFilterQuery, CoursePatch, CourseSearchQuery, CreateCourse, UpdateCourse};
+use crate::models::course_options::CourseOptions;
+use crate::models::pagination::PageQuery;
+use crate::models::version::{etag, VersionCheck};
 
 pub async fn post_new_course(
     new_course: web::Json<CreateCourse>,
     app_state: web::Data<AppState>,
+    // 登录身份, 只能给自己添加课程, 同时作为操作人写入变更历史
+    principal: Principal,
 ) -> Result<HttpResponse, MyError> {
-    println!("Received new course.");
+    tracing::debug!(name = %new_course.name, "received new course");
+    principal.authorize(new_course.teacher_id)?;
     /* let course_count = app_state
         .courses
         .lock() // 获取权限
@@ -32,9 +46,9 @@ pub async fn post_new_course(
     // 调用 post_new_course_db 添加到数据库并返回添加的课程
     // ? CreateCourse 并没有实现 from, 而是实现的 try_from, 所以这里需要使用 try_into
     // ? 后面跟一个 ? 标识转换可能会出错, 简单处理一下
-    post_new_course_db(&app_state.db, new_course.try_into()?)
+    app_state.courses.post_new_course(new_course.try_into()?, &Actor::from(principal))
         .await
-        .map(|course| HttpResponse::Ok().json(course))
+        .map(|course| HttpResponse::Ok().insert_header(etag(course.version)).json(course))
 }
 
 // ? 无需转换, 已弃用
@@ -54,6 +68,12 @@ pub async fn get_courses_for_teacher(
     params: web::Path<i32>,
     // params 参数可以修改为如下所示
     // web::Path(teacher_id): web::Path<i32> 
+    // 分页参数, ?limit=10&offset=0 或者 ?limit=10&cursor=xx
+    page: web::Query<PageQuery>,
+    // 筛选和排序参数, ?language=english&min_price=10&sort=price&order=desc
+    filter: web::Query<CourseFilterQuery>,
+    // If-None-Match 和 If-Modified-Since, 这一页没有变化时返回 304
+    conditions: ConditionalGet,
 ) -> Result<HttpResponse, MyError> {
     /* // 获取元组的第一个元素, 也就是teacher_id
     // let teacher_id: usize = params.0;
@@ -82,19 +102,43 @@ pub async fn get_courses_for_teacher(
     // let teacher_id = translate_usize_to_i32(params.into_inner().0);
     // ? 无需转换
     let teacher_id = params.into_inner();
-    get_courses_for_teacher_db(&app_state.db, teacher_id)
-        .await
-        // 这个表示如果成功, 就将 courses 返回出去
+    // 自定义排序时只能用 offset 翻页
+    if page.cursor.is_some() && filter.sort.is_some() {
+        return Err(MyError::InvalidInput("cursor can not be used together with sort, use offset instead".into()));
+    }
+    let courses = app_state
+        .courses
+        .get_courses_for_teacher(teacher_id, filter.into_inner().try_into()?, page.into_inner().try_into()?)
         // 如果失败就会发生错误, 得到的错误类型就是 MyError
         // 由于MyError实现了 ResponseError 这个 trait, 所以 Actix会把 MyError 自动转换为错误对应的响应信息转发给用户
-        .map(|courses| HttpResponse::Ok().json(courses))
+        .await?;
+    // 成功时将 courses 返回出去
+    conditions.collection(app_state.cache.cache_control(CacheRoute::CourseList), &courses)
+}
+
+// 全文检索所有老师的课程, /courses/search?q=rust&limit=10&offset=0
+pub async fn search_courses(
+    app_state: web::Data<AppState>,
+    search: web::Query<CourseSearchQuery>,
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, MyError> {
+    // 按相关度排序, 只能用 offset 翻页
+    if page.cursor.is_some() {
+        return Err(MyError::InvalidInput("cursor is not supported for search, use offset instead".into()));
+    }
+    app_state
+        .courses
+        .search_courses(search.validated()?, page.into_inner().try_into()?)
+        .await
+        .map(|hits| HttpResponse::Ok().json(hits))
 }
 
 // 获取老师的某一个课程
 pub async fn get_course_detail(
     app_state: web::Data<AppState>,
     // params: web::Path<(usize, usize)>,
-    params: web::Path<(i32, i32)>
+    params: web::Path<(i32, i32)>,
+    conditions: ConditionalGet,
 ) -> Result<HttpResponse, MyError> {
     /* let (teacher_id, course_id) = params.into_inner();
     // 查找这个老师的详细课程
@@ -116,138 +160,534 @@ pub async fn get_course_detail(
     //     translate_usize_to_i32(params_tuple.1),
     // );
     let (teacher_id, course_id) = params.into_inner();
-    get_course_details_db(&app_state.db, teacher_id, course_id)
-        .await
-        .map(|course| HttpResponse::Ok().json(course))
+    let course = app_state.courses.get_course_details(teacher_id, course_id).await?;
+    // 版本号作为 ETag 返回, 修改时通过 If-Match 带回来, 查询时通过 If-None-Match 带回来
+    conditions.entity(app_state.cache.cache_control(CacheRoute::CourseDetail), course.version, course.updated_at, &course)
 }
 
 // 删除课程
 pub async fn delete_course(
     app_state: web::Data<AppState>,
-    params: web::Path<(i32, i32)>
+    params: web::Path<(i32, i32)>,
+    principal: Principal,
 ) -> Result<HttpResponse, MyError> {
     let (teacher_id, course_id) = params.into_inner();
-    delete_course_db(&app_state.db, teacher_id, course_id)
+    principal.authorize(teacher_id)?;
+    app_state.courses.delete_course(teacher_id, course_id, &Actor::from(principal))
     .await
     .map(|res| HttpResponse::Ok().json(res))
 }
 
 // 更新
+// 带了 If-Match 时, 只有版本一致才会修改, 否则返回 412
 pub async fn update_course_details(
     app_state: web::Data<AppState>,
     update_course: web::Json<UpdateCourse>,
     // params: web::Path<(usize, usize)>,
-    params: web::Path<(i32, i32)>
+    params: web::Path<(i32, i32)>,
+    check: VersionCheck,
+    principal: Principal,
 ) -> Result<HttpResponse, MyError> {
     let (teacher_id, course_id) = params.into_inner();
-    // 提取 update_course时, 需要调用一次 into, 因为 updateCourse 实现的是 from trait
-    update_course_details_db(&app_state.db, teacher_id, course_id, update_course.into())
+    principal.authorize(teacher_id)?;
+    // 提取 update_course时, 需要调用一次 try_into, 字段校验不通过时返回 400
+    let patch = CoursePatch::from(UpdateCourse::try_from(update_course)?);
+    app_state.courses.update_course_details(teacher_id, course_id, patch, &check, &Actor::from(principal))
     .await
-    .map(|res| HttpResponse::Ok().json(res))
+    .map(|res| HttpResponse::Ok().insert_header(etag(res.version)).json(res))
+}
+
+// 部分更新, JSON Merge Patch, 可以传 null 清空字段, 同样支持 If-Match
+pub async fn patch_course_details(
+    app_state: web::Data<AppState>,
+    patch: web::Json<CoursePatch>,
+    params: web::Path<(i32, i32)>,
+    check: VersionCheck,
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let (teacher_id, course_id) = params.into_inner();
+    principal.authorize(teacher_id)?;
+    app_state.courses.update_course_details(teacher_id, course_id, patch.try_into()?, &check, &Actor::from(principal))
+    .await
+    .map(|res| HttpResponse::Ok().insert_header(etag(res.version)).json(res))
+}
+
+// 回收站, 某个老师已经删除的课程
+pub async fn get_deleted_courses(
+    app_state: web::Data<AppState>,
+    params: web::Path<i32>,
+    page: web::Query<PageQuery>,
+    // 回收站只有老师自己和管理员可以查看
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let teacher_id = params.into_inner();
+    principal.authorize(teacher_id)?;
+    app_state
+        .courses
+        .get_deleted_courses(teacher_id, page.into_inner().try_into()?)
+        .await
+        .map(|courses| HttpResponse::Ok().json(courses))
+}
+
+// 从回收站恢复课程
+pub async fn restore_course(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let (teacher_id, course_id) = params.into_inner();
+    principal.authorize(teacher_id)?;
+    app_state
+        .courses
+        .restore_course(teacher_id, course_id, &Actor::from(principal))
+        .await
+        .map(|course| HttpResponse::Ok().insert_header(etag(course.version)).json(course))
+}
+
+// 批量导入课程, Content-Type 为 text/csv 或者 application/x-ndjson
+// 每一行都会校验, 有任何一行不合法就返回所有出错的行, 一条都不导入
+pub async fn import_courses(
+    app_state: web::Data<AppState>,
+    params: web::Path<i32>,
+    req: HttpRequest,
+    body: web::Bytes,
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let teacher_id = params.into_inner();
+    principal.authorize(teacher_id)?;
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let format = CatalogFormat::from_content_type(content_type)?;
+    let new_courses = parse_import(format, &body, teacher_id).map_err(MyError::InvalidRows)?;
+    app_state
+        .courses
+        .import_courses(new_courses, &Actor::from(principal))
+        .await
+        .map(|items| HttpResponse::Ok().json(ImportResponse { imported: items.len(), items }))
+}
+
+// 导出老师的所有课程, ?format=csv (默认) 或者 ?format=ndjson
+// ? 以流的形式边读边写, 课程很多时也不用全部读进内存
+pub async fn export_courses(
+    app_state: web::Data<AppState>,
+    params: web::Path<i32>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, MyError> {
+    let teacher_id = params.into_inner();
+    let format = CatalogFormat::from_query(query.format.as_deref())?;
+    let mut with_header = true;
+    let body = app_state.courses.export_courses(teacher_id).map(move |batch| {
+        let bytes = batch.and_then(|courses| encode_export(format, &courses, with_header));
+        with_header = false;
+        bytes
+    });
+    let extension = match format {
+        CatalogFormat::Csv => "csv",
+        CatalogFormat::Ndjson => "ndjson",
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!("courses-{}.{}", teacher_id, extension))],
+        })
+        .streaming(body))
+}
+
+// 课程等级, 格式和语言允许的值, 前端用来渲染下拉框
+pub async fn get_course_options() -> HttpResponse {
+    HttpResponse::Ok().json(CourseOptions::all())
+}
+
+// 课程的变更历史, 课程删除之后也可以查看
+pub async fn get_course_history(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    page: web::Query<PageQuery>,
+    // 变更历史中有修改前后的完整字段, 和回收站一样只有老师自己和管理员可以查看
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let (teacher_id, course_id) = params.into_inner();
+    principal.authorize(teacher_id)?;
+    app_state
+        .courses
+        .get_course_history(teacher_id, course_id, page.into_inner().try_into()?)
+        .await
+        .map(|history| HttpResponse::Ok().json(history))
+}
+
+// 老师的课程变更事件, SSE, 见 events.rs
+// ? 没有 Last-Event-ID 时只推送之后发生的变更, 重连时 EventSource 会自动带上最后收到的事件 id
+pub async fn get_course_events(
+    app_state: web::Data<AppState>,
+    params: web::Path<i32>,
+    req: HttpRequest,
+    // 事件中有修改前后的完整字段和操作人, 和变更历史一样只有老师自己和管理员可以订阅
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let teacher_id = params.into_inner();
+    principal.authorize(teacher_id)?;
+    let last_event_id = match req.headers().get("last-event-id") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<i32>().ok())
+                .filter(|id| *id >= 0)
+                .ok_or_else(|| MyError::InvalidInput("Invalid Last-Event-ID header".into()))?,
+        ),
+        None => None,
+    };
+    // 老师不存在时返回 404, 而不是一直没有事件
+    app_state.teachers.get_teacher_details(teacher_id).await?;
+    // 先订阅再确定起点, 中间发生的变更也会收到通知
+    let changes = app_state.courses.subscribe_course_changes();
+    let position = app_state.courses.course_event_position(teacher_id, last_event_id).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // 让 nginx 之类的反向代理不要缓冲响应
+        .insert_header(("x-accel-buffering", "no"))
+        .streaming(course_event_stream(app_state, teacher_id, position, changes)))
 }
 
 // 测试
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::course::{Course, CourseFilter};
+    use crate::models::course_options::{CourseFormat, CourseLanguage, CourseLevel};
+    use crate::models::pagination::PageParams;
+    use crate::models::teacher::Teacher;
+    use actix_web::body::{to_bytes, MessageBody};
     use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use crate::routers::course_routes;
     use actix_web::ResponseError;
-    use dotenv::dotenv;
-    use sqlx::postgres::PgPoolOptions;
-    use std::env;
-    use std::sync::Mutex;
+    use chrono::{Duration, NaiveDate};
+
+    // 测试不再依赖 postgres, 使用内存实现, 预先放入 teacher_id 为 1 的三门课程
+    fn mock_app_state() -> web::Data<AppState> {
+        let courses = (1..=3)
+            .map(|id| Course {
+                teacher_id: 1,
+                id,
+                name: format!("Test Course {}", id),
+                time: NaiveDate::from_ymd_opt(2023, 1, id as u32).and_then(|date| date.and_hms_opt(0, 0, 0)),
+                description: Some(if id == 3 { "Learn Rust and WebAssembly".into() } else { "Learn Rust".into() }),
+                format: None,
+                structure: None,
+                duration: None,
+                // 价格依次为 30, 20, 10
+                price: Some(40 - id * 10),
+                language: Some(if id == 2 { CourseLanguage::Chinese } else { CourseLanguage::English }),
+                level: None,
+                deleted_at: None,
+                version: 1,
+                updated_at: chrono::Utc::now(),
+            })
+            .collect();
+        let teacher = Teacher {
+            id: 1,
+            name: Some("老师1".into()),
+            picture_url: None,
+            profile: None,
+            deleted_at: None,
+            version: 1,
+            updated_at: chrono::Utc::now(),
+        };
+        AppState::for_tests().courses(courses).teachers(vec![teacher]).build()
+    }
 
     // 异步测试, 需要使用 actix_rt 这个异步运行时
-    #[ignore] // 忽略当前测试, 通过一次后, 数据已经插入
     #[actix_rt::test]
-    // 这个用例暂时只能跑一次, 需要重跑的话就要换个id, 或者删除添加的数据
     async fn post_course_test() {
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-        let db_pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
-        let app_state: web::Data<AppState> = web::Data::new(AppState {
-            health_check_response: "".to_string(),
-            visit_count: Mutex::new(0),
-            db: db_pool,
-        });
+        let app_state = mock_app_state();
         let course = web::Json(CreateCourse {
             teacher_id: 1,
-            name: "Test Course".to_string().into(), // &str 不能直接调用into进行转换, 除非Trait实现了 AsRef, 允许 &str与String共享From
+            name: "Test Course".to_string(),
             description: Some("This is a course".into()),
             format: None,
             structure: None,
             duration: None,
             price: None,
-            language: Some("English".into()),
-            level: Some("Beginner".into()),
+            language: Some(CourseLanguage::English),
+            level: Some(CourseLevel::Beginner),
         });
-        let res = post_new_course(course, app_state).await.unwrap();
+        let res = post_new_course(course, app_state, Principal::teacher(1)).await.unwrap();
         assert_eq!(res.status(), StatusCode::OK);
     }
 
     #[actix_rt::test]
-    async fn get_all_courses_success() {
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-        let db_pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
-        let app_state: web::Data<AppState> = web::Data::new(AppState {
-            health_check_response: "".to_string(),
-            visit_count: Mutex::new(0),
-            db: db_pool,
+    async fn post_course_validation_errors() {
+        let app_state = mock_app_state();
+        let course = web::Json(CreateCourse {
+            teacher_id: 1,
+            name: "   ".into(),
+            description: Some("x".repeat(2001)),
+            format: None,
+            structure: None,
+            duration: None,
+            price: Some(-1),
+            language: None,
+            level: None,
+        });
+        let err = post_new_course(course, app_state.clone(), Principal::teacher(1)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        // 每个字段分别列出错误
+        let body = to_bytes(err.error_response().into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "validation_failed");
+        assert_eq!(body["detail"], "Validation failed");
+        let fields: Vec<&str> = body["errors"].as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(fields, vec!["description", "name", "price"]);
+        assert_eq!(body["errors"]["price"][0], "must not be less than 0");
+        let page = app_state.courses.get_courses_for_teacher(1, CourseFilter::default(), PageParams::default()).await.unwrap();
+        assert_eq!(page.total, 3);
+
+        // 修改时只校验传了的字段
+        let update = web::Json(UpdateCourse {
+            name: Some("".into()),
+            description: None,
+            format: None,
+            structure: None,
+            duration: None,
+            price: None,
+            language: None,
+            level: Some(CourseLevel::Advanced),
         });
+        let err = update_course_details(app_state, update, web::Path::from((1, 1)), VersionCheck::any(), Principal::teacher(1))
+            .await
+            .unwrap_err();
+        match err {
+            MyError::ValidationFailed(errors) => assert_eq!(errors.keys().collect::<Vec<_>>(), vec!["name"]),
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn course_options_are_normalized() {
+        // 不区分大小写, 也接受中文写法, 序列化时统一为小写下划线形式
+        let course: CreateCourse = serde_json::from_str(
+            r#"{"teacher_id": 1, "name": "Rust", "level": "初级", "format": "Self paced", "language": "EN"}"#,
+        )
+        .unwrap();
+        assert_eq!(course.level, Some(CourseLevel::Beginner));
+        assert_eq!(course.format, Some(CourseFormat::SelfPaced));
+        assert_eq!(course.language, Some(CourseLanguage::English));
+        let app_state = mock_app_state();
+        let course = app_state.courses.post_new_course(course, &Actor::anonymous()).await.unwrap();
+        let json = serde_json::to_value(&course).unwrap();
+        assert_eq!((json["level"].as_str(), json["format"].as_str()), (Some("beginner"), Some("self_paced")));
+        // 不在允许范围内的值
+        let err = serde_json::from_str::<CreateCourse>(r#"{"teacher_id": 1, "name": "Rust", "level": "Guru"}"#).unwrap_err();
+        assert!(err.to_string().starts_with("invalid level `Guru`, expected one of beginner, intermediate, advanced"));
+
+        // 筛选条件同样会统一写法
+        let query = CourseFilterQuery { language: Some("英文".into()), ..Default::default() };
+        let page = app_state.courses.get_courses_for_teacher(1, query.try_into().unwrap(), PageParams::default()).await.unwrap();
+        assert_eq!(page.total, 3);
+        let query = CourseFilterQuery { level: Some("expert".into()), ..Default::default() };
+        assert!(CourseFilter::try_from(query).is_err());
+
+        let res = get_course_options().await;
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["levels"][0], serde_json::json!({ "value": "beginner", "label": "Beginner" }));
+        assert_eq!(body["languages"].as_array().unwrap().len(), CourseLanguage::ALL.len());
+    }
+
+    #[actix_rt::test]
+    async fn get_all_courses_success() {
+        let app_state = mock_app_state();
         // ? 这里不加一个逗号, 不会被当做元组编译
         let teacher_id: web::Path<i32> = web::Path::from(1);
         // 简单处理, 直接 unwrap() 取出结果
-        let res = get_courses_for_teacher(app_state, teacher_id)
+        let res = get_courses_for_teacher(app_state, teacher_id, web::Query(PageQuery::default()), web::Query(CourseFilterQuery::default()), ConditionalGet::none())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn get_courses_paginated() {
+        let app_state = mock_app_state();
+        // 第一页两条, 还有下一页
+        let page = app_state
+            .courses
+            .get_courses_for_teacher(1, CourseFilter::default(), PageParams { limit: 2, ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(page.items.iter().map(|c| c.id).collect::<Vec<i32>>(), vec![1, 2]);
+        assert_eq!(page.next_cursor.as_deref(), Some("2"));
+        // 用 cursor 取第二页, 已经是最后一页
+        let query = PageQuery { limit: Some(2), offset: None, cursor: page.next_cursor };
+        let page = app_state
+            .courses
+            .get_courses_for_teacher(1, CourseFilter::default(), query.try_into().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(page.items.iter().map(|c| c.id).collect::<Vec<i32>>(), vec![3]);
+        assert_eq!(page.next_cursor, None);
+        // offset 方式
+        let query = PageQuery { limit: Some(1), offset: Some(1), cursor: None };
+        let page = app_state
+            .courses
+            .get_courses_for_teacher(1, CourseFilter::default(), query.try_into().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(page.items[0].id, 2);
+    }
+
+    #[actix_rt::test]
+    async fn get_courses_filtered_and_sorted() {
+        let app_state = mock_app_state();
+        // 只要英文课程, 按价格升序
+        let query = CourseFilterQuery {
+            language: Some("english".into()),
+            sort: Some("price".into()),
+            ..Default::default()
+        };
+        let page = app_state
+            .courses
+            .get_courses_for_teacher(1, query.try_into().unwrap(), PageParams::default())
+            .await
+            .unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.items.iter().map(|c| c.id).collect::<Vec<i32>>(), vec![3, 1]);
+        // 价格区间和时间区间
+        let query = CourseFilterQuery {
+            min_price: Some(15),
+            from: NaiveDate::from_ymd_opt(2023, 1, 2).and_then(|date| date.and_hms_opt(0, 0, 0)),
+            sort: Some("time".into()),
+            order: Some("desc".into()),
+            ..Default::default()
+        };
+        let page = app_state
+            .courses
+            .get_courses_for_teacher(1, query.try_into().unwrap(), PageParams::default())
             .await
             .unwrap();
+        assert_eq!(page.items.iter().map(|c| c.id).collect::<Vec<i32>>(), vec![2]);
+    }
+
+    #[actix_rt::test]
+    async fn get_courses_invalid_filter() {
+        let app_state = mock_app_state();
+        let teacher_id: web::Path<i32> = web::Path::from(1);
+        let query = CourseFilterQuery { sort: Some("id; DROP TABLE course".into()), ..Default::default() };
+        let res = get_courses_for_teacher(app_state, teacher_id, web::Query(PageQuery::default()), web::Query(query), ConditionalGet::none()).await;
+        match res {
+            Ok(_) => panic!("unknown sort field should be rejected"),
+            Err(err) => assert_eq!(err.status_code(), StatusCode::BAD_REQUEST),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn search_courses_success() {
+        let app_state = mock_app_state();
+        // 名称命中的权重更高, 所以课程 2 排在最前面
+        let search = web::Query(CourseSearchQuery { q: "2".into() });
+        let res = search_courses(app_state.clone(), search, web::Query(PageQuery::default())).await.unwrap();
         assert_eq!(res.status(), StatusCode::OK);
+
+        let page = app_state.courses.search_courses("rust webassembly".into(), PageParams::default()).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.items[0].course.id, 3);
+        assert_eq!(page.items[0].snippet, "Test Course 3 Learn <mark>Rust</mark> and <mark>WebAssembly</mark>");
+
+        let page = app_state.courses.search_courses("rust".into(), PageParams::default()).await.unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[actix_rt::test]
+    async fn search_courses_empty_query() {
+        let app_state = mock_app_state();
+        let search = web::Query(CourseSearchQuery { q: "   ".into() });
+        let res = search_courses(app_state, search, web::Query(PageQuery::default())).await;
+        match res {
+            Ok(_) => panic!("empty query should be rejected"),
+            Err(err) => assert_eq!(err.status_code(), StatusCode::BAD_REQUEST),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn get_courses_empty_is_ok() {
+        // 老师还没有课程, 返回 200 和空列表
+        let app_state = mock_app_state();
+        let teacher_id: web::Path<i32> = web::Path::from(2);
+        let res = get_courses_for_teacher(app_state, teacher_id, web::Query(PageQuery::default()), web::Query(CourseFilterQuery::default()), ConditionalGet::none())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn get_courses_invalid_page() {
+        let app_state = mock_app_state();
+        let teacher_id: web::Path<i32> = web::Path::from(1);
+        let query = PageQuery { limit: Some(0), offset: None, cursor: None };
+        let res = get_courses_for_teacher(app_state, teacher_id, web::Query(query), web::Query(CourseFilterQuery::default()), ConditionalGet::none()).await;
+        match res {
+            Ok(_) => panic!("limit 0 should be rejected"),
+            Err(err) => assert_eq!(err.status_code(), StatusCode::BAD_REQUEST),
+        }
     }
 
     #[actix_rt::test]
     async fn get_one_course_success() { 
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-        let db_pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
-        let app_state: web::Data<AppState> = web::Data::new(AppState {
-            health_check_response: "".to_string(),
-            visit_count: Mutex::new(0),
-            db: db_pool,
-        });
+        let app_state = mock_app_state();
         let params: web::Path<(i32, i32)> = web::Path::from((1, 1));
-        let res = get_course_detail(app_state, params).await.unwrap();
+        let res = get_course_detail(app_state, params, ConditionalGet::none()).await.unwrap();
         assert_eq!(res.status(), StatusCode::OK);
     }
 
     #[actix_rt::test]
     async fn get_one_course_failure() {
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-        let db_pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
-        let app_state: web::Data<AppState> = web::Data::new(AppState {
-            health_check_response: "".to_string(),
-            visit_count: Mutex::new(0),
-            db: db_pool,
-        });
+        let app_state = mock_app_state();
         // course_id不存在
         let params: web::Path<(i32, i32)> = web::Path::from((1, 100));
-        let res = get_course_detail(app_state, params).await;
-        match res {
-            Ok(_) => println!("Something wrong..."),
-            Err(err) => assert_eq!(err.status_code(), StatusCode::NOT_FOUND)
-        }
+        let res = get_course_detail(app_state, params, ConditionalGet::none()).await;
+        assert!(matches!(res, Err(MyError::NotFound(_))));
+    }
+
+    #[actix_rt::test]
+    async fn conditional_get_returns_not_modified_until_course_changes() {
+        let app_state = mock_app_state();
+        let res = get_course_detail(app_state.clone(), web::Path::from((1, 1)), ConditionalGet::if_none_match(etag(1).0)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get("etag").unwrap(), "\"1\"");
+        assert_eq!(res.headers().get("cache-control").unwrap(), "no-cache");
+        assert!(to_bytes(res.into_body()).await.unwrap().is_empty());
+
+        // 列表没有 Last-Modified, 只带 If-Modified-Since 时不会返回 304
+        let list = |conditions| {
+            get_courses_for_teacher(app_state.clone(), web::Path::from(1), web::Query(PageQuery::default()), web::Query(CourseFilterQuery::default()), conditions)
+        };
+        let res = list(ConditionalGet::if_modified_since(chrono::Utc::now() + Duration::seconds(60))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key("last-modified"));
+        // 删除一门课程之后, 剩下的课程都没有修改过, 列表的 ETag 仍然会变化
+        let tag: header::EntityTag = res.headers().get("etag").unwrap().to_str().unwrap().parse().unwrap();
+        delete_course(app_state.clone(), web::Path::from((1, 3)), Principal::teacher(1)).await.unwrap();
+        assert_eq!(list(ConditionalGet::if_none_match(tag)).await.unwrap().status(), StatusCode::OK);
+
+        // 修改之后版本变了, 返回新的内容
+        let patch = web::Json(serde_json::from_value::<CoursePatch>(serde_json::json!({ "price": 5 })).unwrap());
+        patch_course_details(app_state.clone(), patch, web::Path::from((1, 1)), VersionCheck::any(), Principal::teacher(1)).await.unwrap();
+        let res = get_course_detail(app_state, web::Path::from((1, 1)), ConditionalGet::if_none_match(etag(1).0)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("etag").unwrap(), "\"2\"");
     }
 
     #[actix_rt::test]
     async fn update_course_success() {
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-        let db_pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
-        let app_state: web::Data<AppState> = web::Data::new(AppState {
-            health_check_response: "".to_string(),
-            visit_count: Mutex::new(0),
-            db: db_pool,
-        });
+        let app_state = mock_app_state();
         let update_course: UpdateCourse = UpdateCourse {
             name: Some("Courese name changed".into()),
             description: Some("This is another test course".into()),
@@ -255,52 +695,391 @@ mod tests {
             structure: None,
             duration: None,
             price: Some(32),
-            language: Some("Chinese".into()),
-            level: Some("Intermediate".into()),
+            language: Some(CourseLanguage::Chinese),
+            level: Some(CourseLevel::Intermediate),
         };
         let params: web::Path<(i32, i32)> = web::Path::from((1, 2));
         let json_update_course = web::Json(update_course);
-        let res = update_course_details(app_state, json_update_course, params)
+        let res = update_course_details(app_state, json_update_course, params, VersionCheck::any(), Principal::teacher(1))
         .await
         .unwrap();
         assert_eq!(res.status(), StatusCode::OK);
     }
 
-    #[ignore]
+    #[actix_rt::test]
+    async fn update_course_with_stale_version() {
+        let app_state = mock_app_state();
+        let update_course = || UpdateCourse {
+            name: Some("Renamed".into()),
+            description: None,
+            format: None,
+            structure: None,
+            duration: None,
+            price: None,
+            language: None,
+            level: None,
+        };
+        let res = get_course_detail(app_state.clone(), web::Path::from((1, 2)), ConditionalGet::none()).await.unwrap();
+        assert_eq!(res.headers().get("etag").unwrap(), "\"1\"");
+        // 第一次修改成功, 版本变为 2
+        let res = update_course_details(
+            app_state.clone(),
+            web::Json(update_course()),
+            web::Path::from((1, 2)),
+            VersionCheck::version(1),
+            Principal::teacher(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.headers().get("etag").unwrap(), "\"2\"");
+        // 还拿着版本 1 的客户端再修改就会失败, 数据不变
+        let err = update_course_details(
+            app_state.clone(),
+            web::Json(update_course()),
+            web::Path::from((1, 2)),
+            VersionCheck::version(1),
+            Principal::teacher(1),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(app_state.courses.get_course_details(1, 2).await.unwrap().version, 2);
+    }
+
+    #[actix_rt::test]
+    async fn patch_course_tells_absent_null_and_value_apart() {
+        let app_state = mock_app_state();
+        let patch = |value| web::Json(serde_json::from_value::<CoursePatch>(value).unwrap());
+        // description 清空, price 修改, 没有传的字段不变
+        let res = patch_course_details(
+            app_state.clone(),
+            patch(serde_json::json!({ "description": null, "price": 99 })),
+            web::Path::from((1, 1)),
+            VersionCheck::version(1),
+            Principal::teacher(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.headers().get("etag").unwrap(), "\"2\"");
+        let course = app_state.courses.get_course_details(1, 1).await.unwrap();
+        assert_eq!((course.description, course.price), (None, Some(99)));
+        assert_eq!((course.language, course.structure), (Some(CourseLanguage::English), None));
+
+        // 空的 patch 不修改, 版本也不变
+        patch_course_details(app_state.clone(), patch(serde_json::json!({})), web::Path::from((1, 1)), VersionCheck::any(), Principal::teacher(1))
+            .await
+            .unwrap();
+        assert_eq!(app_state.courses.get_course_details(1, 1).await.unwrap().version, 2);
+        // 校验规则只对有值的字段生效
+        let err = patch_course_details(
+            app_state.clone(),
+            patch(serde_json::json!({ "price": -1 })),
+            web::Path::from((1, 1)),
+            VersionCheck::any(),
+            Principal::teacher(1),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        // 课程名不能清空, 不认识的字段直接拒绝
+        assert!(serde_json::from_value::<CoursePatch>(serde_json::json!({ "name": null })).is_err());
+        assert!(serde_json::from_value::<CoursePatch>(serde_json::json!({ "teacher_id": 2 })).is_err());
+
+        // PUT 没有传的字段保持 NULL, 不会被改成空字符串或者 0
+        let update = UpdateCourse {
+            name: Some("Renamed".into()),
+            description: None,
+            format: None,
+            structure: None,
+            duration: None,
+            price: None,
+            language: None,
+            level: None,
+        };
+        update_course_details(app_state.clone(), web::Json(update), web::Path::from((1, 1)), VersionCheck::any(), Principal::teacher(1))
+            .await
+            .unwrap();
+        let course = app_state.courses.get_course_details(1, 1).await.unwrap();
+        assert_eq!(course.name, "Renamed");
+        assert_eq!((course.description, course.structure, course.duration), (None, None, None));
+    }
+
     #[actix_rt::test]
     async fn delete_course_success() {
         // 删除成功
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-        let db_pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
-        let app_state: web::Data<AppState> = web::Data::new(AppState {
-            health_check_response: "".to_string(),
-            visit_count: Mutex::new(0),
-            db: db_pool,
-        });
+        let app_state = mock_app_state();
         let params: web::Path<(i32, i32)> = web::Path::from((1, 3));
-        let res = delete_course(app_state, params)
+        let res = delete_course(app_state, params, Principal::teacher(1))
         .await
         .unwrap();
         assert_eq!(res.status(), StatusCode::OK);
     }
 
     #[actix_rt::test]
-    async fn delete_course_failure() {
-        // 删除成功
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-        let db_pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
-        let app_state: web::Data<AppState> = web::Data::new(AppState {
-            health_check_response: "".to_string(),
-            visit_count: Mutex::new(0),
-            db: db_pool,
+    async fn delete_and_restore_course() {
+        let app_state = mock_app_state();
+        let params: web::Path<(i32, i32)> = web::Path::from((1, 3));
+        delete_course(app_state.clone(), params, Principal::teacher(1)).await.unwrap();
+        // 删除之后查不到, 也不能再删一次, 但是出现在回收站里
+        assert!(app_state.courses.get_course_details(1, 3).await.is_err());
+        let page = app_state.courses.get_courses_for_teacher(1, CourseFilter::default(), PageParams::default()).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert!(app_state.courses.delete_course(1, 3, &Actor::anonymous()).await.is_err());
+        let trash = app_state.courses.get_deleted_courses(1, PageParams::default()).await.unwrap();
+        assert_eq!(trash.items.len(), 1);
+        assert!(trash.items[0].deleted_at.is_some());
+        // 恢复
+        let params: web::Path<(i32, i32)> = web::Path::from((1, 3));
+        let res = restore_course(app_state.clone(), params, Principal::teacher(1)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(app_state.courses.get_course_details(1, 3).await.is_ok());
+        // 没有删除的课程不能恢复
+        let params: web::Path<(i32, i32)> = web::Path::from((1, 3));
+        let err = restore_course(app_state, params, Principal::teacher(1)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn trash_is_only_visible_to_its_owner() {
+        let app_state = mock_app_state();
+        app_state.courses.delete_course(1, 1, &Actor::teacher(1)).await.unwrap();
+        // 没有登录时返回 401
+        let app = init_service(App::new().app_data(app_state.clone()).configure(course_routes)).await;
+        let req = TestRequest::get().uri("/courses/1/trash").to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+        // 其他老师返回 403, 自己和管理员可以查看
+        let err = get_deleted_courses(app_state.clone(), web::Path::from(1), web::Query(PageQuery::default()), Principal::teacher(2))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        for principal in [Principal::teacher(1), Principal::admin(2)] {
+            let res = get_deleted_courses(app_state.clone(), web::Path::from(1), web::Query(PageQuery::default()), principal)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap();
+            assert_eq!(body["total"], 1);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn purge_deleted_courses() {
+        let app_state = mock_app_state();
+        app_state.courses.delete_course(1, 1, &Actor::anonymous()).await.unwrap();
+        // 刚删除的课程没有超过保留时间, 不会被清理
+        assert_eq!(app_state.courses.purge_deleted_courses(Duration::days(30)).await.unwrap(), 0);
+        assert_eq!(app_state.courses.purge_deleted_courses(Duration::zero()).await.unwrap(), 1);
+        let trash = app_state.courses.get_deleted_courses(1, PageParams::default()).await.unwrap();
+        assert_eq!(trash.total, 0);
+    }
+
+    #[actix_rt::test]
+    async fn course_history_records_changes() {
+        let app_state = mock_app_state();
+        let alice = Actor("alice".into());
+        let update_course = UpdateCourse {
+            name: None,
+            description: None,
+            format: None,
+            structure: None,
+            duration: None,
+            price: Some(99),
+            language: None,
+            level: None,
+        };
+        app_state.courses.update_course_details(1, 1, update_course.into(), &VersionCheck::any(), &alice).await.unwrap();
+        app_state.courses.delete_course(1, 1, &Actor::anonymous()).await.unwrap();
+        app_state.courses.restore_course(1, 1, &alice).await.unwrap();
+
+        let history = app_state.courses.get_course_history(1, 1, PageParams::default()).await.unwrap();
+        assert_eq!(history.total, 3);
+        let actions: Vec<&str> = history.items.iter().map(|entry| entry.action.as_str()).collect();
+        assert_eq!(actions, vec!["update", "delete", "restore"]);
+        // 修改前后的字段值都保存下来了
+        let update = &history.items[0];
+        assert_eq!(update.actor, "alice");
+        assert_eq!(update.before.as_ref().unwrap()["price"], 30);
+        assert_eq!(update.after.as_ref().unwrap()["price"], 99);
+        assert_eq!(history.items[1].actor, "anonymous");
+        assert!(history.items[1].after.as_ref().unwrap()["deleted_at"].is_string());
+        // 其他课程和其他老师看不到这些记录
+        let other = app_state.courses.get_course_history(2, 1, PageParams::default()).await.unwrap();
+        assert_eq!(other.total, 0);
+
+        let history = |teacher_id: i32, principal: Principal| {
+            get_course_history(app_state.clone(), web::Path::from((teacher_id, 1)), web::Query(PageQuery::default()), principal)
+        };
+        assert_eq!(history(1, Principal::teacher(1)).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(history(1, Principal::admin(2)).await.unwrap().status(), StatusCode::OK);
+        // 其他老师不能查看
+        let err = history(1, Principal::teacher(2)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        // 没有登录时返回 401
+        let app = init_service(App::new().app_data(app_state.clone()).configure(course_routes)).await;
+        let res = call_service(&app, TestRequest::get().uri("/courses/1/1/history").to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn import_courses_all_or_nothing() {
+        let app_state = mock_app_state();
+        let csv_request = || TestRequest::default().insert_header((header::CONTENT_TYPE, "text/csv")).to_http_request();
+        // 第 3 行没有 name, 第 4 行价格不是数字, 一条都不导入
+        let body = "name,price,language\nRust 101,10,English\n,20,English\nGo 101,free,English\n";
+        let err = import_courses(app_state.clone(), web::Path::from(1), csv_request(), web::Bytes::from(body), Principal::teacher(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        let report: serde_json::Value = serde_json::from_slice(&to_bytes(err.error_response().into_body()).await.unwrap()).unwrap();
+        assert_eq!(report["code"], "invalid_rows");
+        let lines: Vec<i64> = report["errors"].as_array().unwrap().iter().map(|e| e["line"].as_i64().unwrap()).collect();
+        assert_eq!(lines, vec![3, 4]);
+        let page = app_state.courses.get_courses_for_teacher(1, CourseFilter::default(), PageParams::default()).await.unwrap();
+        assert_eq!(page.total, 3);
+
+        // 全部合法时导入到路径中的老师名下
+        let body = "name,price,language\nRust 101,10,English\nGo 101,,Chinese\n";
+        let res = import_courses(app_state.clone(), web::Path::from(1), csv_request(), web::Bytes::from(body), Principal::teacher(1))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let page = app_state.courses.get_courses_for_teacher(1, CourseFilter::default(), PageParams::default()).await.unwrap();
+        assert_eq!(page.total, 5);
+        assert_eq!(page.items[4].price, None);
+
+        // NDJSON, 空行会被跳过
+        let req = TestRequest::default().insert_header((header::CONTENT_TYPE, "application/x-ndjson")).to_http_request();
+        let body = "{\"name\": \"WASM\", \"level\": \"Advanced\"}\n\n";
+        let res = import_courses(app_state, web::Path::from(2), req, web::Bytes::from(body), Principal::teacher(2))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn export_courses_as_csv_and_ndjson() {
+        let app_state = mock_app_state();
+        let res = export_courses(app_state.clone(), web::Path::from(1), web::Query(ExportQuery::default())).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = to_bytes(res.into_body()).await.unwrap();
+        let text = std::str::from_utf8(&body).unwrap();
+        assert_eq!(text.lines().count(), 4);
+        assert!(text.starts_with("id,name,description,format,structure,duration,price,language,level,time,version\n"));
+
+        let query = web::Query(ExportQuery { format: Some("ndjson".into()) });
+        let res = export_courses(app_state, web::Path::from(1), query).await.unwrap();
+        let body = to_bytes(res.into_body()).await.unwrap();
+        let names: Vec<String> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["name"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(names, vec!["Test Course 1", "Test Course 2", "Test Course 3"]);
+    }
+
+    #[actix_rt::test]
+    async fn other_teachers_courses_are_forbidden() {
+        let app_state = mock_app_state();
+        // 老师 2 不能修改老师 1 的课程
+        let err = delete_course(app_state.clone(), web::Path::from((1, 1)), Principal::teacher(2)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        let new_course = web::Json(CreateCourse {
+            teacher_id: 1,
+            name: "Not mine".into(),
+            description: None,
+            format: None,
+            structure: None,
+            duration: None,
+            price: None,
+            language: None,
+            level: None,
         });
+        let err = post_new_course(new_course, app_state.clone(), Principal::teacher(2)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        let req = TestRequest::default().insert_header((header::CONTENT_TYPE, "text/csv")).to_http_request();
+        let err = import_courses(app_state.clone(), web::Path::from(1), req, web::Bytes::from("name\nRust\n"), Principal::teacher(2))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        assert_eq!(app_state.courses.get_course_details(1, 1).await.unwrap().version, 1);
+
+        // 管理员可以修改任何老师的课程, 变更历史中记录的是管理员自己
+        let res = delete_course(app_state.clone(), web::Path::from((1, 1)), Principal::admin(99)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let history = app_state.courses.get_course_history(1, 1, PageParams::default()).await.unwrap();
+        assert_eq!(history.items[0].actor, "teacher:99");
+    }
+
+    #[actix_rt::test]
+    async fn delete_course_failure() {
+        // 删除失败, 课程不存在
+        let app_state = mock_app_state();
         let params: web::Path<(i32, i32)> = web::Path::from((1, 10000));
-        let res = delete_course(app_state, params).await;
-        match res {
-            Ok(_) => println!("Something wrong..."),
-            Err(err) => assert_eq!(err.status_code(), StatusCode::NOT_FOUND)
-        }
+        let res = delete_course(app_state.clone(), params, Principal::teacher(1)).await;
+        assert!(matches!(res, Err(MyError::NotFound(_))));
+        // 已经软删除的课程不能再删除一次
+        delete_course(app_state.clone(), web::Path::from((1, 1)), Principal::teacher(1)).await.unwrap();
+        let res = delete_course(app_state, web::Path::from((1, 1)), Principal::teacher(1)).await;
+        assert!(matches!(res, Err(MyError::NotFound(_))));
+    }
+
+    // 读取 SSE 响应的下一段, 超时说明没有新的事件
+    async fn next_event(body: &mut actix_web::body::BoxBody) -> Option<String> {
+        let next = futures_util::future::poll_fn(|cx| std::pin::Pin::new(&mut *body).poll_next(cx));
+        let chunk = actix_rt::time::timeout(std::time::Duration::from_secs(1), next).await.ok()??.ok()?;
+        Some(String::from_utf8(chunk.to_vec()).unwrap())
+    }
+
+    #[actix_rt::test]
+    async fn course_events_stream_changes_and_resume_from_last_event_id() {
+        let app_state = mock_app_state();
+        let res = get_course_events(app_state.clone(), web::Path::from(1), TestRequest::default().to_http_request(), Principal::teacher(1))
+            .await
+            .unwrap();
+        assert_eq!(res.headers().get("content-type").unwrap(), "text/event-stream");
+        let mut body = res.into_body();
+        assert_eq!(next_event(&mut body).await.unwrap(), "retry: 3000\n\n");
+        // 连接之前的变更不推送
+        assert_eq!(next_event(&mut body).await, None);
+
+        let patch = web::Json(serde_json::from_value::<CoursePatch>(serde_json::json!({ "price": 5 })).unwrap());
+        patch_course_details(app_state.clone(), patch, web::Path::from((1, 1)), VersionCheck::any(), Principal::teacher(1)).await.unwrap();
+        delete_course(app_state.clone(), web::Path::from((1, 2)), Principal::teacher(1)).await.unwrap();
+        let updated = next_event(&mut body).await.unwrap();
+        assert!(updated.starts_with("id: 1\nevent: updated\ndata: {"), "{}", updated);
+        assert!(updated.contains(r#""price":5"#));
+        assert!(next_event(&mut body).await.unwrap().starts_with("id: 2\nevent: deleted\n"));
+
+        // 重连时从 Last-Event-ID 之后继续
+        let req = TestRequest::default().insert_header(("last-event-id", "1")).to_http_request();
+        let mut body = get_course_events(app_state.clone(), web::Path::from(1), req, Principal::teacher(1)).await.unwrap().into_body();
+        next_event(&mut body).await.unwrap();
+        assert!(next_event(&mut body).await.unwrap().starts_with("id: 2\nevent: deleted\n"));
+        // 开始停机时立即结束响应, 不等到心跳
+        app_state.shutdown.begin_draining();
+        let next = futures_util::future::poll_fn(|cx| std::pin::Pin::new(&mut body).poll_next(cx));
+        assert!(actix_rt::time::timeout(std::time::Duration::from_secs(1), next).await.unwrap().is_none());
+
+        let req = TestRequest::default().insert_header(("last-event-id", "abc")).to_http_request();
+        let err = get_course_events(app_state.clone(), web::Path::from(1), req, Principal::teacher(1)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        let err = get_course_events(app_state.clone(), web::Path::from(99), TestRequest::default().to_http_request(), Principal::admin(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn course_events_are_private_to_the_teacher() {
+        let app_state = mock_app_state();
+        // 其他老师不能订阅, 也就不能用 Last-Event-ID: 0 回放全部历史
+        let req = TestRequest::default().insert_header(("last-event-id", "0")).to_http_request();
+        let err = get_course_events(app_state.clone(), web::Path::from(1), req, Principal::teacher(2)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(get_course_events(app_state.clone(), web::Path::from(1), req, Principal::admin(2)).await.unwrap().status(), StatusCode::OK);
+        // 没有登录时返回 401
+        let app = init_service(App::new().app_data(app_state).configure(course_routes)).await;
+        let req = TestRequest::get().uri("/courses/1/events").insert_header(("last-event-id", "0")).to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
     }
 }
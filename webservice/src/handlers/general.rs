@@ -1,18 +1,244 @@
+use crate::errors::MyError;
+use crate::models::health::{HealthStatus, Liveness, Readiness};
 use crate::state::AppState;
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 
-// 健康检查
-pub async fn health_check_handler(
+// 存活检查, 不访问数据库, 只要进程能处理请求就返回 200
+pub async fn liveness_handler(
     // 注入的 AppState, 只要 AppState 在 actix 中注册过, 就可以在 handler 进行注入, 其实就是通过 web::Data<AppState>
     app_state: web::Data<AppState>,
 ) -> HttpResponse {
-    let health_check_response = &app_state.health_check_response;
-    // 访问可变字段, 因为他用 Mutex 包裹, 所以必须先lock, 上锁, 防止其他线程在对这个值进行操作, 然后使用 unwrap 处理一下lock可能产生的错误, 也就是有线程正在操作当前值
-    let mut visit_count = app_state.visit_count.lock().unwrap();
-    // 形成一个响应
-    let response = format!("{} {} times", health_check_response, visit_count);
-    // 更新 visit_count
-    *visit_count += 1;
-    HttpResponse::Ok().json(&response)
-    // 走完这个 handler, 上面的锁就自动释放了
-}
\ No newline at end of file
+    // 计数器都是原子变量, 读取时不需要加锁
+    HttpResponse::Ok().json(Liveness {
+        status: HealthStatus::Ok,
+        uptime_seconds: app_state.metrics.uptime().as_secs(),
+        requests: app_state.metrics.snapshot(),
+    })
+}
+
+// 就绪检查, 数据库不可用或者正在停机时返回 503, 负载均衡暂时不再把请求转发过来
+pub async fn readiness_handler(app_state: web::Data<AppState>) -> HttpResponse {
+    let storage = app_state.courses.check_storage().await;
+    let status = if app_state.shutdown.is_draining() { HealthStatus::Draining } else { storage.status };
+    let readiness = Readiness { status, storage };
+    match status {
+        HealthStatus::Ok => HttpResponse::Ok().json(readiness),
+        HealthStatus::Unavailable | HealthStatus::Draining => HttpResponse::ServiceUnavailable().json(readiness),
+    }
+}
+
+// Prometheus 抓取的指标, 文本格式
+pub async fn metrics_handler(app_state: web::Data<AppState>) -> Result<HttpResponse, MyError> {
+    let body = app_state.metrics.render(app_state.courses.pool_stats()).map_err(MyError::ActixError)?;
+    Ok(HttpResponse::Ok().content_type("text/plain; version=0.0.4; charset=utf-8").body(body))
+}
+
+// 没有匹配的路由
+pub async fn not_found_handler(req: HttpRequest) -> Result<HttpResponse, MyError> {
+    Err(MyError::NotFound(format!("No route matches {} {}", req.method(), req.path())))
+}
+
+// * 测试
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{ProblemDetails, PROBLEM_JSON};
+    use crate::metrics::RecordMetrics;
+    use crate::models::health::PoolStats;
+    use crate::request_id::{AssignRequestId, REQUEST_ID_HEADER};
+    use crate::routers::{course_routes, general_routes};
+    use actix_web::http::{header, StatusCode};
+    use actix_web::{test, App};
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[actix_rt::test]
+    async fn errors_are_problem_json_with_request_id() {
+        let app = test::init_service(
+            App::new()
+                .app_data(AppState::for_tests().build())
+                .configure(course_routes)
+                .default_service(web::route().to(not_found_handler))
+                .wrap(AssignRequestId),
+        )
+        .await;
+
+        // 没有匹配的路由, 生成新的 request id
+        let req = test::TestRequest::get().uri("/nothing/here").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), PROBLEM_JSON);
+        let request_id = res.headers().get(REQUEST_ID_HEADER).unwrap().to_str().unwrap().to_string();
+        let problem: ProblemDetails = test::read_body_json(res).await;
+        assert_eq!(problem.code, "not_found");
+        assert_eq!(problem.status, 404);
+        assert_eq!(problem.instance.as_deref(), Some("/nothing/here"));
+        assert_eq!(problem.request_id, Some(request_id));
+
+        // 中间件返回的错误也是 problem, 并沿用客户端传来的 request id
+        let req = test::TestRequest::post()
+            .uri("/courses/")
+            .insert_header((REQUEST_ID_HEADER, "client-id-1"))
+            .set_json(serde_json::json!({ "teacher_id": 1, "name": "Rust" }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "client-id-1");
+        let problem: ProblemDetails = test::read_body_json(res).await;
+        assert_eq!(problem.code, "unauthorized");
+        assert_eq!(problem.request_id.as_deref(), Some("client-id-1"));
+
+        // 不合法的 request id 会被替换, 成功的响应也带上 request id
+        let req = test::TestRequest::get().uri("/courses/1").insert_header((REQUEST_ID_HEADER, "bad id!")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_ne!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "bad id!");
+    }
+
+    // 收集测试期间输出的日志
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        // JSON 格式一行一条日志
+        fn events(&self) -> Vec<serde_json::Value> {
+            let logs = self.0.lock().unwrap();
+            std::str::from_utf8(&logs).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn each_request_logs_in_its_own_span() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_current_span(true)
+            .with_max_level(tracing::Level::INFO)
+            .with_writer(move || writer.clone())
+            .finish();
+        // 只在当前线程生效, actix_rt 的测试在同一个线程中处理请求
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let app = test::init_service(
+            App::new().app_data(AppState::for_tests().build()).configure(course_routes).wrap(AssignRequestId),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/courses/1/999").insert_header((REQUEST_ID_HEADER, "trace-1")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+        let req = test::TestRequest::get().uri("/courses/1").insert_header((REQUEST_ID_HEADER, "trace-2")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let events = logs.events();
+        let finished = |request_id: &str| {
+            events
+                .iter()
+                .find(|event| event["span"]["request_id"] == request_id)
+                .unwrap_or_else(|| panic!("no log for request {}", request_id))
+                .clone()
+        };
+        // 出错的请求记为 warn, 带上错误码和内部原因, 路由的模板和老师 id 在 span 上
+        let rejected = finished("trace-1");
+        assert_eq!(rejected["level"], "WARN");
+        assert_eq!(rejected["fields"]["message"], "request rejected");
+        assert_eq!(rejected["fields"]["code"], "not_found");
+        assert_eq!(rejected["span"]["name"], "request");
+        assert_eq!(rejected["span"]["method"], "GET");
+        assert_eq!(rejected["span"]["path"], "/courses/1/999");
+        assert_eq!(rejected["span"]["route"], "/courses/{teacher_id}/{course_id}");
+        assert_eq!(rejected["span"]["teacher_id"], 1);
+        assert_eq!(rejected["span"]["status"], 404);
+        // 成功的请求记为 info, 两个请求的日志不会混在同一个 span 中
+        let completed = finished("trace-2");
+        assert_eq!(completed["level"], "INFO");
+        assert_eq!(completed["fields"]["message"], "request completed");
+        assert_eq!(completed["span"]["route"], "/courses/{teacher_id}");
+        assert_eq!(completed["span"]["status"], 200);
+        assert!(completed["span"]["latency_ms"].is_u64());
+    }
+
+    #[actix_rt::test]
+    async fn health_probes_and_request_metrics() {
+        let app_state = AppState::for_tests().build();
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .configure(general_routes)
+                .configure(course_routes)
+                .wrap(RecordMetrics),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/health/ready").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["storage"]["backend"], "memory");
+
+        let req = test::TestRequest::get().uri("/courses/1/999").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+        // 存活检查本身也是一个进行中的请求
+        let req = test::TestRequest::get().uri("/health/live").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["requests"]["requests_total"], 3);
+        assert_eq!(body["requests"]["requests_in_flight"], 1);
+        let snapshot = app_state.metrics.snapshot();
+        assert_eq!(snapshot.requests_in_flight, 0);
+        assert_eq!(snapshot.responses["2xx"], 2);
+        assert_eq!(snapshot.responses["4xx"], 1);
+
+        // Prometheus 的指标按路由的模板统计, 错误按错误码统计
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(
+            r#"http_requests_total{method="GET",route="/courses/{teacher_id}/{course_id}",status="404"} 1"#
+        ));
+        assert!(body.contains(r#"http_requests_total{method="GET",route="/health/ready",status="200"} 1"#));
+        assert!(body.contains(r#"http_request_duration_seconds_count{method="GET",route="/health/live"} 1"#));
+        assert!(body.contains(r#"http_errors_total{code="not_found"} 1"#));
+        assert!(body.contains("http_requests_in_flight 1"));
+        // 内存存储没有连接池
+        assert!(!body.contains("db_pool_"));
+
+        // 收到停机信号之后就绪检查失败, 存活检查不受影响
+        app_state.shutdown.begin_draining();
+        let req = test::TestRequest::get().uri("/health/ready").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["status"], "draining");
+        assert_eq!(body["storage"]["status"], "ok");
+        let req = test::TestRequest::get().uri("/health/live").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn pool_saturation() {
+        let stats = PoolStats::new(10, 2, 10);
+        assert_eq!(stats.in_use, 8);
+        assert_eq!(stats.saturation, 0.8);
+        assert_eq!(PoolStats::new(0, 0, 0).saturation, 0.0);
+    }
+
+    #[actix_rt::test]
+    async fn internal_errors_are_not_leaked() {
+        let problem = MyError::DBError("relation \"course\" does not exist".into()).problem();
+        assert_eq!(problem.status, 500);
+        assert_eq!(problem.code, "database_error");
+        assert!(!problem.detail.contains("relation"));
+    }
+}
@@ -1,3 +1,5 @@
+pub mod auth; // 注册, 登录和刷新 token
 pub mod course; // course相关业务
 pub mod general; // 健康检查
 pub mod teacher; // 教师管理
+pub mod webhook; // webhook 的注册, 投递记录和重新投递
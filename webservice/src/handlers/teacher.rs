@@ -1,154 +1,453 @@
-use crate::db_access::teacher::*;
 use crate::errors::MyError;
 use crate::state::AppState;
 use actix_web::{web, HttpResponse};
 
-use crate::models::teacher::{CreateTeacher, UpdateTeacher};
+use crate::auth::Principal;
+use crate::cache::{CacheRoute, ConditionalGet};
+use crate::models::audit::Actor;
+use crate::models::pagination::PageQuery;
+use crate::models::version::{etag, VersionCheck};
+use crate::models::teacher::{CoursePolicy, CreateTeacher, DeleteTeacherQuery, TeacherPatch, UpdateTeacher};
 
 // * 查询全部教师
-pub async fn get_all_teachers(app_state: web::Data<AppState>) -> Result<HttpResponse, MyError> {
-    get_all_teachers_db(&app_state.db)
-        .await
-        .map(|teachers| HttpResponse::Ok().json(teachers))
+pub async fn get_all_teachers(
+    app_state: web::Data<AppState>,
+    page: web::Query<PageQuery>,
+    conditions: ConditionalGet,
+) -> Result<HttpResponse, MyError> {
+    let teachers = app_state.teachers.get_all_teachers(page.into_inner().try_into()?).await?;
+    conditions.collection(app_state.cache.cache_control(CacheRoute::TeacherList), &teachers)
 }
 
 // * 获取老师详细信息
 pub async fn get_teacher_details(
     app_state: web::Data<AppState>,
     params: web::Path<i32>,
+    conditions: ConditionalGet,
 ) -> Result<HttpResponse, MyError> {
     let teacher_id = params.into_inner();
-    get_teacher_details_db(&app_state.db, teacher_id)
-        .await
-        .map(|teacher| HttpResponse::Ok().json(teacher))
+    let teacher = app_state.teachers.get_teacher_details(teacher_id).await?;
+    conditions.entity(app_state.cache.cache_control(CacheRoute::TeacherDetail), teacher.version, teacher.updated_at, &teacher)
 }
 
-// * 新增老师
+// * 新增老师, 只有管理员可以直接添加, 老师自己通过 /auth/register 注册
 pub async fn post_new_teacher(
     new_teacher: web::Json<CreateTeacher>,
     app_state: web::Data<AppState>,
+    principal: Principal,
 ) -> Result<HttpResponse, MyError> {
-    post_new_course_db(&app_state.db, CreateTeacher::from(new_teacher))
+    principal.require_admin()?;
+    app_state.teachers.post_new_teacher(CreateTeacher::try_from(new_teacher)?, &Actor::from(principal))
         .await
-        .map(|teacher| HttpResponse::Ok().json(teacher))
+        .map(|teacher| HttpResponse::Ok().insert_header(etag(teacher.version)).json(teacher))
 }
 
 pub async fn update_teacher_details(
     app_state: web::Data<AppState>,
     update_teacher: web::Json<UpdateTeacher>,
     params: web::Path<i32>,
+    // If-Match, 版本不一致时返回 412
+    check: VersionCheck,
+    // 老师只能修改自己的信息
+    principal: Principal,
 ) -> Result<HttpResponse, MyError> {
     let teacher_id = params.into_inner();
-    update_teacher_details_db(&app_state.db, teacher_id, update_teacher.into())
+    principal.authorize(teacher_id)?;
+    let patch = TeacherPatch::from(UpdateTeacher::try_from(update_teacher)?);
+    app_state.teachers.update_teacher_details(teacher_id, patch, &check, &Actor::from(principal))
         .await
-        .map(|teacher| HttpResponse::Ok().json(teacher))
+        .map(|teacher| HttpResponse::Ok().insert_header(etag(teacher.version)).json(teacher))
 }
 
+// 部分更新, JSON Merge Patch, 头像和简介可以传 null 清空
+pub async fn patch_teacher_details(
+    app_state: web::Data<AppState>,
+    patch: web::Json<TeacherPatch>,
+    params: web::Path<i32>,
+    check: VersionCheck,
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let teacher_id = params.into_inner();
+    principal.authorize(teacher_id)?;
+    app_state.teachers.update_teacher_details(teacher_id, patch.try_into()?, &check, &Actor::from(principal))
+        .await
+        .map(|teacher| HttpResponse::Ok().insert_header(etag(teacher.version)).json(teacher))
+}
+
+// * 删除老师, 成功返回 204
+// ? 老师还有课程时, 通过 ?courses=reject|cascade|reassign&reassign_to=id 选择处理方式, 默认 reject
+// ? reassign 只有管理员可以使用, 老师不能不经过对方同意就把课程转给其他老师
 pub async fn delete_teacher(
     app_state: web::Data<AppState>,
     params: web::Path<i32>,
+    query: web::Query<DeleteTeacherQuery>,
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let teacher_id = params.into_inner();
+    principal.authorize(teacher_id)?;
+    let policy = CoursePolicy::try_from(query.into_inner())?;
+    if let CoursePolicy::Reassign(_) = policy {
+        principal.require_admin()?;
+    }
+    app_state.teachers.delete_teacher(teacher_id, policy, &Actor::from(principal))
+        .await
+        .map(|_| HttpResponse::NoContent().finish())
+}
+
+// * 恢复已删除的老师
+pub async fn restore_teacher(
+    app_state: web::Data<AppState>,
+    params: web::Path<i32>,
+    principal: Principal,
 ) -> Result<HttpResponse, MyError> {
     let teacher_id = params.into_inner();
-    delete_teacher_db(&app_state.db, teacher_id)
+    principal.authorize(teacher_id)?;
+    app_state
+        .teachers
+        .restore_teacher(teacher_id, &Actor::from(principal))
         .await
-        .map(|res| HttpResponse::Ok().json(res))
+        .map(|teacher| HttpResponse::Ok().insert_header(etag(teacher.version)).json(teacher))
+}
+
+// * 老师信息的变更历史
+pub async fn get_teacher_history(
+    app_state: web::Data<AppState>,
+    params: web::Path<i32>,
+    page: web::Query<PageQuery>,
+    // 和课程的变更历史一样, 只有老师自己和管理员可以查看
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let teacher_id = params.into_inner();
+    principal.authorize(teacher_id)?;
+    app_state
+        .teachers
+        .get_teacher_history(teacher_id, page.into_inner().try_into()?)
+        .await
+        .map(|history| HttpResponse::Ok().json(history))
 }
 
 // * 测试
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::audit::EventPosition;
+    use crate::models::course::{Course, CourseFilter, UpdateCourse};
+    use crate::models::webhook::NewWebhook;
+    use crate::models::pagination::PageParams;
+    use crate::models::teacher::Teacher;
+    use actix_web::http::header::EntityTag;
     use actix_web::http::StatusCode;
-    use dotenv::dotenv;
-    use sqlx::postgres::PgPoolOptions;
-    use std::env;
-    use std::sync::Mutex;
+    use actix_web::ResponseError;
+
+    // 使用内存实现, 预先放入 id 为 1, 100, 200 的三位老师, 老师 200 有两门课程
+    fn mock_app_state() -> web::Data<AppState> {
+        let courses = (1..=2)
+            .map(|id| Course {
+                teacher_id: 200,
+                id,
+                name: format!("课程{}", id),
+                time: None,
+                description: None,
+                format: None,
+                structure: None,
+                duration: None,
+                price: None,
+                language: None,
+                level: None,
+                deleted_at: None,
+                version: 1,
+                updated_at: chrono::Utc::now(),
+            })
+            .collect();
+        let teachers = [1, 100, 200]
+            .into_iter()
+            .map(|id| Teacher {
+                id,
+                name: Some(format!("老师{}", id)),
+                picture_url: None,
+                profile: Some("高级教师".into()),
+                deleted_at: None,
+                version: 1,
+                updated_at: chrono::Utc::now(),
+            })
+            .collect();
+        AppState::for_tests().courses(courses).teachers(teachers).build()
+    }
 
     #[actix_rt::test]
     async fn get_all_teachers_success_test() {
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-        let db_pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
-        let app_state: web::Data<AppState> = web::Data::new(AppState {
-            health_check_response: "".to_string(),
-            visit_count: Mutex::new(0),
-            db: db_pool,
-        });
-
-        let res = get_all_teachers(app_state).await.unwrap();
+        let app_state = mock_app_state();
+        let res = get_all_teachers(app_state, web::Query(PageQuery::default()), ConditionalGet::none()).await.unwrap();
         assert_eq!(res.status(), StatusCode::OK);
     }
 
     #[actix_rt::test]
     async fn get_teacher_details_success() {
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-        let db_pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
-        let app_state: web::Data<AppState> = web::Data::new(AppState {
-            health_check_response: "".to_string(),
-            visit_count: Mutex::new(0),
-            db: db_pool,
-        });
+        let app_state = mock_app_state();
         let params: web::Path<i32> = web::Path::from(1);
-        let res = get_teacher_details(app_state, params).await.unwrap();
+        let res = get_teacher_details(app_state, params, ConditionalGet::none()).await.unwrap();
         assert_eq!(res.status(), StatusCode::OK);
     }
 
     #[actix_rt::test]
     async fn post_teacher_success_test() {
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-        let db_pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
-        let app_state: web::Data<AppState> = web::Data::new(AppState {
-            health_check_response: "".to_string(),
-            visit_count: Mutex::new(0),
-            db: db_pool,
-        });
-        
+        let app_state = mock_app_state();
         let new_teacher = web::Json(CreateTeacher {
             name: "张三".into(),
             picture_url: "https://commonresource-1252524126.cdn.xiaoeknow.com/image/l2y5zx530z40.png".into(),
             profile: "高级教师".into()
         });
 
-        let res = post_new_teacher(new_teacher, app_state).await.unwrap();
+        let res = post_new_teacher(new_teacher, app_state, Principal::admin(1)).await.unwrap();
         assert_eq!(res.status(), StatusCode::OK);
     }
 
-    #[ignore]
     #[actix_rt::test]
-    async fn delete_teacher_success_test() {
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-        let db_pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
-        let app_state: web::Data<AppState> = web::Data::new(AppState {
-            health_check_response: "".to_string(),
-            visit_count: Mutex::new(0),
-            db: db_pool,
+    async fn teacher_mutations_are_forbidden_for_others() {
+        let app_state = mock_app_state();
+        // 老师不能直接新增老师, 只能由管理员添加
+        let new_teacher = web::Json(CreateTeacher { name: "王五".into(), picture_url: "".into(), profile: "".into() });
+        let err = post_new_teacher(new_teacher, app_state.clone(), Principal::teacher(1)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        // 也不能修改或者删除别的老师
+        let update = web::Json(UpdateTeacher { name: Some("改名".into()), picture_url: None, profile: None });
+        let err = update_teacher_details(app_state.clone(), update, web::Path::from(200), VersionCheck::any(), Principal::teacher(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        let err = delete_teacher(app_state.clone(), web::Path::from(100), web::Query(DeleteTeacherQuery::default()), Principal::teacher(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        assert!(app_state.teachers.get_teacher_details(100).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn teacher_validation_errors() {
+        let app_state = mock_app_state();
+        let new_teacher = web::Json(CreateTeacher {
+            name: "".into(),
+            picture_url: "javascript:alert(1)".into(),
+            profile: "高级教师".into(),
         });
+        let err = post_new_teacher(new_teacher, app_state.clone(), Principal::admin(1)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        match err {
+            MyError::ValidationFailed(errors) => {
+                assert_eq!(errors.keys().collect::<Vec<_>>(), vec!["name", "picture_url"]);
+                assert_eq!(errors["picture_url"], vec!["must be an http or https URL"]);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        let update = web::Json(UpdateTeacher { name: None, picture_url: None, profile: Some("长".repeat(2001)) });
+        let err = update_teacher_details(app_state, update, web::Path::from(1), VersionCheck::any(), Principal::teacher(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn delete_teacher_success_test() {
+        let app_state = mock_app_state();
         let params: web::Path<i32> = web::Path::from(100);
-        let res = delete_teacher(app_state, params).await.unwrap();
+        let res = delete_teacher(app_state, params, web::Query(DeleteTeacherQuery::default()), Principal::teacher(100))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[actix_rt::test]
+    async fn delete_teacher_not_found() {
+        let app_state = mock_app_state();
+        let params: web::Path<i32> = web::Path::from(404);
+        let err = delete_teacher(app_state, params, web::Query(DeleteTeacherQuery::default()), Principal::admin(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn delete_teacher_course_policies() {
+        let app_state = mock_app_state();
+        let query = |courses: &str, reassign_to: Option<i32>| {
+            web::Query(DeleteTeacherQuery { courses: Some(courses.into()), reassign_to })
+        };
+        // 老师 200 还有课程, 默认不允许删除
+        let err = delete_teacher(app_state.clone(), web::Path::from(200), query("reject", None), Principal::teacher(200))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::CONFLICT);
+        // 参数不合法, 转给自己或者转给不存在的老师
+        for bad in [query("reassign", None), query("reassign", Some(200)), query("reassign", Some(404)), query("drop", None)] {
+            let err = delete_teacher(app_state.clone(), web::Path::from(200), bad, Principal::admin(100)).await.unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        }
+        // 老师自己不能把课程转给其他老师
+        let err = delete_teacher(app_state.clone(), web::Path::from(200), query("reassign", Some(1)), Principal::teacher(200))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        assert!(app_state.teachers.get_teacher_details(200).await.is_ok());
+        let page = app_state.courses.get_courses_for_teacher(1, CourseFilter::default(), PageParams::default()).await.unwrap();
+        assert_eq!(page.total, 0);
+
+        // 管理员把课程转给老师 1
+        let res = delete_teacher(app_state.clone(), web::Path::from(200), query("reassign", Some(1)), Principal::admin(100))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(app_state.teachers.get_teacher_details(200).await.is_err());
+        let page = app_state.courses.get_courses_for_teacher(1, CourseFilter::default(), PageParams::default()).await.unwrap();
+        assert_eq!(page.total, 2);
+
+        // 老师 1 删除时课程一起删除
+        let res = delete_teacher(app_state.clone(), web::Path::from(1), query("cascade", None), Principal::teacher(1))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let trash = app_state.courses.get_deleted_courses(1, PageParams::default()).await.unwrap();
+        assert_eq!(trash.total, 2);
+        let history = app_state.courses.get_course_history(1, 1, PageParams::default()).await.unwrap();
+        let actions: Vec<&str> = history.items.iter().map(|entry| entry.action.as_str()).collect();
+        assert_eq!(actions, vec!["update", "delete"]);
+    }
+
+    #[actix_rt::test]
+    async fn reassigned_courses_keep_their_history() {
+        let app_state = mock_app_state();
+        let webhook = |teacher_id: Option<i32>| NewWebhook {
+            teacher_id,
+            url: "https://lms.example.com/hooks".into(),
+            events: vec!["course.updated".into(), "course.deleted".into()],
+            secret: "0123456789abcdef".into(),
+        };
+        let old_owner_hook = app_state.webhooks.create_webhook(webhook(Some(200)), &Actor::teacher(200)).await.unwrap();
+        let global_hook = app_state.webhooks.create_webhook(webhook(None), &Actor::teacher(1)).await.unwrap();
+        // 转移之前老师 200 修改过课程 1
+        let update = UpdateCourse {
+            name: None,
+            description: None,
+            format: None,
+            structure: None,
+            duration: None,
+            price: Some(99),
+            language: None,
+            level: None,
+        };
+        app_state.courses.update_course_details(200, 1, update.into(), &VersionCheck::any(), &Actor::teacher(200)).await.unwrap();
+
+        let query = web::Query(DeleteTeacherQuery { courses: Some("reassign".into()), reassign_to: Some(1) });
+        delete_teacher(app_state.clone(), web::Path::from(200), query, Principal::admin(100)).await.unwrap();
+
+        // 新的老师可以看到转移之前的记录
+        let history = app_state.courses.get_course_history(1, 1, PageParams::default()).await.unwrap();
+        let changes: Vec<(&str, i32)> = history.items.iter().map(|entry| (entry.action.as_str(), entry.teacher_id)).collect();
+        assert_eq!(changes, vec![("update", 200), ("update", 1)]);
+        assert_eq!(history.items[1].before.as_ref().unwrap()["teacher_id"], 200);
+        // 课程已经不属于原来的老师了
+        let history = app_state.courses.get_course_history(200, 1, PageParams::default()).await.unwrap();
+        assert_eq!(history.total, 0);
+
+        // 原来的老师的事件流中两门课程都是 move, 推送为 deleted
+        let events = app_state.courses.get_course_events(200, EventPosition::default(), 100).await.unwrap();
+        let moved: Vec<(i32, &str)> = events.iter().map(|entry| (entry.entity_id, entry.action.as_str())).collect();
+        assert_eq!(moved, vec![(1, "update"), (1, "move"), (2, "move")]);
+        let events = app_state.courses.get_course_events(1, EventPosition::default(), 100).await.unwrap();
+        assert_eq!(events.len(), 2);
+
+        // 原来的老师的 webhook 收到 course.deleted, 所有老师的 webhook 每次修改只收到一次
+        let page = app_state.webhooks.list_deliveries(old_owner_hook.id, None, PageParams::default()).await.unwrap();
+        let kinds: Vec<&str> = page.items.iter().map(|delivery| delivery.event_type.as_str()).collect();
+        assert_eq!(kinds, vec!["course.updated", "course.deleted", "course.deleted"]);
+        let page = app_state.webhooks.list_deliveries(global_hook.id, None, PageParams::default()).await.unwrap();
+        let kinds: Vec<&str> = page.items.iter().map(|delivery| delivery.event_type.as_str()).collect();
+        assert_eq!(kinds, vec!["course.updated", "course.updated", "course.updated"]);
+    }
+
+    #[actix_rt::test]
+    async fn restore_teacher_success_test() {
+        let app_state = mock_app_state();
+        delete_teacher(app_state.clone(), web::Path::from(100), web::Query(DeleteTeacherQuery::default()), Principal::teacher(100))
+            .await
+            .unwrap();
+        assert!(app_state.teachers.get_teacher_details(100).await.is_err());
+        let res = restore_teacher(app_state.clone(), web::Path::from(100), Principal::admin(1)).await.unwrap();
         assert_eq!(res.status(), StatusCode::OK);
+        assert!(app_state.teachers.get_teacher_details(100).await.is_ok());
+        // 删除和恢复都记录在变更历史中
+        let res = get_teacher_history(app_state.clone(), web::Path::from(100), web::Query(PageQuery::default()), Principal::teacher(100))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let err = get_teacher_history(app_state.clone(), web::Path::from(100), web::Query(PageQuery::default()), Principal::teacher(200))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        let history = app_state.teachers.get_teacher_history(100, Default::default()).await.unwrap();
+        let actions: Vec<&str> = history.items.iter().map(|entry| entry.action.as_str()).collect();
+        assert_eq!(actions, vec!["delete", "restore"]);
     }
 
     #[actix_rt::test]
     async fn update_teacher_success_test() {
-        dotenv().ok();
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not defined");
-        let db_pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
-        let app_state: web::Data<AppState> = web::Data::new(AppState {
-            health_check_response: "".to_string(),
-            visit_count: Mutex::new(0),
-            db: db_pool,
-        });
-
+        let app_state = mock_app_state();
         let update_teacher_json = web::Json(UpdateTeacher {
             name: Some("新李四".into()),
             picture_url: Some("https://commonresource-1252524126.cdn.xiaoeknow.com/image/l2y5zx530z40.png".into()),
             profile: None
         });
         let params: web::Path<i32> = web::Path::from(200);
-        let res = update_teacher_details(app_state, update_teacher_json, params).await.unwrap();
+        let res = update_teacher_details(app_state, update_teacher_json, params, VersionCheck::version(1), Principal::teacher(200))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn update_missing_teacher_returns_404() {
+        let app_state = mock_app_state();
+        let update_teacher_json = web::Json(UpdateTeacher { name: Some("不存在".into()), picture_url: None, profile: None });
+        let err = update_teacher_details(app_state, update_teacher_json, web::Path::from(999), VersionCheck::any(), Principal::admin(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+        assert!(matches!(err, MyError::NotFound(ref message) if message == "Teacher is not found"), "{:?}", err);
+    }
+
+    #[actix_rt::test]
+    async fn patch_teacher_clears_profile() {
+        let app_state = mock_app_state();
+        let patch: TeacherPatch = serde_json::from_value(serde_json::json!({ "profile": null })).unwrap();
+        let res = patch_teacher_details(app_state.clone(), web::Json(patch), web::Path::from(200), VersionCheck::version(1), Principal::teacher(200))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let teacher = app_state.teachers.get_teacher_details(200).await.unwrap();
+        assert_eq!((teacher.name.as_deref(), teacher.profile), (Some("老师200"), None));
+        // 头像地址同样要校验
+        let patch: TeacherPatch = serde_json::from_value(serde_json::json!({ "picture_url": "ftp://x" })).unwrap();
+        let err = patch_teacher_details(app_state, web::Json(patch), web::Path::from(200), VersionCheck::any(), Principal::teacher(200))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn teacher_list_etag_follows_content() {
+        let app_state = mock_app_state();
+        let list = |conditions| get_all_teachers(app_state.clone(), web::Query(PageQuery::default()), conditions);
+        let res = list(ConditionalGet::none()).await.unwrap();
+        let tag: EntityTag = res.headers().get("etag").unwrap().to_str().unwrap().parse().unwrap();
+        assert!(!res.headers().contains_key("last-modified"));
+        assert_eq!(list(ConditionalGet::if_none_match(tag.clone())).await.unwrap().status(), StatusCode::NOT_MODIFIED);
+
+        // 任意一位老师修改之后列表的 ETag 也会变化
+        let patch: TeacherPatch = serde_json::from_value(serde_json::json!({ "profile": "特级教师" })).unwrap();
+        patch_teacher_details(app_state.clone(), web::Json(patch), web::Path::from(100), VersionCheck::any(), Principal::teacher(100))
+            .await
+            .unwrap();
+        let res = list(ConditionalGet::if_none_match(tag.clone())).await.unwrap();
         assert_eq!(res.status(), StatusCode::OK);
+        assert_ne!(res.headers().get("etag").unwrap().to_str().unwrap(), tag.to_string());
     }
 }
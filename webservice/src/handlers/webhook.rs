@@ -0,0 +1,333 @@
+use crate::errors::MyError;
+use crate::state::AppState;
+use actix_web::{web, HttpResponse};
+
+use crate::auth::Principal;
+use crate::models::audit::Actor;
+use crate::models::pagination::PageQuery;
+use crate::models::webhook::{CreateWebhook, CreatedWebhook, DeliveryQuery, NewWebhook, Webhook, WEBHOOK_EVENTS};
+use crate::webhooks::generate_secret;
+
+// 老师只能管理自己的 webhook, 所有老师的 (teacher_id 为 null) 只有管理员可以
+fn authorize_webhook(principal: &Principal, teacher_id: Option<i32>) -> Result<(), MyError> {
+    match teacher_id {
+        Some(teacher_id) => principal.authorize(teacher_id),
+        None => principal.require_admin(),
+    }
+}
+
+// 读取 webhook 并检查权限, 查看投递记录和重新投递之前都要先调用
+async fn owned_webhook(app_state: &AppState, principal: &Principal, id: i32) -> Result<Webhook, MyError> {
+    let webhook = app_state.webhooks.get_webhook(id).await?;
+    authorize_webhook(principal, webhook.teacher_id)?;
+    Ok(webhook)
+}
+
+// * 注册 webhook, 响应中带有签名用的密钥, 之后不会再返回
+// ? 没有传 teacher_id 时订阅自己的课程和信息, 没有传 events 时订阅所有事件
+pub async fn create_webhook(
+    app_state: web::Data<AppState>,
+    new_webhook: web::Json<CreateWebhook>,
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let new_webhook = CreateWebhook::try_from(new_webhook)?;
+    let teacher_id = new_webhook.teacher_id.unwrap_or(Some(principal.teacher_id));
+    authorize_webhook(&principal, teacher_id)?;
+    if let Some(teacher_id) = teacher_id {
+        // 老师不存在时返回 404
+        app_state.teachers.get_teacher_details(teacher_id).await?;
+    }
+    app_state.webhook_policy.check_target(&new_webhook.url)?;
+
+    let mut events = match new_webhook.events.is_empty() {
+        true => WEBHOOK_EVENTS.iter().map(|event| event.to_string()).collect(),
+        false => new_webhook.events,
+    };
+    events.sort();
+    events.dedup();
+    let secret = new_webhook.secret.unwrap_or_else(generate_secret);
+    let webhook = NewWebhook { teacher_id, url: new_webhook.url, events, secret: secret.clone() };
+    app_state
+        .webhooks
+        .create_webhook(webhook, &Actor::from(principal))
+        .await
+        .map(|webhook| HttpResponse::Ok().json(CreatedWebhook { webhook, secret }))
+}
+
+// * webhook 列表, 老师只能看到自己的, 管理员可以看到所有的
+pub async fn get_webhooks(
+    app_state: web::Data<AppState>,
+    page: web::Query<PageQuery>,
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let teacher_id = principal.require_admin().err().map(|_err| principal.teacher_id);
+    app_state
+        .webhooks
+        .list_webhooks(teacher_id, page.into_inner().try_into()?)
+        .await
+        .map(|webhooks| HttpResponse::Ok().json(webhooks))
+}
+
+pub async fn get_webhook(
+    app_state: web::Data<AppState>,
+    params: web::Path<i32>,
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let webhook = owned_webhook(&app_state, &principal, params.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(webhook))
+}
+
+// * 删除 webhook, 还没有发送的投递不再发送, 成功返回 204
+pub async fn delete_webhook(
+    app_state: web::Data<AppState>,
+    params: web::Path<i32>,
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let webhook = owned_webhook(&app_state, &principal, params.into_inner()).await?;
+    app_state.webhooks.delete_webhook(webhook.id).await.map(|_| HttpResponse::NoContent().finish())
+}
+
+// * 投递记录, ?status=dead 查看死信
+pub async fn get_webhook_deliveries(
+    app_state: web::Data<AppState>,
+    params: web::Path<i32>,
+    query: web::Query<DeliveryQuery>,
+    page: web::Query<PageQuery>,
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let webhook = owned_webhook(&app_state, &principal, params.into_inner()).await?;
+    app_state
+        .webhooks
+        .list_deliveries(webhook.id, query.into_inner().try_into()?, page.into_inner().try_into()?)
+        .await
+        .map(|deliveries| HttpResponse::Ok().json(deliveries))
+}
+
+// * 一次投递的详情, 包括发送的请求体和每一次请求的结果
+pub async fn get_webhook_delivery(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let (webhook_id, delivery_id) = params.into_inner();
+    owned_webhook(&app_state, &principal, webhook_id).await?;
+    app_state
+        .webhooks
+        .get_delivery(webhook_id, delivery_id)
+        .await
+        .map(|delivery| HttpResponse::Ok().json(delivery))
+}
+
+// * 重新投递, 由投递任务在下一次检查时发送, 所以返回 202
+pub async fn redeliver_webhook_delivery(
+    app_state: web::Data<AppState>,
+    params: web::Path<(i32, i32)>,
+    principal: Principal,
+) -> Result<HttpResponse, MyError> {
+    let (webhook_id, delivery_id) = params.into_inner();
+    owned_webhook(&app_state, &principal, webhook_id).await?;
+    app_state
+        .webhooks
+        .redeliver(webhook_id, delivery_id)
+        .await
+        .map(|delivery| HttpResponse::Accepted().json(delivery))
+}
+
+// * 测试
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::course::CreateCourse;
+    use crate::models::teacher::Teacher;
+    use crate::webhooks::{client, deliver_due, sign, WebhookPolicy};
+    use actix_web::body::to_bytes;
+    use actix_web::http::StatusCode;
+    use actix_web::{App, HttpRequest, HttpServer, ResponseError};
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    // 预先放入 id 为 1 和 2 的两位老师, 没有课程
+    fn mock_app_state(webhook_policy: WebhookPolicy) -> web::Data<AppState> {
+        let teachers = [1, 2]
+            .into_iter()
+            .map(|id| Teacher {
+                id,
+                name: Some(format!("老师{}", id)),
+                picture_url: None,
+                profile: None,
+                deleted_at: None,
+                version: 1,
+                updated_at: chrono::Utc::now(),
+            })
+            .collect();
+        AppState::for_tests().teachers(teachers).webhook_policy(webhook_policy).build()
+    }
+
+    fn create_json(value: Value) -> web::Json<CreateWebhook> {
+        web::Json(serde_json::from_value(value).unwrap())
+    }
+
+    async fn json_body(res: HttpResponse) -> Value {
+        serde_json::from_slice(&to_bytes(res.into_body()).await.unwrap()).unwrap()
+    }
+
+    // 收到的请求: 请求头和请求体
+    type Received = Arc<Mutex<Vec<(actix_web::http::header::HeaderMap, web::Bytes)>>>;
+
+    // 在本机随机端口启动一个接收 webhook 的服务, 前 failures 个请求返回 500, 之后返回 204
+    fn start_receiver(failures: usize) -> (String, Received) {
+        let received: Received = Arc::default();
+        let recorded = received.clone();
+        let server = HttpServer::new(move || {
+            let recorded = recorded.clone();
+            App::new().default_service(web::to(move |req: HttpRequest, body: web::Bytes| {
+                let recorded = recorded.clone();
+                async move {
+                    let mut recorded = recorded.lock().unwrap();
+                    recorded.push((req.headers().clone(), body));
+                    match recorded.len() <= failures {
+                        true => HttpResponse::InternalServerError().finish(),
+                        false => HttpResponse::NoContent().finish(),
+                    }
+                }
+            }))
+        })
+        .workers(1)
+        .disable_signals()
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let url = format!("http://{}/hooks", server.addrs()[0]);
+        actix_rt::spawn(server.run());
+        (url, received)
+    }
+
+    fn new_course(teacher_id: i32, name: &str) -> CreateCourse {
+        serde_json::from_value(json!({ "teacher_id": teacher_id, "name": name })).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn deliveries_are_signed_retried_dead_lettered_and_redelivered() {
+        let (url, received) = start_receiver(2);
+        let policy = WebhookPolicy {
+            max_attempts: 2,
+            initial_backoff: Duration::ZERO,
+            timeout: Duration::from_secs(2),
+            allow_private_targets: true,
+            ..WebhookPolicy::default()
+        };
+        let app_state = mock_app_state(policy.clone());
+        let client = client(&policy);
+        let secret = "0123456789abcdef-secret";
+        let body = json!({ "url": url, "events": ["course.created"], "secret": secret });
+        let res = create_webhook(app_state.clone(), create_json(body), Principal::teacher(1)).await.unwrap();
+        let webhook = json_body(res).await;
+        assert_eq!(webhook["teacher_id"], 1);
+        assert_eq!(webhook["secret"], secret);
+        let webhook_id = webhook["id"].as_i64().unwrap() as i32;
+
+        // 没有订阅的事件和其他老师的课程不投递
+        app_state.courses.post_new_course(new_course(2, "别人的课程"), &Actor::teacher(2)).await.unwrap();
+        let course = app_state.courses.post_new_course(new_course(1, "Rust 入门"), &Actor::teacher(1)).await.unwrap();
+
+        // 第一次和重试都返回 500, 超过 max_attempts 之后进入死信
+        assert_eq!(deliver_due(&app_state, &client).await.unwrap(), 1);
+        assert_eq!(deliver_due(&app_state, &client).await.unwrap(), 1);
+        assert_eq!(deliver_due(&app_state, &client).await.unwrap(), 0);
+        let query = web::Query(DeliveryQuery { status: Some("dead".into()) });
+        let res = get_webhook_deliveries(app_state.clone(), web::Path::from(webhook_id), query, web::Query(PageQuery::default()), Principal::teacher(1))
+            .await
+            .unwrap();
+        let dead = json_body(res).await;
+        assert_eq!(dead["total"], 1);
+        assert_eq!(dead["items"][0]["attempts"], 2);
+        assert_eq!(dead["items"][0]["last_status_code"], 500);
+        let delivery_id = dead["items"][0]["id"].as_i64().unwrap() as i32;
+
+        // 手动重新投递, 这次对方返回 204
+        let res = redeliver_webhook_delivery(app_state.clone(), web::Path::from((webhook_id, delivery_id)), Principal::teacher(1)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert_eq!(deliver_due(&app_state, &client).await.unwrap(), 1);
+        let res = get_webhook_delivery(app_state.clone(), web::Path::from((webhook_id, delivery_id)), Principal::teacher(1)).await.unwrap();
+        let detail = json_body(res).await;
+        assert_eq!(detail["status"], "delivered");
+        assert_eq!(detail["attempts"].as_array().unwrap().len(), 3);
+        assert_eq!(detail["payload"]["type"], "course.created");
+
+        // 三次请求的内容相同, 都带有签名
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        let (headers, body) = received.last().unwrap();
+        let header = |name: &str| headers.get(name).unwrap().to_str().unwrap().to_string();
+        assert_eq!(header("x-webhook-event"), "course.created");
+        assert_eq!(header("x-webhook-id"), delivery_id.to_string());
+        assert_eq!(header("content-type"), "application/json");
+        let timestamp: i64 = header("x-webhook-timestamp").parse().unwrap();
+        assert_eq!(header("x-webhook-signature"), sign(secret, timestamp, body));
+        let payload: Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload, detail["payload"]);
+        assert_eq!(payload["teacher_id"], 1);
+        assert_eq!(payload["actor"], "teacher:1");
+        assert_eq!(payload["data"]["id"], course.id);
+        assert_eq!(payload["data"]["name"], "Rust 入门");
+        assert_eq!(received[0].1, *body);
+    }
+
+    #[actix_rt::test]
+    async fn webhooks_are_scoped_to_their_teacher() {
+        let app_state = mock_app_state(WebhookPolicy::default());
+        let url = "https://lms.example.com/hooks";
+
+        // 不能为其他老师或者所有老师注册, 管理员可以
+        let err = create_webhook(app_state.clone(), create_json(json!({ "url": url, "teacher_id": 2 })), Principal::teacher(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        let err = create_webhook(app_state.clone(), create_json(json!({ "url": url, "teacher_id": null })), Principal::teacher(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        let res = create_webhook(app_state.clone(), create_json(json!({ "url": url, "teacher_id": null })), Principal::admin(2))
+            .await
+            .unwrap();
+        let global = json_body(res).await;
+        assert_eq!(global["teacher_id"], Value::Null);
+        assert_eq!(global["events"].as_array().unwrap().len(), WEBHOOK_EVENTS.len());
+        assert!(global["secret"].as_str().unwrap().starts_with("whsec_"));
+
+        // 不合法的事件, 内网地址和不存在的老师
+        let err = create_webhook(app_state.clone(), create_json(json!({ "url": url, "events": ["course.renamed"] })), Principal::teacher(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        let err = create_webhook(app_state.clone(), create_json(json!({ "url": "http://127.0.0.1:8080/" })), Principal::teacher(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        let err = create_webhook(app_state.clone(), create_json(json!({ "url": url, "teacher_id": 99 })), Principal::admin(2))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+
+        let res = create_webhook(app_state.clone(), create_json(json!({ "url": url })), Principal::teacher(1)).await.unwrap();
+        let own = json_body(res).await;
+        let own_id = own["id"].as_i64().unwrap() as i32;
+        // 其他老师看不到, 也不能删除
+        let err = get_webhook(app_state.clone(), web::Path::from(own_id), Principal::teacher(2)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        let err = delete_webhook(app_state.clone(), web::Path::from(own_id), Principal::teacher(2)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+        // 列表中老师只有自己的, 管理员有所有的, 都不包含密钥
+        let res = get_webhooks(app_state.clone(), web::Query(PageQuery::default()), Principal::teacher(1)).await.unwrap();
+        let list = json_body(res).await;
+        assert_eq!(list["total"], 1);
+        assert!(list["items"][0].get("secret").is_none());
+        let res = get_webhooks(app_state.clone(), web::Query(PageQuery::default()), Principal::admin(2)).await.unwrap();
+        assert_eq!(json_body(res).await["total"], 2);
+
+        let res = delete_webhook(app_state.clone(), web::Path::from(own_id), Principal::teacher(1)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let err = get_webhook(app_state, web::Path::from(own_id), Principal::teacher(1)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,313 @@
+// 请求计数, 替代原来 AppState 中用 Mutex 保护的 visit_count
+// 所有计数器都是原子变量, 每个请求只做几次原子加减, 不需要加锁
+// 另外按路由统计请求数和耗时, 按错误码统计错误数, 由 GET /metrics 以 Prometheus 的文本格式输出
+use crate::errors::error_code;
+use crate::models::health::PoolStats;
+use crate::state::AppState;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{web, Error};
+use futures_util::future::LocalBoxFuture;
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::{ready, Ready};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// 按状态码的类别计数, 下标 0 是 1xx, 4 是 5xx
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+// 没有匹配到路由的请求统一记为这个 route, 避免随意的路径让标签无限增长
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+pub struct Metrics {
+    started_at: Instant,
+    requests_total: AtomicU64,
+    requests_in_flight: AtomicI64,
+    responses: [AtomicU64; 5],
+    prometheus: PrometheusMetrics,
+}
+
+/**
+ * Prometheus 的指标
+ * ? route 是路由的模板, 例如 /courses/{teacher_id}, 而不是实际的路径, 否则每个 id 都会产生一组新的时间序列
+ * ? 进行中的请求数, 连接池和运行时间在抓取时才更新, 所以用 Gauge
+ */
+struct PrometheusMetrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    duration: HistogramVec,
+    errors: IntCounterVec,
+    in_flight: IntGauge,
+    uptime: Gauge,
+    pool_connections: IntGaugeVec,
+    pool_max_connections: IntGauge,
+}
+
+impl PrometheusMetrics {
+    fn new() -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of HTTP requests by method, route and status"),
+            &["method", "route", "status"],
+        )
+        .expect("valid metric");
+        let duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by method and route"),
+            &["method", "route"],
+        )
+        .expect("valid metric");
+        let errors = IntCounterVec::new(
+            Opts::new("http_errors_total", "Number of error responses by error code"),
+            &["code"],
+        )
+        .expect("valid metric");
+        let in_flight = IntGauge::new("http_requests_in_flight", "Number of HTTP requests being handled").expect("valid metric");
+        let uptime = Gauge::new("process_uptime_seconds", "Seconds since the service started").expect("valid metric");
+        let pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database pool connections by state"),
+            &["state"],
+        )
+        .expect("valid metric");
+        let pool_max_connections =
+            IntGauge::new("db_pool_max_connections", "Maximum number of database pool connections").expect("valid metric");
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone())).expect("unique metric");
+        registry.register(Box::new(duration.clone())).expect("unique metric");
+        registry.register(Box::new(errors.clone())).expect("unique metric");
+        registry.register(Box::new(in_flight.clone())).expect("unique metric");
+        registry.register(Box::new(uptime.clone())).expect("unique metric");
+        registry.register(Box::new(pool_connections.clone())).expect("unique metric");
+        registry.register(Box::new(pool_max_connections.clone())).expect("unique metric");
+        PrometheusMetrics { registry, requests, duration, errors, in_flight, uptime, pool_connections, pool_max_connections }
+    }
+}
+
+// 某一时刻的计数, 各个计数器分别读取, 彼此之间不保证完全一致
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub requests_total: u64,
+    pub requests_in_flight: i64,
+    pub responses: BTreeMap<&'static str, u64>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            started_at: Instant::now(),
+            requests_total: AtomicU64::new(0),
+            requests_in_flight: AtomicI64::new(0),
+            responses: Default::default(),
+            prometheus: PrometheusMetrics::new(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    fn request_started(&self) {
+        self.requests_total.fetch_add(1, Ordering::Relaxed);
+        self.requests_in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn request_finished(&self) {
+        self.requests_in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn record_response(&self, status: StatusCode) {
+        let class = (status.as_u16() / 100).clamp(1, 5) as usize - 1;
+        self.responses[class].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // 一个请求结束, 按路由记录状态码和耗时, 出错时按错误码计数
+    fn record_request(&self, method: &Method, route: &str, status: StatusCode, elapsed: Duration, error: Option<&str>) {
+        let prometheus = &self.prometheus;
+        prometheus
+            .requests
+            .with_label_values(&[method.as_str(), route, status.as_str()])
+            .inc();
+        prometheus
+            .duration
+            .with_label_values(&[method.as_str(), route])
+            .observe(elapsed.as_secs_f64());
+        if let Some(code) = error {
+            prometheus.errors.with_label_values(&[code]).inc();
+        }
+    }
+
+    /**
+     * 输出 Prometheus 的文本格式
+     * ? pool 是抓取时连接池的使用情况, 内存存储没有连接池, 不输出连接池的指标
+     */
+    pub fn render(&self, pool: Option<PoolStats>) -> Result<String, String> {
+        let prometheus = &self.prometheus;
+        prometheus.in_flight.set(self.requests_in_flight.load(Ordering::Relaxed));
+        prometheus.uptime.set(self.uptime().as_secs_f64());
+        if let Some(pool) = &pool {
+            prometheus.pool_connections.with_label_values(&["idle"]).set(pool.idle as i64);
+            prometheus.pool_connections.with_label_values(&["in_use"]).set(pool.in_use as i64);
+            prometheus.pool_max_connections.set(pool.max_connections as i64);
+        }
+        let mut families = prometheus.registry.gather();
+        if pool.is_none() {
+            families.retain(|family| !family.get_name().starts_with("db_pool_"));
+        }
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&families, &mut buf).map_err(|err| err.to_string())?;
+        String::from_utf8(buf).map_err(|err| err.to_string())
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            requests_total: self.requests_total.load(Ordering::Relaxed),
+            requests_in_flight: self.requests_in_flight.load(Ordering::Relaxed),
+            responses: STATUS_CLASSES
+                .iter()
+                .zip(&self.responses)
+                .map(|(class, count)| (*class, count.load(Ordering::Relaxed)))
+                .collect(),
+        }
+    }
+}
+
+// 请求结束 (包括客户端断开, future 被丢弃) 时减少进行中的请求数
+struct InFlight(web::Data<AppState>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.metrics.request_finished();
+    }
+}
+
+// 统计请求数, 响应的状态码和耗时
+// ? 需要挂在 AssignRequestId 的里面, 这样还能拿到响应中的错误, 按错误码计数
+pub struct RecordMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RecordMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RecordMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RecordMetricsMiddleware { service }))
+    }
+}
+
+pub struct RecordMetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RecordMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let app_state = match req.app_data::<web::Data<AppState>>() {
+            Some(app_state) => app_state.clone(),
+            None => return Box::pin(self.service.call(req)),
+        };
+        app_state.metrics.request_started();
+        let in_flight = InFlight(app_state);
+        let method = req.method().clone();
+        let started = Instant::now();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let result = fut.await;
+            // 路由匹配之后才知道路由的模板, 内层直接返回错误时拿不到请求
+            let (status, route, error) = match &result {
+                Ok(res) => (
+                    res.status(),
+                    res.request().match_pattern().unwrap_or_else(|| UNMATCHED_ROUTE.to_string()),
+                    res.response().error().map(error_code),
+                ),
+                Err(err) => (err.as_response_error().status_code(), UNMATCHED_ROUTE.to_string(), Some(error_code(err))),
+            };
+            let metrics = &in_flight.0.metrics;
+            metrics.record_response(status);
+            metrics.record_request(&method, &route, status, started.elapsed(), error);
+            drop(in_flight);
+            result
+        })
+    }
+}
+
+// * 测试
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_access::postgres::{PgCourseRepository, PgTeacherRepository, PgWebhookRepository};
+    use crate::errors::MyError;
+    use crate::routers::general_routes;
+    use actix_web::{test, App, HttpResponse};
+    use sqlx::postgres::PgPoolOptions;
+    use std::sync::Arc;
+
+    // 使用不会真正连接的 postgres 连接池, 读取连接池的使用情况不需要访问数据库
+    fn lazy_pool_app_state() -> web::Data<AppState> {
+        let pool = PgPoolOptions::new().connect_lazy("postgres://metrics@127.0.0.1:1/metrics").unwrap();
+        AppState::for_tests()
+            .repositories(
+                Arc::new(PgCourseRepository::new(pool.clone(), 10)),
+                Arc::new(PgTeacherRepository::new(pool.clone())),
+                Arc::new(PgWebhookRepository::new(pool)),
+            )
+            .build()
+    }
+
+    async fn missing_course() -> Result<HttpResponse, MyError> {
+        Err(MyError::NotFound("Course is not found".into()))
+    }
+
+    #[actix_rt::test]
+    async fn prometheus_text_has_route_templates_error_codes_and_pool_gauges() {
+        let app = test::init_service(
+            App::new()
+                .app_data(lazy_pool_app_state())
+                .configure(general_routes)
+                .route("/courses/{teacher_id}/{course_id}", web::get().to(missing_course))
+                .wrap(RecordMetrics),
+        )
+        .await;
+        for uri in ["/courses/1/42", "/courses/2/7", "/no/such/route"] {
+            let res = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        }
+
+        let body = test::call_and_read_body(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        // 不同的 id 计入同一个路由模板, 实际的路径不会成为标签
+        assert!(body.contains(r#"http_requests_total{method="GET",route="/courses/{teacher_id}/{course_id}",status="404"} 2"#));
+        assert!(body.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#));
+        assert!(!body.contains("/courses/1/42"));
+        // 只有 handler 返回的错误有错误码
+        assert!(body.contains(r#"http_errors_total{code="not_found"} 2"#));
+        // 连接池还没有建立连接
+        assert!(body.contains(r#"db_pool_connections{state="idle"} 0"#));
+        assert!(body.contains(r#"db_pool_connections{state="in_use"} 0"#));
+        assert!(body.contains("db_pool_max_connections 10"));
+    }
+}
@@ -0,0 +1,171 @@
+// 数据库迁移
+// migrations 目录下的 sql 文件会在编译时通过 sqlx::migrate! 嵌入到二进制中
+// 所以部署时不需要额外拷贝 sql 文件, 新的开发数据库执行一次 migrate up 就和代码的期望一致
+// ? 每个迁移都有 .up.sql 和 .down.sql 两个文件, 版本号就是文件名前面的时间戳
+use crate::errors::MyError;
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::postgres::PgPool;
+use std::collections::HashSet;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+impl From<MigrateError> for MyError {
+    fn from(err: MigrateError) -> Self {
+        MyError::DBError(err.to_string())
+    }
+}
+
+// migrate 子命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrateCommand {
+    Up,
+    Down(Option<i64>),
+    Status,
+}
+
+impl MigrateCommand {
+    // args 是 migrate 之后的参数, 例如 ["down", "20230201000000"]
+    pub fn parse(args: &[String]) -> Result<MigrateCommand, MyError> {
+        match args.first().map(String::as_str) {
+            Some("up") => Ok(MigrateCommand::Up),
+            Some("down") => {
+                let target = match args.get(1) {
+                    Some(target) => Some(target.parse::<i64>().map_err(|_err| {
+                        MyError::InvalidInput(format!("Invalid migration version: {}", target))
+                    })?),
+                    None => None,
+                };
+                Ok(MigrateCommand::Down(target))
+            }
+            Some("status") => Ok(MigrateCommand::Status),
+            _ => Err(MyError::InvalidInput(
+                "Usage: teacher-service migrate <up|down [version]|status>".into(),
+            )),
+        }
+    }
+}
+
+// 单个迁移的状态, 用于 migrate status 输出
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+// 执行所有还没有执行过的迁移
+pub async fn migrate_up(pool: &PgPool) -> Result<(), MyError> {
+    MIGRATOR.run(pool).await?;
+    Ok(())
+}
+
+// 回滚迁移
+// target 为 None 时只回滚最后一个已执行的迁移, 否则回滚所有版本号大于 target 的迁移
+pub async fn migrate_down(pool: &PgPool, target: Option<i64>) -> Result<(), MyError> {
+    let target = match target {
+        Some(target) => target,
+        None => match previous_version(applied_versions(pool).await?) {
+            Some(target) => target,
+            None => return Ok(()),
+        },
+    };
+    MIGRATOR.undo(pool, target).await?;
+    Ok(())
+}
+
+// 没有指定版本时回滚到的版本: 最后一个已执行的迁移的前一个, 只有一个时为 0, 一个都没有执行时不需要回滚
+fn previous_version(applied: HashSet<i64>) -> Option<i64> {
+    let mut versions: Vec<i64> = applied.into_iter().collect();
+    versions.sort_unstable();
+    match versions.as_slice() {
+        [] => None,
+        [_] => Some(0),
+        [.., previous, _] => Some(*previous),
+    }
+}
+
+// 列出所有迁移以及是否已经执行
+pub async fn migrate_status(pool: &PgPool) -> Result<Vec<MigrationStatus>, MyError> {
+    let applied = applied_versions(pool).await?;
+    Ok(MIGRATOR
+        .iter()
+        // 每个迁移都有 up 和 down 两条记录, 只看 up 的即可
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            description: migration.description.to_string(),
+            applied: applied.contains(&migration.version),
+        })
+        .collect())
+}
+
+async fn applied_versions(pool: &PgPool) -> Result<HashSet<i64>, MyError> {
+    let mut conn = pool.acquire().await?;
+    // 第一次执行时 _sqlx_migrations 表可能还不存在
+    conn.ensure_migrations_table().await?;
+    Ok(conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect())
+}
+
+// 处理 teacher-service migrate <up|down|status> 子命令
+// args 是 migrate 之后的参数, 例如 ["down", "20230201000000"]
+pub async fn run_migrate_command(pool: &PgPool, args: &[String]) -> Result<(), MyError> {
+    match MigrateCommand::parse(args)? {
+        MigrateCommand::Up => {
+            migrate_up(pool).await?;
+            println!("迁移已全部执行");
+        }
+        MigrateCommand::Down(target) => {
+            migrate_down(pool, target).await?;
+            println!("迁移已回滚");
+        }
+        MigrateCommand::Status => {
+            for status in migrate_status(pool).await? {
+                println!(
+                    "{} {} {}",
+                    status.version,
+                    if status.applied { "applied" } else { "pending" },
+                    status.description
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// * 测试
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<MigrateCommand, MyError> {
+        MigrateCommand::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn migrate_arguments_are_parsed() {
+        assert_eq!(parse(&["up"]).unwrap(), MigrateCommand::Up);
+        assert_eq!(parse(&["status"]).unwrap(), MigrateCommand::Status);
+        assert_eq!(parse(&["down"]).unwrap(), MigrateCommand::Down(None));
+        assert_eq!(parse(&["down", "20230201000000"]).unwrap(), MigrateCommand::Down(Some(20230201000000)));
+
+        // 没有子命令或者不认识的子命令时提示用法
+        for args in [&[][..], &["sideways"][..]] {
+            let err = parse(args).unwrap_err();
+            assert!(matches!(err, MyError::InvalidInput(ref message) if message.starts_with("Usage:")), "{:?}", err);
+        }
+        let err = parse(&["down", "latest"]).unwrap_err();
+        assert!(matches!(err, MyError::InvalidInput(ref message) if message == "Invalid migration version: latest"));
+    }
+
+    #[test]
+    fn down_without_version_reverts_the_last_migration() {
+        assert_eq!(previous_version(HashSet::new()), None);
+        assert_eq!(previous_version(HashSet::from([20230201000000])), Some(0));
+        let applied = HashSet::from([20230203000000, 20230201000000, 20230202000000]);
+        assert_eq!(previous_version(applied), Some(20230202000000));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::auth::Principal;
+use crate::errors::MyError;
+use crate::models::course::Course;
+use crate::models::teacher::Teacher;
+use actix_web::{dev::Payload, FromRequest, HttpMessage, HttpRequest};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use serde_json::Value;
+use std::future::{ready, Ready};
+
+// 发起变更的操作人
+// ? 登录之后是 teacher:<id>, 修改类的请求都需要登录, 只有注册这样的公开接口会是 anonymous
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Actor(pub String);
+
+impl Actor {
+    pub fn anonymous() -> Self {
+        Actor("anonymous".into())
+    }
+
+    pub fn teacher(teacher_id: i32) -> Self {
+        Actor(format!("teacher:{}", teacher_id))
+    }
+
+    // 后台任务, 例如定时清理回收站
+    pub fn system() -> Self {
+        Actor("system".into())
+    }
+}
+
+// 管理员也是老师, 同样记录为 teacher:<id>
+impl From<Principal> for Actor {
+    fn from(principal: Principal) -> Self {
+        Actor::teacher(principal.teacher_id)
+    }
+}
+
+// 实现 FromRequest 之后, handler 的参数里直接写 actor: Actor 即可
+impl FromRequest for Actor {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        // 认证中间件已经把登录身份放进了 extensions
+        let actor = match req.extensions().get::<Principal>() {
+            Some(principal) => Actor::from(*principal),
+            None => Actor::anonymous(),
+        };
+        ready(Ok(actor))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    Purge, // 从回收站彻底删除
+    Move,  // 课程转给了其他老师, 写在原来的老师名下, 变更历史中不显示
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+            AuditAction::Restore => "restore",
+            AuditAction::Purge => "purge",
+            AuditAction::Move => "move",
+        }
+    }
+}
+
+// 待写入的变更记录, id 和 created_at 由数据库生成
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    pub entity_type: &'static str,
+    pub entity_id: i32,
+    pub teacher_id: i32,
+    pub action: AuditAction,
+    pub actor: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+// 把记录序列化为 JSON 快照, 字段和接口返回的一致
+fn snapshot<T: Serialize>(value: Option<&T>) -> Option<Value> {
+    value.and_then(|value| serde_json::to_value(value).ok())
+}
+
+impl AuditRecord {
+    pub fn course(action: AuditAction, actor: &Actor, before: Option<&Course>, after: Option<&Course>) -> Self {
+        // before 和 after 至少有一个, 从中取出课程的 id 和所属老师
+        // ? 优先取 after, 课程转给其他老师之后, 这条记录属于新的老师
+        let course = after.or(before).expect("audit record needs a before or after course");
+        AuditRecord {
+            entity_type: "course",
+            entity_id: course.id,
+            teacher_id: course.teacher_id,
+            action,
+            actor: actor.0.clone(),
+            before: snapshot(before),
+            after: snapshot(after),
+        }
+    }
+
+    /**
+     * 课程转给其他老师时, 在原来的老师名下再写一条 move
+     * ? course() 写的 update 属于新的老师, 原来的老师的 SSE 和 webhook 只能通过这条知道课程被移走了
+     */
+    pub fn course_moved(actor: &Actor, before: &Course, after: &Course) -> Self {
+        AuditRecord { teacher_id: before.teacher_id, ..AuditRecord::course(AuditAction::Move, actor, Some(before), Some(after)) }
+    }
+
+    pub fn teacher(action: AuditAction, actor: &Actor, before: Option<&Teacher>, after: Option<&Teacher>) -> Self {
+        let teacher = before.or(after).expect("audit record needs a before or after teacher");
+        AuditRecord {
+            entity_type: "teacher",
+            entity_id: teacher.id,
+            teacher_id: teacher.id,
+            action,
+            actor: actor.0.clone(),
+            before: snapshot(before),
+            after: snapshot(after),
+        }
+    }
+}
+
+// 变更历史接口返回的一条记录
+#[derive(Serialize, Debug, Clone, sqlx::FromRow)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub entity_type: String,
+    pub entity_id: i32,
+    pub teacher_id: i32,
+    pub action: String,
+    pub actor: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub created_at: NaiveDateTime,
+    // 提交的顺序, 只用于 SSE 按提交的顺序推送, 接口不返回
+    // ? 在事务提交之前分配, 写入它的事务中读到的是 None (见 audit_log_commit_seq 迁移)
+    #[serde(skip)]
+    pub commit_seq: Option<i64>,
+}
+
+/**
+ * SSE 推送到的位置, 也就是推送过的最后一条记录的 commit_seq
+ * ? 同一个事务写入的记录按 id 的顺序分配, 不同事务按提交的顺序, 见 get_course_events_db
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EventPosition {
+    pub seq: i64,
+}
+
+impl From<&AuditEntry> for EventPosition {
+    fn from(entry: &AuditEntry) -> Self {
+        EventPosition { seq: entry.commit_seq.unwrap_or_default() }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::errors::MyError;
+use crate::models::teacher::CreateTeacher;
+use crate::models::validation::{http_url, not_blank};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use validator::Validate;
+
+// 注册老师, 老师信息加上登录账号, 老师信息的校验规则和 CreateTeacher 一致
+#[derive(Deserialize, Debug, Clone, Validate)]
+pub struct RegisterTeacher {
+    #[validate(length(min = 1, max = 200), custom = "not_blank")]
+    pub name: String,
+    #[validate(length(max = 200), custom = "http_url")]
+    pub picture_url: String,
+    #[validate(length(max = 2000))]
+    pub profile: String,
+    #[validate(length(min = 3, max = 100))]
+    pub username: String,
+    #[validate(length(min = 8, max = 128))]
+    pub password: String,
+}
+
+// 校验之后的注册信息, 密码还没有哈希
+#[derive(Debug, Clone)]
+pub struct NewAccount {
+    pub teacher: CreateTeacher,
+    pub username: String,
+    pub password: String,
+}
+
+impl TryFrom<RegisterTeacher> for NewAccount {
+    type Error = MyError;
+
+    fn try_from(register: RegisterTeacher) -> Result<Self, Self::Error> {
+        // 用户名去掉首尾空白之后再校验
+        let register = RegisterTeacher { username: register.username.trim().to_string(), ..register };
+        register.validate()?;
+        Ok(NewAccount {
+            teacher: CreateTeacher {
+                name: register.name,
+                picture_url: register.picture_url,
+                profile: register.profile,
+            },
+            username: register.username,
+            password: register.password,
+        })
+    }
+}
+
+// 账号的角色, 签发 token 时写入 token 中
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Teacher, // 只能修改自己名下的数据
+    Admin, // 可以修改所有老师的数据
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Teacher => "teacher",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+// 数据库中保存的账号
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Account {
+    pub teacher_id: i32,
+    pub username: String,
+    pub password_hash: String,
+    pub role: String,
+}
+
+impl Account {
+    // 不认识的角色按权限最小的 teacher 处理
+    pub fn role(&self) -> Role {
+        match self.role.as_str() {
+            "admin" => Role::Admin,
+            _ => Role::Teacher,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+// 登录和刷新 token 的响应
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String, // 固定为 Bearer
+    pub expires_in: i64,    // access_token 的有效期, 单位秒
+}
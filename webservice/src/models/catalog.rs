@@ -0,0 +1,238 @@
+// 课程目录的批量导入和导出, 支持 CSV 和 NDJSON (每行一个 JSON)
+use crate::errors::MyError;
+use crate::models::course::{Course, CreateCourse};
+use std::str::FromStr;
+use validator::Validate;
+use actix_web::web::Bytes;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+// 一次最多导入的行数, 超过需要拆成多个文件
+pub const MAX_IMPORT_ROWS: usize = 1000;
+// 导入请求体的大小限制
+pub const MAX_IMPORT_BYTES: usize = 5 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatalogFormat {
+    Csv,
+    Ndjson,
+}
+
+impl CatalogFormat {
+    // 导入时根据 Content-Type 判断格式
+    pub fn from_content_type(content_type: &str) -> Result<Self, MyError> {
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+        match mime.as_str() {
+            "text/csv" => Ok(CatalogFormat::Csv),
+            "application/x-ndjson" | "application/ndjson" | "application/jsonl" | "application/x-jsonlines" => {
+                Ok(CatalogFormat::Ndjson)
+            }
+            _ => Err(MyError::InvalidInput(
+                "Content-Type must be text/csv or application/x-ndjson".into(),
+            )),
+        }
+    }
+
+    // 导出时根据 ?format=csv|ndjson 判断格式, 默认 csv
+    pub fn from_query(format: Option<&str>) -> Result<Self, MyError> {
+        match format.map(str::to_lowercase).as_deref() {
+            None | Some("csv") => Ok(CatalogFormat::Csv),
+            Some("ndjson") | Some("jsonl") => Ok(CatalogFormat::Ndjson),
+            Some(other) => Err(MyError::InvalidInput(format!(
+                "Unknown export format: {}, expected csv or ndjson",
+                other
+            ))),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            CatalogFormat::Csv => "text/csv; charset=utf-8",
+            CatalogFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+// 导出的查询参数, /courses/1/export?format=ndjson
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ExportQuery {
+    pub format: Option<String>,
+}
+
+// 导入文件中的一行, 列和 CreateCourse 一致, teacher_id 取自路径
+// ? 导出文件中多出来的 id, time, version 等列会被忽略, 所以导出的文件可以直接再导入
+#[derive(Deserialize, Debug, Clone)]
+struct ImportRow {
+    name: Option<String>,
+    description: Option<String>,
+    format: Option<String>,
+    structure: Option<String>,
+    duration: Option<String>,
+    price: Option<i32>,
+    language: Option<String>,
+    level: Option<String>,
+}
+
+// 某一行的错误, line 是文件中的行号, 从 1 开始, CSV 的表头是第 1 行
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RowError {
+    pub line: usize,
+    pub message: String,
+}
+
+// 导入成功时的响应
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportResponse {
+    pub imported: usize,
+    pub items: Vec<Course>,
+}
+
+// 等级, 格式和语言, 空白的单元格当作没有填
+fn parse_option<T: FromStr<Err = String>>(value: Option<String>) -> Result<Option<T>, String> {
+    match value {
+        Some(value) if !value.trim().is_empty() => value.parse().map(Some),
+        _ => Ok(None),
+    }
+}
+
+impl ImportRow {
+    // 校验规则和新增课程的接口一致, 都写在 CreateCourse 上
+    fn into_course(self, teacher_id: i32) -> Result<CreateCourse, String> {
+        let name = match self.name.map(|name| name.trim().to_string()) {
+            Some(name) if !name.is_empty() => name,
+            _ => return Err("name is required".into()),
+        };
+        let course = CreateCourse {
+            teacher_id,
+            name,
+            description: self.description,
+            format: parse_option(self.format)?,
+            structure: self.structure,
+            duration: self.duration,
+            price: self.price,
+            language: parse_option(self.language)?,
+            level: parse_option(self.level)?,
+        };
+        // 一行中所有字段的错误合并成一条信息
+        course.validate().map_err(|errors| MyError::from(errors).to_string())?;
+        Ok(course)
+    }
+}
+
+/**
+ * 解析导入文件, 每一行都会校验
+ * 只要有一行出错就返回所有出错的行, 一条都不导入
+ */
+pub fn parse_import(
+    format: CatalogFormat,
+    body: &[u8],
+    teacher_id: i32,
+) -> Result<Vec<CreateCourse>, Vec<RowError>> {
+    let rows: Vec<(usize, Result<ImportRow, String>)> = match format {
+        CatalogFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(body);
+            reader
+                .deserialize::<ImportRow>()
+                .enumerate()
+                .map(|(index, row)| match row {
+                    // position 是记录开始的行号, 没有的话按表头之后第几条推算
+                    Ok(row) => (index + 2, Ok(row)),
+                    Err(err) => {
+                        let line = err.position().map(|pos| pos.line() as usize).unwrap_or(index + 2);
+                        (line, Err(err.to_string()))
+                    }
+                })
+                .collect()
+        }
+        CatalogFormat::Ndjson => String::from_utf8_lossy(body)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| (index + 1, serde_json::from_str::<ImportRow>(line).map_err(|err| err.to_string())))
+            .collect(),
+    };
+
+    if rows.is_empty() {
+        return Err(vec![RowError { line: 1, message: "No rows to import".into() }]);
+    }
+    if rows.len() > MAX_IMPORT_ROWS {
+        return Err(vec![RowError {
+            line: rows[MAX_IMPORT_ROWS].0,
+            message: format!("At most {} rows can be imported at once", MAX_IMPORT_ROWS),
+        }]);
+    }
+
+    let mut courses = Vec::with_capacity(rows.len());
+    let mut errors = vec![];
+    for (line, row) in rows {
+        match row.and_then(|row| row.into_course(teacher_id)) {
+            Ok(course) => courses.push(course),
+            Err(message) => errors.push(RowError { line, message }),
+        }
+    }
+    if errors.is_empty() {
+        Ok(courses)
+    } else {
+        Err(errors)
+    }
+}
+
+// 导出为 CSV 时的一行, 字段顺序就是列的顺序
+#[derive(Serialize, Debug)]
+struct ExportRow<'a> {
+    id: i32,
+    name: &'a str,
+    description: Option<&'a str>,
+    format: Option<&'a str>,
+    structure: Option<&'a str>,
+    duration: Option<&'a str>,
+    price: Option<i32>,
+    language: Option<&'a str>,
+    level: Option<&'a str>,
+    time: Option<NaiveDateTime>,
+    version: i32,
+}
+
+impl<'a> From<&'a Course> for ExportRow<'a> {
+    fn from(course: &'a Course) -> Self {
+        ExportRow {
+            id: course.id,
+            name: &course.name,
+            description: course.description.as_deref(),
+            format: course.format.map(|format| format.as_str()),
+            structure: course.structure.as_deref(),
+            duration: course.duration.as_deref(),
+            price: course.price,
+            language: course.language.map(|language| language.as_str()),
+            level: course.level.map(|level| level.as_str()),
+            time: course.time,
+            version: course.version,
+        }
+    }
+}
+
+/**
+ * 把一批课程编码成要写出的字节
+ * 导出是分批进行的, CSV 只有第一批需要带表头
+ */
+pub fn encode_export(format: CatalogFormat, courses: &[Course], with_header: bool) -> Result<Bytes, MyError> {
+    let mut buf = vec![];
+    match format {
+        CatalogFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new().has_headers(with_header).from_writer(&mut buf);
+            for course in courses {
+                writer
+                    .serialize(ExportRow::from(course))
+                    .map_err(|err| MyError::ActixError(err.to_string()))?;
+            }
+            writer.flush().map_err(|err| MyError::ActixError(err.to_string()))?;
+        }
+        CatalogFormat::Ndjson => {
+            for course in courses {
+                serde_json::to_writer(&mut buf, course).map_err(|err| MyError::ActixError(err.to_string()))?;
+                buf.push(b'\n');
+            }
+        }
+    }
+    Ok(Bytes::from(buf))
+}
@@ -1,8 +1,14 @@
 use actix_web::web;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize}; // 反序列化 和 序列化
 use crate::errors::MyError;
+use crate::models::course_options::{CourseFormat, CourseLanguage, CourseLevel};
+use crate::models::patch::{non_null, nullable};
+use crate::models::validation::not_blank;
+use std::cmp::Ordering;
 use std::convert::TryFrom;
+use std::str::FromStr;
+use validator::Validate;
 
 // 移动进来以后, 引用路径就变成了 use crate::models::course::Course
 // FromRow 用于在添加数据库后, 从数据库读取数据时, 自动将数据库表的数据映射为 Crouse 这个 struct
@@ -16,43 +22,310 @@ pub struct Course {
     pub time: Option<NaiveDateTime>, // 允许是None的日期时间类型
 
     // 新增字段全是 Option 的, 所以他们是可空的
-    pub description: Option<String>, // 描述
-    pub format: Option<String>,      // 格式 安排进度或者自行安排进度, 直播课, 线下课等
-    pub structure: Option<String>,   // 课程的结构
-    pub duration: Option<String>,    // 课程持续时间, 单位可能不同, 所以使用字符串
-    pub price: Option<i32>,          // 价格
-    pub language: Option<String>,    // 语言
-    pub level: Option<String>,       // 等级, 初级 中级 高级 等
+    pub description: Option<String>,       // 描述
+    pub format: Option<CourseFormat>,      // 格式 自定进度, 安排进度, 直播课, 线下课
+    pub structure: Option<String>,         // 课程的结构
+    pub duration: Option<String>,          // 课程持续时间, 单位可能不同, 所以使用字符串
+    pub price: Option<i32>,                // 价格
+    pub language: Option<CourseLanguage>,  // 语言
+    pub level: Option<CourseLevel>,        // 等级, 初级 中级 高级
+    // 软删除的时间, 没有删除时为 None, 此时不序列化这个字段
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<NaiveDateTime>,
+    // 版本号, 每次修改加一, 同时作为 ETag 返回
+    pub version: i32,
+    // 最后修改时间, 作为 Last-Modified 返回, 由数据库的触发器维护
+    pub updated_at: DateTime<Utc>,
 }
 
 // ? 新增专用 struct
 // ? id 和 time 都是数据库生成的, 所以不需要我们单独实现
-#[derive(Deserialize, Debug, Clone)]
+// ? 校验规则写在字段上, 长度和 course 表的列长度保持一致, Option 字段为 None 时不校验
+#[derive(Deserialize, Debug, Clone, Validate)]
 pub struct CreateCourse {
+    #[validate(range(min = 1))]
     pub teacher_id: i32,
+    #[validate(length(min = 1, max = 140), custom = "not_blank")]
     pub name: String,
+    #[validate(length(max = 2000))]
     pub description: Option<String>, // 描述
-    pub format: Option<String>,      // 格式 安排进度或者自行安排进度, 直播课, 线下课等
+    // ? 等级, 格式和语言是枚举, 不合法的值在反序列化时就会被拒绝
+    pub format: Option<CourseFormat>,
+    #[validate(length(max = 200))]
     pub structure: Option<String>,   // 课程的结构
+    #[validate(length(max = 30))]
     pub duration: Option<String>,    // 课程持续时间, 单位可能不同, 所以使用字符串
+    #[validate(range(min = 0))]
     pub price: Option<i32>,          // 价格
-    pub language: Option<String>,    // 语言
-    pub level: Option<String>,       // 等级, 初级 中级 高级 等
+    pub language: Option<CourseLanguage>,
+    pub level: Option<CourseLevel>,
 }
 
 // 修改课程, 老师不能修改, 所以不需要 teacher_id
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, Validate)]
 pub struct UpdateCourse {
+    #[validate(length(min = 1, max = 140), custom = "not_blank")]
     pub name: Option<String>, // 因为更新课程时, 下面的所有属性都可能不进行更新, 所以他是一个 Option, 不更新的时候就是空值
+    #[validate(length(max = 2000))]
     pub description: Option<String>, // 描述
-    pub format: Option<String>,      // 格式 安排进度或者自行安排进度, 直播课, 线下课等
+    pub format: Option<CourseFormat>,
+    #[validate(length(max = 200))]
     pub structure: Option<String>,   // 课程的结构
+    #[validate(length(max = 30))]
     pub duration: Option<String>,    // 课程持续时间, 单位可能不同, 所以使用字符串
+    #[validate(range(min = 0))]
     pub price: Option<i32>,          // 价格
-    pub language: Option<String>,    // 语言
-    pub level: Option<String>,       // 等级, 初级 中级 高级 等
+    pub language: Option<CourseLanguage>,
+    pub level: Option<CourseLevel>,
+}
+
+// PATCH 修改课程, JSON Merge Patch, 见 patch.rs
+// ? 除了课程名都可以传 null 清空, 不认识的字段 (例如 teacher_id) 直接拒绝, 不会被悄悄忽略
+#[derive(Deserialize, Debug, Clone, Default, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct CoursePatch {
+    #[serde(default, deserialize_with = "non_null")]
+    #[validate(length(min = 1, max = 140), custom = "not_blank")]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(length(max = 2000))]
+    pub description: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub format: Option<Option<CourseFormat>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(length(max = 200))]
+    pub structure: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(length(max = 30))]
+    pub duration: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(range(min = 0))]
+    pub price: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub language: Option<Option<CourseLanguage>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub level: Option<Option<CourseLevel>>,
+}
+
+impl CoursePatch {
+    // 一个字段都没有, 不需要修改
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.description.is_none()
+            && self.format.is_none()
+            && self.structure.is_none()
+            && self.duration.is_none()
+            && self.price.is_none()
+            && self.language.is_none()
+            && self.level.is_none()
+    }
+}
+
+// PUT 没有清空字段的写法, 传了的字段修改, 没有传的保持原样 (包括 NULL)
+impl From<UpdateCourse> for CoursePatch {
+    fn from(course: UpdateCourse) -> Self {
+        CoursePatch {
+            name: course.name,
+            description: course.description.map(Some),
+            format: course.format.map(Some),
+            structure: course.structure.map(Some),
+            duration: course.duration.map(Some),
+            price: course.price.map(Some),
+            language: course.language.map(Some),
+            level: course.level.map(Some),
+        }
+    }
+}
+
+
+// 课程列表的筛选和排序参数, 例如 /courses/1?language=English&min_price=10&sort=price&order=desc
+// ? 和分页参数 PageQuery 来自同一个查询字符串, 互不影响
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct CourseFilterQuery {
+    pub language: Option<String>,
+    pub level: Option<String>,
+    pub format: Option<String>,
+    pub min_price: Option<i32>,
+    pub max_price: Option<i32>,
+    pub from: Option<NaiveDateTime>, // 创建时间范围, 例如 2023-01-01T00:00:00
+    pub to: Option<NaiveDateTime>,
+    pub sort: Option<String>,  // price, name, time
+    pub order: Option<String>, // asc, desc, 默认 asc
 }
 
+// 允许排序的字段, 只有这几个字段会被拼进 ORDER BY, 防止 SQL 注入
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CourseSortField {
+    Price,
+    Name,
+    Time,
+}
+
+impl CourseSortField {
+    // 对应的数据库列名
+    pub fn column(&self) -> &'static str {
+        match self {
+            CourseSortField::Price => "price",
+            CourseSortField::Name => "name",
+            CourseSortField::Time => "time",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+// 校验之后的筛选条件, db_access 只接收这个类型
+// sort 为 None 时按 id 排序, 只有这种情况下才支持 cursor 分页
+#[derive(Debug, Clone, Default)]
+pub struct CourseFilter {
+    pub language: Option<CourseLanguage>,
+    pub level: Option<CourseLevel>,
+    pub format: Option<CourseFormat>,
+    pub min_price: Option<i32>,
+    pub max_price: Option<i32>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub sort: Option<(CourseSortField, SortOrder)>,
+}
+
+impl TryFrom<CourseFilterQuery> for CourseFilter {
+    type Error = MyError;
+
+    fn try_from(query: CourseFilterQuery) -> Result<Self, Self::Error> {
+        if let (Some(min_price), Some(max_price)) = (query.min_price, query.max_price) {
+            if min_price > max_price {
+                return Err(MyError::InvalidInput("min_price must not be greater than max_price".into()));
+            }
+        }
+        if let (Some(from), Some(to)) = (query.from, query.to) {
+            if from > to {
+                return Err(MyError::InvalidInput("from must not be later than to".into()));
+            }
+        }
+        let field = match query.sort.as_deref() {
+            None => None,
+            Some("price") => Some(CourseSortField::Price),
+            Some("name") => Some(CourseSortField::Name),
+            Some("time") => Some(CourseSortField::Time),
+            Some(other) => {
+                return Err(MyError::InvalidInput(format!(
+                    "Invalid sort field: {}, expected price, name or time",
+                    other
+                )))
+            }
+        };
+        let order = match query.order.as_deref() {
+            None | Some("asc") => SortOrder::Asc,
+            Some("desc") => SortOrder::Desc,
+            Some(other) => {
+                return Err(MyError::InvalidInput(format!(
+                    "Invalid sort order: {}, expected asc or desc",
+                    other
+                )))
+            }
+        };
+        // 和新增课程一样, 不区分大小写, 也接受中文写法
+        fn parse<T: FromStr<Err = String>>(value: Option<String>) -> Result<Option<T>, MyError> {
+            value.map(|value| value.parse().map_err(MyError::InvalidInput)).transpose()
+        }
+        Ok(CourseFilter {
+            language: parse(query.language)?,
+            level: parse(query.level)?,
+            format: parse(query.format)?,
+            min_price: query.min_price,
+            max_price: query.max_price,
+            from: query.from,
+            to: query.to,
+            sort: field.map(|field| (field, order)),
+        })
+    }
+}
+
+impl CourseFilter {
+    // 内存实现用到的匹配逻辑, 和 postgres 实现里拼出来的 WHERE 条件保持一致
+    pub fn matches(&self, course: &Course) -> bool {
+        fn eq<T: PartialEq>(expected: &Option<T>, actual: &Option<T>) -> bool {
+            expected.is_none() || expected == actual
+        }
+        eq(&self.language, &course.language)
+            && eq(&self.level, &course.level)
+            && eq(&self.format, &course.format)
+            && self.min_price.is_none_or(|min| course.price.is_some_and(|price| price >= min))
+            && self.max_price.is_none_or(|max| course.price.is_some_and(|price| price <= max))
+            && self.from.is_none_or(|from| course.time.is_some_and(|time| time >= from))
+            && self.to.is_none_or(|to| course.time.is_some_and(|time| time <= to))
+    }
+
+    // 内存实现用到的排序逻辑, 对应 ORDER BY <column> <order> NULLS LAST, id
+    pub fn compare(&self, a: &Course, b: &Course) -> Ordering {
+        fn nulls_last<T: Ord>(a: Option<T>, b: Option<T>, order: SortOrder) -> Ordering {
+            match (a, b) {
+                (Some(a), Some(b)) => match order {
+                    SortOrder::Asc => a.cmp(&b),
+                    SortOrder::Desc => b.cmp(&a),
+                },
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        }
+        let by_field = match self.sort {
+            None => Ordering::Equal,
+            Some((CourseSortField::Price, order)) => nulls_last(a.price, b.price, order),
+            Some((CourseSortField::Name, order)) => nulls_last(Some(&a.name), Some(&b.name), order),
+            Some((CourseSortField::Time, order)) => nulls_last(a.time, b.time, order),
+        };
+        by_field.then(a.id.cmp(&b.id))
+    }
+}
+
+// 全文检索参数, /courses/search?q=rust
+#[derive(Deserialize, Debug, Clone)]
+pub struct CourseSearchQuery {
+    pub q: String,
+}
+
+// 检索关键字的最大长度
+pub const MAX_SEARCH_QUERY_LEN: usize = 200;
+
+impl CourseSearchQuery {
+    // 去掉首尾空白并校验, 返回真正用于检索的关键字
+    pub fn validated(&self) -> Result<String, MyError> {
+        let q = self.q.trim();
+        if q.is_empty() {
+            return Err(MyError::InvalidInput("Search query q must not be empty".into()));
+        }
+        if q.chars().count() > MAX_SEARCH_QUERY_LEN {
+            return Err(MyError::InvalidInput(format!(
+                "Search query q must not be longer than {} characters",
+                MAX_SEARCH_QUERY_LEN
+            )));
+        }
+        Ok(q.to_string())
+    }
+}
+
+// 检索结果, 课程本身加上相关度和高亮片段, 命中的词用 <mark></mark> 包裹
+#[derive(Serialize, Debug, Clone)]
+pub struct CourseSearchHit {
+    #[serde(flatten)]
+    pub course: Course,
+    pub rank: f32,
+    pub snippet: String,
+}
 
 // 这里需要的是 From<web::Json>到 CreateCourse, 而Course不需要实现 From trait 了
 // impl From<web::Json<CreateCourse>> for CreateCourse {
@@ -62,12 +335,12 @@ pub struct UpdateCourse {
             // teacher_id: course.teacher_id,
             // name: course.name.clone(),
             // description: course.description.clone(),
-            // format: course.format.clone(),
+            // format: course.format,
             // structure: course.structure.clone(),
             // duration: course.duration.clone(),
             // price: course.price,
-            // language: course.language.clone(),
-            // level: course.level.clone(),
+            // language: course.language,
+            // level: course.level,
 //         }
 //     }
 // }
@@ -76,34 +349,48 @@ impl TryFrom<web::Json<CreateCourse>> for CreateCourse {
     type Error = MyError;
     // 转换稍微不是那么直接, 会自动处理错误信息, 防止 panic
     // 这里如果失败的话, 会直接返回 Self::Error, 也就是 MyError
+    // ? 按字段上的规则校验, 不合法时返回 400 并列出每个字段的错误
     fn try_from(course: web::Json<CreateCourse>) -> Result<Self, Self::Error> {
+        course.validate()?;
         Ok(CreateCourse {
             teacher_id: course.teacher_id,
             name: course.name.clone(),
             description: course.description.clone(),
-            format: course.format.clone(),
+            format: course.format,
             structure: course.structure.clone(),
             duration: course.duration.clone(),
             price: course.price,
-            language: course.language.clone(),
-            level: course.level.clone(),
+            language: course.language,
+            level: course.level,
         })
     }
 }
 
-// 修改课程用 From trait即可, 一般不会失败
-impl From<web::Json<UpdateCourse>> for UpdateCourse {
-    fn from(course: web::Json<UpdateCourse>) -> Self {
-        UpdateCourse {
+// 修改课程同样需要校验, 所以也改为 TryFrom
+impl TryFrom<web::Json<UpdateCourse>> for UpdateCourse {
+    type Error = MyError;
+
+    fn try_from(course: web::Json<UpdateCourse>) -> Result<Self, Self::Error> {
+        course.validate()?;
+        Ok(UpdateCourse {
             name: course.name.clone(),
             description: course.description.clone(),
-            format: course.format.clone(),
+            format: course.format,
             structure: course.structure.clone(),
             duration: course.duration.clone(),
             price: course.price,
-            language: course.language.clone(),
-            level: course.level.clone(),
-        }
+            language: course.language,
+            level: course.level,
+        })
+    }
+}
+
+impl TryFrom<web::Json<CoursePatch>> for CoursePatch {
+    type Error = MyError;
+
+    fn try_from(patch: web::Json<CoursePatch>) -> Result<Self, Self::Error> {
+        patch.validate()?;
+        Ok(patch.into_inner())
     }
 }
 
@@ -0,0 +1,165 @@
+// 课程的等级, 形式和语言, 只允许固定的几个值
+// ? 数据库中保存的是小写下划线形式的值 (beginner, self_paced), 由 CHECK 约束保证, 序列化为 JSON 时也是这个值
+// ? 解析时不区分大小写, 空格和连字符等同于下划线, 另外接受常见的中文写法, 例如 初级, 直播课, 英文
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef};
+use sqlx::{Decode, Encode, Postgres, Type};
+use std::fmt;
+use std::str::FromStr;
+
+// 给前端展示的一个可选值
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CourseOption {
+    pub value: &'static str,
+    pub label: &'static str,
+}
+
+// GET /courses/options 的响应, 前端用来渲染下拉框
+#[derive(Serialize, Debug, Clone)]
+pub struct CourseOptions {
+    pub levels: Vec<CourseOption>,
+    pub formats: Vec<CourseOption>,
+    pub languages: Vec<CourseOption>,
+}
+
+impl CourseOptions {
+    pub fn all() -> Self {
+        CourseOptions {
+            levels: CourseLevel::options(),
+            formats: CourseFormat::options(),
+            languages: CourseLanguage::options(),
+        }
+    }
+}
+
+// 统一大小写和分隔符, 再和值以及别名比较
+fn normalize(value: &str) -> String {
+    value.trim().to_lowercase().replace([' ', '-'], "_")
+}
+
+/**
+ * 定义一个只允许固定值的枚举
+ * 每个变体依次是 数据库和 JSON 中的值, 展示用的名称, 解析时额外接受的别名
+ * 生成 FromStr, Display, serde 和 sqlx 的实现, 数据库中按字符串读写
+ */
+macro_rules! course_option {
+    ($(#[$meta:meta])* $name:ident, $field:literal, { $($variant:ident => $value:literal, $label:literal, [$($alias:literal),*]),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant),+
+        }
+
+        impl $name {
+            pub const ALL: &'static [$name] = &[$($name::$variant),+];
+
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $($name::$variant => $value),+
+                }
+            }
+
+            pub fn label(&self) -> &'static str {
+                match self {
+                    $($name::$variant => $label),+
+                }
+            }
+
+            pub fn options() -> Vec<CourseOption> {
+                Self::ALL.iter().map(|item| CourseOption { value: item.as_str(), label: item.label() }).collect()
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = String;
+
+            fn from_str(value: &str) -> Result<Self, Self::Err> {
+                let normalized = normalize(value);
+                $(
+                    if normalized == $value $(|| normalized == normalize($alias))* {
+                        return Ok($name::$variant);
+                    }
+                )+
+                let allowed: Vec<&str> = Self::ALL.iter().map(|item| item.as_str()).collect();
+                Err(format!("invalid {} `{}`, expected one of {}", $field, value, allowed.join(", ")))
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let value = String::deserialize(deserializer)?;
+                value.parse().map_err(serde::de::Error::custom)
+            }
+        }
+
+        // 数据库中是带 CHECK 约束的 VARCHAR 列, 按字符串编码和解码
+        impl Type<Postgres> for $name {
+            fn type_info() -> PgTypeInfo {
+                <&str as Type<Postgres>>::type_info()
+            }
+
+            fn compatible(ty: &PgTypeInfo) -> bool {
+                <&str as Type<Postgres>>::compatible(ty)
+            }
+        }
+
+        impl<'q> Encode<'q, Postgres> for $name {
+            fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+                <&str as Encode<Postgres>>::encode(self.as_str(), buf)
+            }
+        }
+
+        impl<'r> Decode<'r, Postgres> for $name {
+            fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+                let value = <&str as Decode<Postgres>>::decode(value)?;
+                Ok(value.parse()?)
+            }
+        }
+    };
+}
+
+course_option!(
+    // 课程等级
+    CourseLevel, "level", {
+        Beginner => "beginner", "Beginner", ["初级", "入门", "basic", "elementary"],
+        Intermediate => "intermediate", "Intermediate", ["中级"],
+        Advanced => "advanced", "Advanced", ["高级"],
+    }
+);
+
+course_option!(
+    // 授课形式
+    CourseFormat, "format", {
+        SelfPaced => "self_paced", "Self-paced", ["自定进度", "自行安排进度", "on demand"],
+        Scheduled => "scheduled", "Scheduled", ["安排进度", "instructor paced"],
+        Live => "live", "Live", ["直播", "直播课"],
+        Offline => "offline", "Offline", ["线下", "线下课", "in person"],
+    }
+);
+
+course_option!(
+    // 授课语言
+    CourseLanguage, "language", {
+        English => "english", "English", ["英文", "英语", "en"],
+        Chinese => "chinese", "Chinese", ["中文", "汉语", "普通话", "zh", "mandarin"],
+        Japanese => "japanese", "Japanese", ["日语", "日文", "ja"],
+        Korean => "korean", "Korean", ["韩语", "韩文", "ko"],
+        French => "french", "French", ["法语", "fr"],
+        German => "german", "German", ["德语", "de"],
+        Spanish => "spanish", "Spanish", ["西班牙语", "es"],
+    }
+);
@@ -0,0 +1,58 @@
+// 健康检查的响应
+// /health/live 只说明进程还活着, /health/ready 会检查数据库, 数据库不可用或者正在停机时返回 503
+use crate::metrics::MetricsSnapshot;
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Unavailable,
+    Draining, // 正在停机, 见 shutdown.rs
+}
+
+// 连接池的使用情况, saturation 是正在使用的连接占最大连接数的比例, 接近 1 时说明连接池不够用了
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: u32,
+    pub in_use: u32,
+    pub max_connections: u32,
+    pub saturation: f64,
+}
+
+impl PoolStats {
+    pub fn new(size: u32, idle: u32, max_connections: u32) -> Self {
+        let in_use = size.saturating_sub(idle);
+        let saturation = if max_connections == 0 { 0.0 } else { in_use as f64 / max_connections as f64 };
+        PoolStats { size, idle, in_use, max_connections, saturation }
+    }
+}
+
+// 数据存储的检查结果, 内存实现没有连接池
+// ? error 只给出笼统的原因, 具体的 sqlx 错误写在日志中
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StorageCheck {
+    pub backend: &'static str,
+    pub status: HealthStatus,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<PoolStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// GET /health/live 的响应
+#[derive(Serialize, Debug, Clone)]
+pub struct Liveness {
+    pub status: HealthStatus,
+    pub uptime_seconds: u64,
+    pub requests: MetricsSnapshot,
+}
+
+// GET /health/ready 的响应
+#[derive(Serialize, Debug, Clone)]
+pub struct Readiness {
+    pub status: HealthStatus,
+    pub storage: StorageCheck,
+}
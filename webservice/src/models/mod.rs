@@ -1,2 +1,12 @@
+pub mod audit; // 变更历史
+pub mod auth; // 老师账号, 登录和 token
+pub mod catalog; // 课程的批量导入导出
 pub mod course; // 对应的就是 course.rs
+pub mod course_options; // 课程的等级, 格式和语言
+pub mod health; // 存活和就绪检查
+pub mod pagination; // 列表接口的分页参数和返回结构
+pub mod patch; // PATCH 请求体, 区分没有传, null 和值
 pub mod teacher; // teacher.rs
+pub mod validation; // 参数校验中的自定义规则
+pub mod version; // 乐观锁, ETag 和 If-Match
+pub mod webhook; // 对外推送变更的 webhook 和投递记录
@@ -0,0 +1,104 @@
+use crate::errors::MyError;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+// 每页默认条数和最大条数
+pub const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+// 列表接口的查询参数, 例如 /courses/1?limit=10&offset=20 或者 /courses/1?limit=10&cursor=35
+// ? 两种方式都支持, 传了 cursor 时忽略 offset
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PageQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub cursor: Option<String>, // 上一页返回的 next_cursor, 对客户端来说是不透明的
+}
+
+// 校验之后的分页参数, db_access 只接收这个类型
+#[derive(Debug, Clone, Copy)]
+pub struct PageParams {
+    pub limit: i64,
+    pub offset: i64,
+    pub after_id: i32, // 只返回 id 大于它的记录, 没有 cursor 时为 0
+}
+
+impl Default for PageParams {
+    fn default() -> Self {
+        PageParams {
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+            after_id: 0,
+        }
+    }
+}
+
+// 和 CreateCourse 一样, 用 TryFrom 把前端传的参数转换为校验后的类型
+impl TryFrom<PageQuery> for PageParams {
+    type Error = MyError;
+
+    fn try_from(query: PageQuery) -> Result<Self, Self::Error> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(MyError::InvalidInput(format!(
+                "limit must be between 1 and {}",
+                MAX_LIMIT
+            )));
+        }
+        let offset = query.offset.unwrap_or(0);
+        if offset < 0 {
+            return Err(MyError::InvalidInput("offset must not be negative".into()));
+        }
+        match query.cursor {
+            Some(cursor) => {
+                let after_id = cursor
+                    .parse::<i32>()
+                    .map_err(|_err| MyError::InvalidInput("Invalid cursor".into()))?;
+                Ok(PageParams {
+                    limit,
+                    offset: 0,
+                    after_id,
+                })
+            }
+            None => Ok(PageParams {
+                limit,
+                offset,
+                after_id: 0,
+            }),
+        }
+    }
+}
+
+// 列表接口的返回结构
+// total 是满足条件的总条数, next_cursor 为 None 说明已经是最后一页
+#[derive(Serialize, Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    // rows 是按 id 升序查出来的 limit + 1 条记录, 多出来的那一条说明还有下一页
+    pub fn from_rows(mut rows: Vec<T>, total: i64, params: &PageParams, id_of: impl Fn(&T) -> i32) -> Self {
+        let has_more = rows.len() as i64 > params.limit;
+        rows.truncate(params.limit as usize);
+        let next_cursor = match rows.last() {
+            Some(last) if has_more => Some(id_of(last).to_string()),
+            _ => None,
+        };
+        Page {
+            items: rows,
+            total,
+            next_cursor,
+        }
+    }
+
+    // 不再返回 next_cursor, 用于 cursor 不适用的排序方式
+    pub fn without_cursor(self) -> Self {
+        Page {
+            next_cursor: None,
+            ..self
+        }
+    }
+}
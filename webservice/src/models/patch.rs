@@ -0,0 +1,31 @@
+// PATCH 请求体, JSON Merge Patch (RFC 7396)
+// ? 字段没有出现: 不修改, 值为 null: 清空 (数据库中设为 NULL), 其余的值: 修改为这个值
+// ? 可以清空的字段用 Option<Option<T>> 表示, 外层 None 是没有出现, Some(None) 是 null, 校验规则只对 Some(Some(..)) 生效
+// ? 不能清空的字段 (例如课程名) 用 Option<T>, 传 null 时反序列化失败, 返回 400
+// ? 请求的 Content-Type 可以是 application/merge-patch+json 或者 application/json
+use serde::{Deserialize, Deserializer};
+
+// 可以清空的字段, 配合 #[serde(default)] 使用, 字段没有出现时是 None
+pub fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+// 不能清空的字段, 出现了就必须有值
+pub fn non_null<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// 把一个字段的修改应用到当前值上, 内存实现使用
+pub fn apply<T>(patch: Option<Option<T>>, current: &mut Option<T>) {
+    if let Some(value) = patch {
+        *current = value;
+    }
+}
@@ -1,5 +1,11 @@
+use crate::errors::MyError;
+use crate::models::patch::{non_null, nullable};
+use crate::models::validation::{http_url, not_blank};
 use actix_web::web;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize}; // 反序列化 和 序列化
+use std::convert::TryFrom;
+use validator::Validate;
 
 #[derive(Deserialize, Serialize, Debug, Clone, sqlx::FromRow)]
 pub struct Teacher {
@@ -7,40 +13,132 @@ pub struct Teacher {
     pub name: Option<String>,
     pub picture_url: Option<String>,
     pub profile: Option<String>,
+    // 软删除的时间, 没有删除时为 None
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<NaiveDateTime>,
+    // 版本号, 每次修改加一, 同时作为 ETag 返回
+    #[serde(default)]
+    pub version: i32,
+    // 最后修改时间, 作为 Last-Modified 返回
+    #[serde(default)]
+    pub updated_at: DateTime<Utc>,
 }
 
 // 新增和编辑均不需要序列化, 只需要反序列化
-#[derive(Deserialize, Debug, Clone)]
+// ? 校验规则和 teacher 表的列长度保持一致, 头像地址可以为空
+#[derive(Deserialize, Debug, Clone, Validate)]
 pub struct CreateTeacher {
+    #[validate(length(min = 1, max = 200), custom = "not_blank")]
     pub name: String,
+    #[validate(length(max = 200), custom = "http_url")]
     pub picture_url: String,
+    #[validate(length(max = 2000))]
     pub profile: String,
 }
 
 // 更新时可以不传, 说明当前字段不需要更新, 所以属性可以是None, 使用 Option 枚举
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, Validate)]
 pub struct UpdateTeacher {
+    #[validate(length(min = 1, max = 200), custom = "not_blank")]
     pub name: Option<String>,
+    #[validate(length(max = 200), custom = "http_url")]
     pub picture_url: Option<String>,
+    #[validate(length(max = 2000))]
     pub profile: Option<String>,
 }
 
-impl From<web::Json<CreateTeacher>> for CreateTeacher {
-    fn from(new_teacher: web::Json<CreateTeacher>) -> Self {
-        CreateTeacher {
-            name: new_teacher.name.clone(),
-            picture_url: new_teacher.picture_url.clone(),
-            profile: new_teacher.profile.clone()
+// PATCH 修改老师, JSON Merge Patch, 头像和简介可以传 null 清空, 名字不能
+#[derive(Deserialize, Debug, Clone, Default, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct TeacherPatch {
+    #[serde(default, deserialize_with = "non_null")]
+    #[validate(length(min = 1, max = 200), custom = "not_blank")]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(length(max = 200), custom = "http_url")]
+    pub picture_url: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(length(max = 2000))]
+    pub profile: Option<Option<String>>,
+}
+
+impl TeacherPatch {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.picture_url.is_none() && self.profile.is_none()
+    }
+}
+
+// PUT 只修改传了的字段, 没有传的保持原样
+impl From<UpdateTeacher> for TeacherPatch {
+    fn from(teacher: UpdateTeacher) -> Self {
+        TeacherPatch {
+            name: teacher.name,
+            picture_url: teacher.picture_url.map(Some),
+            profile: teacher.profile.map(Some),
         }
     }
 }
 
-impl From<web::Json<UpdateTeacher>> for UpdateTeacher {
-    fn from(update_teacher: web::Json<UpdateTeacher>) -> Self {
-        UpdateTeacher {
-            name: update_teacher.name.clone(),
-            picture_url: update_teacher.picture_url.clone(),
-            profile: update_teacher.profile.clone()
+// 转换时按字段上的规则校验, 不合法时返回 400
+impl TryFrom<web::Json<CreateTeacher>> for CreateTeacher {
+    type Error = MyError;
+
+    fn try_from(new_teacher: web::Json<CreateTeacher>) -> Result<Self, Self::Error> {
+        new_teacher.validate()?;
+        Ok(new_teacher.into_inner())
+    }
+}
+
+impl TryFrom<web::Json<UpdateTeacher>> for UpdateTeacher {
+    type Error = MyError;
+
+    fn try_from(update_teacher: web::Json<UpdateTeacher>) -> Result<Self, Self::Error> {
+        update_teacher.validate()?;
+        Ok(update_teacher.into_inner())
+    }
+}
+
+impl TryFrom<web::Json<TeacherPatch>> for TeacherPatch {
+    type Error = MyError;
+
+    fn try_from(patch: web::Json<TeacherPatch>) -> Result<Self, Self::Error> {
+        patch.validate()?;
+        Ok(patch.into_inner())
+    }
+}
+
+// 删除老师时的查询参数, 例如 /teachers/1?courses=reassign&reassign_to=2
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct DeleteTeacherQuery {
+    pub courses: Option<String>, // reject, cascade, reassign, 默认 reject
+    pub reassign_to: Option<i32>,
+}
+
+// 老师还有课程时如何处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoursePolicy {
+    Reject,         // 还有课程就不允许删除
+    Cascade,        // 课程一起软删除
+    Reassign(i32),  // 课程转给另一位老师
+}
+
+impl TryFrom<DeleteTeacherQuery> for CoursePolicy {
+    type Error = MyError;
+
+    fn try_from(query: DeleteTeacherQuery) -> Result<Self, Self::Error> {
+        let policy = query.courses.as_deref().map(str::to_lowercase);
+        match (policy.as_deref(), query.reassign_to) {
+            (None | Some("reject"), None) => Ok(CoursePolicy::Reject),
+            (Some("cascade"), None) => Ok(CoursePolicy::Cascade),
+            (Some("reassign"), Some(teacher_id)) => Ok(CoursePolicy::Reassign(teacher_id)),
+            (Some("reassign"), None) => Err(MyError::InvalidInput("reassign_to is required when courses=reassign".into())),
+            (Some("reject" | "cascade") | None, Some(_)) => {
+                Err(MyError::InvalidInput("reassign_to can only be used with courses=reassign".into()))
+            }
+            (Some(other), _) => Err(MyError::InvalidInput(format!(
+                "Unknown courses policy: {}, expected reject, cascade or reassign",
+                other
+            ))),
         }
     }
 }
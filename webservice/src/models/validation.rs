@@ -0,0 +1,26 @@
+// 参数校验规则中用到的自定义校验函数
+// ? 长度和范围等简单规则直接写在结构体字段的 #[validate(...)] 上, 这里只放 validator 没有内置的规则
+use validator::{validate_url, ValidationError};
+
+fn error(code: &'static str, message: String) -> ValidationError {
+    let mut err = ValidationError::new(code);
+    err.message = Some(message.into());
+    err
+}
+
+// 不能只有空白字符, 长度的下限交给 length 规则
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(error("blank", "must not be blank".into()));
+    }
+    Ok(())
+}
+
+// 头像地址, 允许为空, 不为空时必须是 http 或者 https 地址
+pub fn http_url(value: &str) -> Result<(), ValidationError> {
+    let is_http = value.starts_with("http://") || value.starts_with("https://");
+    if value.is_empty() || (is_http && validate_url(value)) {
+        return Ok(());
+    }
+    Err(error("url", "must be an http or https URL".into()))
+}
@@ -0,0 +1,56 @@
+use crate::errors::MyError;
+use actix_web::http::header::{self, EntityTag, ETag, Header, IfMatch};
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use std::future::{ready, Ready};
+
+// 版本号对应的 ETag, 例如 ETag: "3"
+pub fn etag(version: i32) -> ETag {
+    ETag(EntityTag::new_strong(version.to_string()))
+}
+
+// If-Match 请求头, 修改之前检查记录的当前版本
+// ? 没有传 If-Match 或者传的是 * 时不做检查, 兼容还不支持 ETag 的客户端
+#[derive(Debug, Clone, Default)]
+pub struct VersionCheck(Option<Vec<EntityTag>>);
+
+impl VersionCheck {
+    // 不做检查
+    pub fn any() -> Self {
+        VersionCheck(None)
+    }
+
+    // 只有当前版本等于 version 时才允许修改
+    #[cfg(test)]
+    pub fn version(version: i32) -> Self {
+        VersionCheck(Some(vec![etag(version).0]))
+    }
+
+    // 当前版本和 If-Match 中的任意一个 ETag 强比较相等即可
+    pub fn check(&self, current: i32) -> Result<(), MyError> {
+        match &self.0 {
+            None => Ok(()),
+            Some(tags) if tags.iter().any(|tag| tag.strong_eq(&etag(current).0)) => Ok(()),
+            Some(_) => Err(MyError::PreconditionFailed(format!(
+                "The resource has been modified, current version is {}",
+                current
+            ))),
+        }
+    }
+}
+
+impl FromRequest for VersionCheck {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        if !req.headers().contains_key(header::IF_MATCH) {
+            return ready(Ok(VersionCheck::any()));
+        }
+        let check = match IfMatch::parse(req) {
+            Ok(IfMatch::Any) => Ok(VersionCheck::any()),
+            Ok(IfMatch::Items(tags)) => Ok(VersionCheck(Some(tags))),
+            Err(_err) => Err(MyError::InvalidInput("Invalid If-Match header".into())),
+        };
+        ready(check)
+    }
+}
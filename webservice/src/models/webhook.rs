@@ -0,0 +1,232 @@
+use crate::errors::MyError;
+use crate::models::audit::{AuditAction, AuditEntry};
+use crate::models::patch::nullable;
+use crate::models::validation::{http_url, not_blank};
+use actix_web::web;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::TryFrom;
+use validator::{Validate, ValidationError};
+
+// 可以订阅的事件, 课程和老师的新增, 修改和删除
+pub const WEBHOOK_EVENTS: &[&str] = &[
+    "course.created",
+    "course.updated",
+    "course.deleted",
+    "teacher.created",
+    "teacher.updated",
+    "teacher.deleted",
+];
+
+/**
+ * 变更历史中的一条记录对应的事件类型, 例如 course.created
+ * ? 和迁移中 enqueue_webhook_deliveries() 的规则一致: 从回收站恢复算作 created, 彻底删除不投递
+ * ? 课程转给其他老师时原来的老师收到 course.deleted (move), 只投递给这位老师的 webhook
+ */
+pub fn event_type(entry: &AuditEntry) -> Option<String> {
+    let suffix = match entry.action.as_str() {
+        action if action == AuditAction::Purge.as_str() => return None,
+        "create" | "restore" => "created",
+        "delete" | "move" => "deleted",
+        _ => "updated",
+    };
+    Some(format!("{}.{}", entry.entity_type, suffix))
+}
+
+fn known_events(events: &[String]) -> Result<(), ValidationError> {
+    match events.iter().find(|event| !WEBHOOK_EVENTS.contains(&event.as_str())) {
+        None => Ok(()),
+        Some(event) => {
+            let mut err = ValidationError::new("event");
+            err.message = Some(format!("unknown event `{}`, expected one of {}", event, WEBHOOK_EVENTS.join(", ")).into());
+            Err(err)
+        }
+    }
+}
+
+// 注册 webhook 的请求体
+#[derive(Deserialize, Debug, Clone, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct CreateWebhook {
+    // 没有传: 登录的老师自己, null: 所有老师 (只有管理员可以), 其他老师的 id 也只有管理员可以
+    #[serde(default, deserialize_with = "nullable")]
+    pub teacher_id: Option<Option<i32>>,
+    #[validate(length(min = 1, max = 2000), custom = "http_url")]
+    pub url: String,
+    // 没有传时订阅所有事件
+    #[serde(default)]
+    #[validate(custom = "known_events")]
+    pub events: Vec<String>,
+    // 签名用的密钥, 没有传时自动生成
+    #[validate(length(min = 16, max = 200), custom = "not_blank")]
+    pub secret: Option<String>,
+}
+
+impl TryFrom<web::Json<CreateWebhook>> for CreateWebhook {
+    type Error = MyError;
+
+    fn try_from(webhook: web::Json<CreateWebhook>) -> Result<Self, Self::Error> {
+        webhook.validate()?;
+        Ok(webhook.into_inner())
+    }
+}
+
+// 校验和确定了所属老师之后的 webhook, db_access 只接收这个类型
+#[derive(Debug, Clone)]
+pub struct NewWebhook {
+    pub teacher_id: Option<i32>,
+    pub url: String,
+    pub events: Vec<String>,
+    pub secret: String,
+}
+
+// 接口返回的 webhook, 不包含密钥
+#[derive(Serialize, Debug, Clone, sqlx::FromRow)]
+pub struct Webhook {
+    pub id: i32,
+    pub teacher_id: Option<i32>,
+    pub url: String,
+    pub events: Vec<String>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// 创建时的响应, 只有这一次会返回密钥
+#[derive(Serialize, Debug, Clone)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    Pending,   // 等待发送或者等待重试
+    Delivered, // 对方返回了 2xx
+    Dead,      // 重试次数用完, 进入死信, 可以手动重新投递
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Dead => "dead",
+        }
+    }
+}
+
+// 投递记录的查询参数, 例如 ?status=dead 查看死信
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct DeliveryQuery {
+    pub status: Option<String>,
+}
+
+impl TryFrom<DeliveryQuery> for Option<DeliveryStatus> {
+    type Error = MyError;
+
+    fn try_from(query: DeliveryQuery) -> Result<Self, Self::Error> {
+        match query.status.as_deref().map(str::to_lowercase).as_deref() {
+            None => Ok(None),
+            Some("pending") => Ok(Some(DeliveryStatus::Pending)),
+            Some("delivered") => Ok(Some(DeliveryStatus::Delivered)),
+            Some("dead") => Ok(Some(DeliveryStatus::Dead)),
+            Some(other) => Err(MyError::InvalidInput(format!(
+                "Unknown delivery status: {}, expected pending, delivered or dead",
+                other
+            ))),
+        }
+    }
+}
+
+// 一个事件对一个 webhook 的投递
+#[derive(Serialize, Debug, Clone, sqlx::FromRow)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event_id: i32,
+    pub event_type: String,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+// 一次请求的结果
+#[derive(Serialize, Debug, Clone, sqlx::FromRow)]
+pub struct WebhookAttempt {
+    pub id: i32,
+    pub delivery_id: i32,
+    pub attempt: i32,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+// 查看一次投递时返回发送的请求体和每一次请求的结果
+#[derive(Serialize, Debug, Clone)]
+pub struct DeliveryDetail {
+    #[serde(flatten)]
+    pub delivery: WebhookDelivery,
+    pub payload: Value,
+    pub attempts: Vec<WebhookAttempt>,
+}
+
+// 发送给对方的请求体, 由变更历史生成, 重试时内容不变
+// ? id 是事件的 id, 同一个事件重试或者重新投递时不变, 接收方可以用来去重
+#[derive(Serialize, Debug)]
+struct WebhookPayload<'a> {
+    id: i32,
+    #[serde(rename = "type")]
+    event_type: &'a str,
+    occurred_at: NaiveDateTime,
+    teacher_id: i32,
+    actor: &'a str,
+    // 变更之后的课程或者老师, 删除时是删除之前的 (带有 deleted_at)
+    data: Option<&'a Value>,
+}
+
+pub fn payload(event_type: &str, event: &AuditEntry) -> Value {
+    let payload = WebhookPayload {
+        id: event.id,
+        event_type,
+        occurred_at: event.created_at,
+        teacher_id: event.teacher_id,
+        actor: &event.actor,
+        data: event.after.as_ref().or(event.before.as_ref()),
+    };
+    serde_json::to_value(payload).unwrap_or(Value::Null)
+}
+
+// 到期需要发送的投递, 由投递任务领取
+#[derive(Debug, Clone)]
+pub struct DueDelivery {
+    pub id: i32,
+    pub url: String,
+    pub secret: String,
+    // 之前已经失败的次数
+    pub attempts: i32,
+    pub event_type: String,
+    pub event: AuditEntry,
+}
+
+// 发送一次的结果, error 为 None 说明成功
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttemptOutcome {
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+}
+
+// 发送之后投递的下一个状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NextAttempt {
+    Delivered,
+    RetryAt(DateTime<Utc>),
+    Dead,
+}
@@ -0,0 +1,374 @@
+// 限流, 令牌桶算法, 课程和老师两组路由分别配置 (见 config.rs 中的 rate_limit)
+// 每个客户端一个桶: 登录之后按老师 id 计数, 没有登录时按客户端 IP 计数
+// ? 桶的容量是 burst, 每秒补充 per_second 个令牌, 每个请求消耗一个, 没有令牌时返回 429
+// ? 响应头按 IETF 的 RateLimit 草案: RateLimit-Limit, RateLimit-Remaining, RateLimit-Reset (多少秒之后桶满), RateLimit-Policy, 429 时另外带上 Retry-After
+// ? 桶保存在进程的内存中, 部署多个实例时各自计数
+// ? 最多记录 MAX_TRACKED_CLIENTS 个客户端, 满了之后淘汰最久没有请求的, 被淘汰的客户端再来时是一个满的桶
+use crate::auth::Principal;
+use crate::errors::MyError;
+use crate::state::AppState;
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::{web, Error, HttpMessage};
+use futures_util::future::LocalBoxFuture;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::future::{ready, Ready};
+use std::sync::Mutex;
+use std::time::Instant;
+
+// 最多记录的客户端数量, 避免大量不同的 IP 占满内存
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+// 一组路由的限流策略
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitPolicy {
+    // 桶的容量, 也就是允许的突发请求数
+    pub burst: u32,
+    // 每秒补充的令牌数, 也就是长期的平均速率
+    pub per_second: f64,
+}
+
+// 分别限流的路由分组, 和 routers.rs 中的 scope 对应
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteGroup {
+    Courses,
+    Teachers,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// 所有客户端的桶, 另外按最后一次请求的时间排序, 满了之后不需要遍历就能找到最久没有请求的客户端
+#[derive(Default)]
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    by_updated: BTreeSet<(Instant, String)>,
+}
+
+// 一次检查的结果, 用来生成响应头
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    // 多少秒之后桶会补满
+    pub reset: u64,
+    // 被拒绝时多少秒之后可以重试
+    pub retry_after: u64,
+}
+
+pub struct RateLimiter {
+    policy: RateLimitPolicy,
+    buckets: Mutex<Buckets>,
+    max_clients: usize,
+}
+
+impl RateLimiter {
+    pub fn new(policy: RateLimitPolicy) -> Self {
+        RateLimiter { policy, buckets: Mutex::default(), max_clients: MAX_TRACKED_CLIENTS }
+    }
+
+    pub fn check(&self, key: &str) -> Decision {
+        self.check_at(key, Instant::now())
+    }
+
+    // 时间作为参数传入, 测试时不需要真的等待
+    fn check_at(&self, key: &str, now: Instant) -> Decision {
+        let RateLimitPolicy { burst, per_second } = self.policy;
+        let capacity = burst as f64;
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * per_second).min(capacity)
+        };
+        // 锁只在计算期间持有, 不跨越 await
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut bucket = match buckets.by_key.remove(key) {
+            Some(bucket) => {
+                buckets.by_updated.remove(&(bucket.updated, key.to_string()));
+                bucket
+            }
+            None => {
+                if buckets.by_key.len() >= self.max_clients {
+                    if let Some((_, oldest)) = buckets.by_updated.pop_first() {
+                        buckets.by_key.remove(&oldest);
+                    }
+                }
+                Bucket { tokens: capacity, updated: now }
+            }
+        };
+        bucket.tokens = refill(&bucket);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let seconds = |tokens: f64| (tokens.max(0.0) / per_second).ceil() as u64;
+        let decision = Decision {
+            allowed,
+            limit: burst,
+            remaining: bucket.tokens.floor() as u32,
+            reset: seconds(capacity - bucket.tokens),
+            retry_after: if allowed { 0 } else { seconds(1.0 - bucket.tokens).max(1) },
+        };
+        buckets.by_updated.insert((now, key.to_string()));
+        buckets.by_key.insert(key.to_string(), bucket);
+        decision
+    }
+
+    // 写到响应头中的策略, 例如 120;w=60 表示 60 秒内最多 120 个请求
+    fn policy_header(&self) -> String {
+        let window = (self.policy.burst as f64 / self.policy.per_second).ceil() as u64;
+        format!("{};w={}", self.policy.burst, window)
+    }
+}
+
+// 所有分组的限流器, 放在 AppState 中, 多个 worker 共享
+pub struct RateLimits {
+    courses: Option<RateLimiter>,
+    teachers: Option<RateLimiter>,
+    // 在反向代理后面时按 X-Forwarded-For 中的地址计数, 直接对外时不能信任这个请求头
+    trust_proxy_headers: bool,
+}
+
+impl RateLimits {
+    pub fn new(courses: Option<RateLimitPolicy>, teachers: Option<RateLimitPolicy>, trust_proxy_headers: bool) -> Self {
+        RateLimits {
+            courses: courses.map(RateLimiter::new),
+            teachers: teachers.map(RateLimiter::new),
+            trust_proxy_headers,
+        }
+    }
+
+    // 不限流, 测试中使用
+    #[cfg(test)]
+    pub fn disabled() -> Self {
+        RateLimits::new(None, None, false)
+    }
+
+    fn limiter(&self, group: RouteGroup) -> Option<&RateLimiter> {
+        match group {
+            RouteGroup::Courses => self.courses.as_ref(),
+            RouteGroup::Teachers => self.teachers.as_ref(),
+        }
+    }
+
+    // 计数的依据, 登录之后是老师 id, 同一个老师换 IP 也共用一个桶
+    fn client_key(&self, req: &ServiceRequest) -> String {
+        if let Some(principal) = req.extensions().get::<Principal>() {
+            return format!("teacher:{}", principal.teacher_id);
+        }
+        let peer = || req.peer_addr().map(|addr| addr.ip().to_string());
+        let ip = if self.trust_proxy_headers { forwarded_for(req).or_else(peer) } else { peer() };
+        format!("ip:{}", ip.unwrap_or_else(|| "unknown".into()))
+    }
+}
+
+// 反向代理把它看到的客户端地址追加到 X-Forwarded-For 的最后, 所以只使用最右边的一项
+// ? 前面的项是客户端自己带来的, 可以随意伪造; 这里假设前面只有一层代理
+fn forwarded_for(req: &ServiceRequest) -> Option<String> {
+    req.headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .last()
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(str::to_string)
+}
+
+fn insert_headers(headers: &mut HeaderMap, limiter: &RateLimiter, decision: &Decision) {
+    let values = [
+        ("ratelimit-limit", decision.limit.to_string()),
+        ("ratelimit-remaining", decision.remaining.to_string()),
+        ("ratelimit-reset", decision.reset.to_string()),
+        ("ratelimit-policy", limiter.policy_header()),
+    ];
+    for (name, value) in values {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(HeaderName::from_static(name), value);
+        }
+    }
+}
+
+// 限流中间件, 挂在课程和老师的 scope 上
+// ? 需要在 RequireAuth 里面 (也就是先 wrap), 这样才能拿到登录的身份
+pub struct RateLimit(pub RouteGroup);
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware { service, group: self.0 }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    group: RouteGroup,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let app_state = req.app_data::<web::Data<AppState>>().cloned();
+        let group = self.group;
+        // 没有配置这一组的限流时直接放行
+        let decision = app_state.as_ref().and_then(|state| {
+            let limiter = state.rate_limits.limiter(group)?;
+            Some(limiter.check(&state.rate_limits.client_key(&req)))
+        });
+        let decision = match decision {
+            Some(decision) => decision,
+            None => {
+                let fut = self.service.call(req);
+                return Box::pin(async move { fut.await.map(ServiceResponse::map_into_left_body) });
+            }
+        };
+        if !decision.allowed {
+            let mut res = req.error_response(MyError::RateLimited(decision.retry_after));
+            if let Some(limiter) = app_state.as_ref().and_then(|state| state.rate_limits.limiter(group)) {
+                insert_headers(res.headers_mut(), limiter, &decision);
+            }
+            return Box::pin(async move { Ok(res.map_into_right_body()) });
+        }
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if let Some(limiter) = app_state.as_ref().and_then(|state| state.rate_limits.limiter(group)) {
+                insert_headers(res.headers_mut(), limiter, &decision);
+            }
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+// * 测试
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ProblemDetails;
+    use crate::models::auth::Role;
+    use crate::request_id::AssignRequestId;
+    use crate::routers::{course_routes, teacher_routes};
+    use actix_web::http::{header, StatusCode};
+    use actix_web::{test, App};
+    use std::time::Duration;
+
+    const POLICY: RateLimitPolicy = RateLimitPolicy { burst: 2, per_second: 0.5 };
+
+    #[actix_rt::test]
+    async fn token_bucket_refills_over_time() {
+        let limiter = RateLimiter::new(POLICY);
+        let start = Instant::now();
+        let first = limiter.check_at("ip:1", start);
+        assert_eq!((first.allowed, first.remaining, first.reset), (true, 1, 2));
+        assert!(limiter.check_at("ip:1", start).allowed);
+        let denied = limiter.check_at("ip:1", start);
+        assert_eq!((denied.allowed, denied.remaining, denied.retry_after, denied.reset), (false, 0, 2, 4));
+        // 别的客户端不受影响
+        assert!(limiter.check_at("ip:2", start).allowed);
+        // 两秒补充一个令牌
+        assert!(!limiter.check_at("ip:1", start + Duration::from_secs(1)).allowed);
+        assert!(limiter.check_at("ip:1", start + Duration::from_secs(2)).allowed);
+        // 很久之后也不会超过容量
+        assert_eq!(limiter.check_at("ip:1", start + Duration::from_secs(3600)).remaining, 1);
+        assert_eq!(limiter.policy_header(), "2;w=4");
+    }
+
+    #[actix_rt::test]
+    async fn least_recently_seen_clients_are_evicted_first() {
+        let limiter = RateLimiter { max_clients: 2, ..RateLimiter::new(POLICY) };
+        let start = Instant::now();
+        limiter.check_at("ip:1", start);
+        limiter.check_at("ip:2", start + Duration::from_millis(1));
+        limiter.check_at("ip:1", start + Duration::from_millis(2));
+        // ip:2 最久没有请求, 被新的客户端挤掉
+        limiter.check_at("ip:3", start + Duration::from_millis(3));
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_key.len(), 2);
+        assert_eq!(buckets.by_updated.len(), 2);
+        assert!(buckets.by_key.contains_key("ip:1") && buckets.by_key.contains_key("ip:3"));
+        // ip:1 的桶还在, 已经用掉的令牌不会因为淘汰而恢复
+        assert!(buckets.by_key["ip:1"].tokens < 1.0);
+    }
+
+    #[actix_rt::test]
+    async fn only_the_proxy_added_forwarded_address_is_trusted() {
+        let key = |trust: bool, forwarded: &str| {
+            let req = test::TestRequest::default()
+                .peer_addr("10.0.0.9:1000".parse().unwrap())
+                .insert_header(("x-forwarded-for", forwarded))
+                .to_srv_request();
+            RateLimits::new(None, None, trust).client_key(&req)
+        };
+        // 客户端在请求头中伪造的地址不影响计数
+        assert_eq!(key(true, "1.1.1.1, 203.0.113.7"), "ip:203.0.113.7");
+        assert_eq!(key(true, "2.2.2.2, 203.0.113.7"), "ip:203.0.113.7");
+        // 不信任代理时使用连接的地址
+        assert_eq!(key(false, "203.0.113.7"), "ip:10.0.0.9");
+    }
+
+    #[actix_rt::test]
+    async fn clients_over_the_limit_get_429() {
+        // 只限制课程
+        let app_state = AppState::for_tests().rate_limits(RateLimits::new(Some(POLICY), None, false)).build();
+        let token = app_state.tokens.issue(Principal { teacher_id: 1, role: Role::Teacher }).unwrap().access_token;
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state)
+                .configure(course_routes)
+                .configure(teacher_routes)
+                .wrap(AssignRequestId),
+        )
+        .await;
+        let get = |peer: &str| test::TestRequest::get().uri("/courses/1").peer_addr(peer.parse().unwrap());
+
+        let res = test::call_service(&app, get("10.0.0.1:1000").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("ratelimit-remaining").unwrap(), "1");
+        // 同一个 IP 换了端口也算同一个客户端
+        test::call_service(&app, get("10.0.0.1:2000").to_request()).await;
+        let res = test::call_service(&app, get("10.0.0.1:1000").to_request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "2");
+        assert_eq!(res.headers().get("ratelimit-remaining").unwrap(), "0");
+        assert_eq!(res.headers().get("ratelimit-policy").unwrap(), "2;w=4");
+        let problem: ProblemDetails = test::read_body_json(res).await;
+        assert_eq!((problem.code.as_str(), problem.retry_after), ("rate_limited", Some(2)));
+        assert!(problem.request_id.is_some());
+
+        // 别的 IP 和登录之后的老师各自计数
+        let res = test::call_service(&app, get("10.0.0.2:1000").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let req = get("10.0.0.1:1000").insert_header((header::AUTHORIZATION, format!("Bearer {}", token)));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+        // 老师的路由没有配置限流
+        for _ in 0..3 {
+            let req = test::TestRequest::get().uri("/teachers").peer_addr("10.0.0.1:1000".parse().unwrap());
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert!(res.headers().get("ratelimit-limit").is_none());
+        }
+    }
+}
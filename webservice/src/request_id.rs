@@ -0,0 +1,174 @@
+// 请求 id, 请求的日志 span 和统一的错误响应
+// 每个请求分配一个 id, 放到响应头 X-Request-Id 中, 客户端带了合法的 X-Request-Id 时沿用它, 方便跨服务串起日志
+// 每个请求一个 tracing span, 带上 request_id, method, route, teacher_id, status 和耗时, 请求中的日志都在这个 span 下面
+// 出错时在这里写日志 (包括数据库错误等不会返回给前端的内部原因), 并把错误响应改写成带 request_id 的 problem+json
+use crate::errors::{error_code, MyError, ProblemDetails};
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::error::InternalError;
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpMessage, HttpRequest, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use std::future::{ready, Ready};
+use std::time::Instant;
+use tracing::field::Empty;
+use tracing::{error, info, info_span, warn, Instrument, Span};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// 放在请求的 extensions 中, handler 里需要时可以取出来
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+// 客户端传来的 id 会写进日志和响应头, 只接受长度有限的简单字符
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+// 转换为返回给前端的 problem, 以及写进日志的内部原因
+fn problem_for(err: &Error) -> (ProblemDetails, String) {
+    if let Some(err) = err.as_error::<MyError>() {
+        return (err.problem(), err.to_string());
+    }
+    let status = err.as_response_error().status_code();
+    let detail = if status.is_server_error() {
+        "An internal server error occurred".to_string()
+    } else {
+        err.to_string()
+    };
+    (ProblemDetails::new(status, error_code(err), detail), err.to_string())
+}
+
+// 写日志, 并生成带 request_id 和请求路径的错误响应
+fn problem_response(err: &Error, path: &str, request_id: &str) -> HttpResponse {
+    let (problem, internal) = problem_for(err);
+    let problem = problem.with_request(Some(request_id), Some(path));
+    log_completion(problem.status_code(), Some((&problem.code, &internal)));
+    problem.to_response()
+}
+
+// 路由的模板和路径中的 teacher_id 在路由匹配之后才知道, 请求结束时补到 span 上
+fn record_route(req: &HttpRequest) {
+    let span = Span::current();
+    if let Some(route) = req.match_pattern() {
+        span.record("route", route.as_str());
+    }
+    if let Some(teacher_id) = req.match_info().get("teacher_id").and_then(|id| id.parse::<i32>().ok()) {
+        span.record("teacher_id", teacher_id);
+    }
+}
+
+// 请求结束时的日志, 服务器错误记为 error, 客户端错误记为 warn
+fn log_completion(status: StatusCode, failure: Option<(&str, &str)>) {
+    Span::current().record("status", status.as_u16());
+    match failure {
+        Some((code, error)) if status.is_server_error() => error!(code, error, "request failed"),
+        Some((code, error)) => warn!(code, error, "request rejected"),
+        None => info!("request completed"),
+    }
+}
+
+// 挂在整个 App 上, 需要在最外层, 这样认证等中间件返回的错误也会经过这里
+pub struct AssignRequestId;
+
+impl<S, B> Transform<S, ServiceRequest> for AssignRequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = AssignRequestIdMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AssignRequestIdMiddleware { service }))
+    }
+}
+
+pub struct AssignRequestIdMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for AssignRequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| is_valid_request_id(value))
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+        // ? 不能 clone 整个请求, actix 在路由时要求请求没有别的引用
+        let path = req.path().to_string();
+        let span = info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %path,
+            route = Empty,
+            teacher_id = Empty,
+            principal = Empty,
+            status = Empty,
+            latency_ms = Empty,
+        );
+        let started = Instant::now();
+        // 内层中间件 (例如认证) 的 call 是同步执行的, 也要放在 span 里面
+        let fut = span.in_scope(|| self.service.call(req));
+
+        let handle = async move {
+            let result = fut.await;
+            Span::current().record("latency_ms", started.elapsed().as_millis() as u64);
+            let mut res = match result {
+                Ok(res) => {
+                    record_route(res.request());
+                    match res.response().error() {
+                        Some(err) => {
+                            let mut response = problem_response(err, &path, &request_id);
+                            // 保留内层中间件设置的响应头, 例如限流的 RateLimit-*
+                            for (name, value) in res.headers() {
+                                if name != header::CONTENT_TYPE && !response.headers().contains_key(name) {
+                                    response.headers_mut().append(name.clone(), value.clone());
+                                }
+                            }
+                            res.into_response(response.map_into_right_body())
+                        }
+                        None => {
+                            log_completion(res.status(), None);
+                            res.map_into_left_body()
+                        }
+                    }
+                }
+                // 内层直接返回的错误, 响应替换为 problem, 由 actix 发送
+                Err(err) => {
+                    let mut response = problem_response(&err, &path, &request_id);
+                    if let Ok(value) = HeaderValue::from_str(&request_id) {
+                        response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                    }
+                    return Err(InternalError::from_response(err, response).into());
+                }
+            };
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+            Ok(res)
+        };
+        Box::pin(handle.instrument(span))
+    }
+}
@@ -1,11 +1,30 @@
+use super::handlers::auth::{login, refresh, register};
 use super::handlers::course::*;
 use super::handlers::teacher::*;
-use crate::handlers::general::health_check_handler;
+use super::handlers::webhook::*;
+use crate::auth::middleware::RequireAuth;
+use crate::handlers::general::{liveness_handler, metrics_handler, readiness_handler};
+use crate::models::catalog::MAX_IMPORT_BYTES;
+use crate::rate_limit::{RateLimit, RouteGroup};
 use actix_web::web;
 
-// 健康检查
+// 健康检查, /health 保留给旧的调用方, 和 /health/live 相同
+// 以及 Prometheus 抓取指标的 /metrics
 pub fn general_routes(cfg: &mut web::ServiceConfig) {
-    cfg.route("/health", web::get().to(health_check_handler));
+    cfg.route("/health", web::get().to(liveness_handler))
+        .route("/health/live", web::get().to(liveness_handler))
+        .route("/health/ready", web::get().to(readiness_handler))
+        .route("/metrics", web::get().to(metrics_handler));
+}
+
+// 注册, 登录和刷新 token, 这几个接口本身不需要登录
+pub fn auth_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/auth")
+            .route("/register", web::post().to(register))
+            .route("/login", web::post().to(login))
+            .route("/refresh", web::post().to(refresh)),
+    );
 }
 
 // 注册课程路由
@@ -15,12 +34,34 @@ pub fn course_routes(cfg: &mut web::ServiceConfig) {
         // 在其下方可以继续添加资源
         .service(
             web::scope("/courses")
+                // 限流, 在认证里面, 登录之后按老师计数
+                .wrap(RateLimit(RouteGroup::Courses))
+                // 修改类的请求需要登录
+                .wrap(RequireAuth)
                 .route("/", web::post().to(post_new_course))
+                // 必须在 /{teacher_id} 之前注册, 否则 search 会被当成 teacher_id 匹配
+                .route("/search", web::get().to(search_courses))
+                // 等级, 格式和语言允许的值, 同样需要在 /{teacher_id} 之前注册
+                .route("/options", web::get().to(get_course_options))
                 // 添加路由, 动态路由, user_id也就是teacher_id
                 .route("/{teacher_id}", web::get().to(get_courses_for_teacher))
+                // 回收站, 同样需要在 /{teacher_id}/{course_id} 之前注册
+                .route("/{teacher_id}/trash", web::get().to(get_deleted_courses))
+                // 批量导入导出, 导入的文件可能比较大, 单独放宽请求体的限制
+                .service(
+                    web::resource("/{teacher_id}/import")
+                        .app_data(web::PayloadConfig::new(MAX_IMPORT_BYTES))
+                        .route(web::post().to(import_courses)),
+                )
+                .route("/{teacher_id}/export", web::get().to(export_courses))
+                // 课程变更事件 (SSE)
+                .route("/{teacher_id}/events", web::get().to(get_course_events))
+                .route("/{teacher_id}/{course_id}/restore", web::post().to(restore_course))
+                .route("/{teacher_id}/{course_id}/history", web::get().to(get_course_history))
                 .route("/{teacher_id}/{course_id}", web::get().to(get_course_detail))
                 .route("/{teacher_id}/{course_id}", web::delete().to(delete_course))
                 .route("/{teacher_id}/{course_id}", web::put().to(update_course_details))
+                .route("/{teacher_id}/{course_id}", web::patch().to(patch_course_details))
         );
 }
 
@@ -28,10 +69,31 @@ pub fn teacher_routes(cfg: &mut web::ServiceConfig) {
     cfg
         .service(
             web::scope("/teachers")
+            .wrap(RateLimit(RouteGroup::Teachers))
+            .wrap(RequireAuth)
             .route("", web::post().to(post_new_teacher))
             .route("", web::get().to(get_all_teachers))
             .route("/{teacher_id}", web::get().to(get_teacher_details))
             .route("/{teacher_id}", web::put().to(update_teacher_details))
+            .route("/{teacher_id}", web::patch().to(patch_teacher_details))
             .route("/{teacher_id}", web::delete().to(delete_teacher))
+            .route("/{teacher_id}/restore", web::post().to(restore_teacher))
+            .route("/{teacher_id}/history", web::get().to(get_teacher_history))
         );
 }
+
+// webhook 的注册和投递记录, 查询也需要登录 (handler 中的 Principal)
+pub fn webhook_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/webhooks")
+            .wrap(RateLimit(RouteGroup::Teachers))
+            .wrap(RequireAuth)
+            .route("", web::post().to(create_webhook))
+            .route("", web::get().to(get_webhooks))
+            .route("/{webhook_id}", web::get().to(get_webhook))
+            .route("/{webhook_id}", web::delete().to(delete_webhook))
+            .route("/{webhook_id}/deliveries", web::get().to(get_webhook_deliveries))
+            .route("/{webhook_id}/deliveries/{delivery_id}", web::get().to(get_webhook_delivery))
+            .route("/{webhook_id}/deliveries/{delivery_id}/redeliver", web::post().to(redeliver_webhook_delivery)),
+    );
+}
@@ -0,0 +1,130 @@
+// 优雅停机, 滚动发布时不中断正在处理的请求
+// 收到 SIGTERM 或者 SIGINT (Ctrl+C) 之后:
+// 1. 就绪检查改为返回 503 (draining), 负载均衡不再把新请求转发过来
+// 2. 等待 server.shutdown_delay_secs 秒, 给负载均衡发现的时间, 这期间照常处理请求
+// 3. 停止接受新连接, 等待正在处理的请求完成, 最多 server.shutdown_timeout_secs 秒, 超时的连接会被断开
+// 4. 服务停止之后由 main 关闭数据库连接池
+// ? 等待期间再收到一次信号就立即停止
+use crate::state::AppState;
+use actix_web::dev::ServerHandle;
+use actix_web::web;
+use futures_util::future::{select, Either};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+// 是否正在停机, 放在 AppState 中, 就绪检查读取
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    draining: AtomicBool,
+    // 唤醒 SSE 这样的长连接, 让它们在停机开始时就断开
+    started: Notify,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown::default()
+    }
+
+    pub fn begin_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+        self.started.notify_waiters();
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    // 等到开始停机, 已经在停机时立即返回
+    pub async fn draining(&self) {
+        // 先创建 Notified 再检查, 两者之间调用的 notify_waiters 也能收到
+        let notified = self.started.notified();
+        if self.is_draining() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+// 等待 SIGTERM (容器编排发出的) 或者 SIGINT (Ctrl+C), 返回信号的名称
+async fn wait_for_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use actix_rt::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                let interrupt = pin!(actix_rt::signal::ctrl_c());
+                return match select(pin!(terminate.recv()), interrupt).await {
+                    Either::Left(_) => "SIGTERM",
+                    Either::Right(_) => "SIGINT",
+                };
+            }
+            Err(err) => tracing::warn!(error = %err, "unable to listen for SIGTERM, only SIGINT triggers shutdown"),
+        }
+    }
+    // 监听失败时永远等待, 不能因此停机
+    if actix_rt::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+    "SIGINT"
+}
+
+/**
+ * 等待停机信号并按顺序停止服务, 在 main 中 spawn
+ * ? HttpServer 需要 disable_signals, 否则 actix 收到信号后会直接开始停止, 就绪检查来不及变为失败
+ */
+pub async fn drain_on_signal(handle: ServerHandle, app_state: web::Data<AppState>, delay: Duration) {
+    let signal = wait_for_signal().await;
+    app_state.shutdown.begin_draining();
+    let in_flight = app_state.metrics.snapshot().requests_in_flight;
+    tracing::info!(signal, in_flight, delay_secs = delay.as_secs(), "shutdown requested, readiness is now failing");
+    // 等待期间再次收到信号时跳过等待, 并且不再等待正在处理的请求
+    let graceful = match select(pin!(actix_rt::time::sleep(delay)), pin!(wait_for_signal())).await {
+        Either::Left(_) => true,
+        Either::Right((signal, _)) => {
+            tracing::warn!(signal, "second signal received, stopping immediately");
+            false
+        }
+    };
+    tracing::info!(in_flight = app_state.metrics.snapshot().requests_in_flight, "stopping server, draining in-flight requests");
+    handle.stop(graceful).await;
+}
+
+// * 测试
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routers::general_routes;
+    use actix_rt::time::timeout;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+
+    #[actix_rt::test]
+    async fn draining_fails_readiness_and_wakes_waiters() {
+        let app_state = AppState::for_tests().build();
+        let app = test::init_service(App::new().app_data(app_state.clone()).configure(general_routes)).await;
+        let ready = || test::TestRequest::get().uri("/health/ready").to_request();
+        assert_eq!(test::call_service(&app, ready()).await.status(), StatusCode::OK);
+
+        // 还没有开始停机时一直等待
+        assert!(timeout(Duration::from_millis(20), app_state.shutdown.draining()).await.is_err());
+        let waiter = {
+            let app_state = app_state.clone();
+            actix_rt::spawn(async move { app_state.shutdown.draining().await })
+        };
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        // 开始停机时唤醒正在等待的任务, 之后再等待立即返回
+        app_state.shutdown.begin_draining();
+        assert!(app_state.shutdown.is_draining());
+        timeout(Duration::from_secs(1), waiter).await.expect("draining() resolves").unwrap();
+        assert!(timeout(Duration::from_millis(20), app_state.shutdown.draining()).await.is_ok());
+
+        let res = test::call_service(&app, ready()).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["status"], "draining");
+    }
+}
@@ -1,18 +1,127 @@
 // 应用程序的状态
 // 由于使用了 actix 这个框架, 所以类似 AppState 这样的状态, 可以被注入到 请求他的 handler 中
 // 所以 handler 可以通过参数来访问 AppState
-use std::sync::Mutex;
+use std::sync::Arc;
 // use super::models::Course;
-use sqlx::postgres::PgPool;
+use crate::auth::TokenService;
+use crate::cache::CachePolicies;
+use crate::db_access::{CourseRepository, TeacherRepository, WebhookRepository};
+use crate::metrics::Metrics;
+use crate::rate_limit::RateLimits;
+use crate::shutdown::Shutdown;
+use crate::webhooks::WebhookPolicy;
 
 pub struct AppState {
-    // 健康检查, 不可变， 所有线程均持有
-    pub health_check_response: String,
-    // 可变的, 是一个数值, 这里使用 Mutex
-    // ? Mutex 是标准库提供的一个保障线程通信的一个机制, 也就是在修改 visit_count 之前, 当前线程必须持有数据的控制权, 就是由 Mutex 完成的
-    pub visit_count: Mutex<u32>,
+    // 请求计数, 内部都是原子变量, 多个线程同时修改也不需要加锁
+    // ? 原来的 visit_count 用 Mutex 保护, 每次健康检查都要加锁, 现在由 metrics.rs 中的中间件统计所有请求
+    pub metrics: Metrics,
     // pub courses: Mutex<Vec<Course>>,
-    // 表示db是一个数据库连接池
-    pub db: PgPool,
+    // 课程和教师的数据访问, 不再直接持有 PgPool
+    // ? 使用 trait 对象, 启动时决定用 postgres 还是内存实现
+    pub courses: Arc<dyn CourseRepository>,
+    pub teachers: Arc<dyn TeacherRepository>,
+    // webhook 的订阅和投递记录
+    pub webhooks: Arc<dyn WebhookRepository>,
+    // 签发和校验登录 token, 认证中间件也从这里取
+    pub tokens: TokenService,
+    // 课程和老师路由的限流, 限流中间件从这里取
+    pub rate_limits: RateLimits,
+    // 收到停机信号之后就绪检查返回 503
+    pub shutdown: Shutdown,
+    // 查询接口的 Cache-Control, 按路由配置
+    pub cache: CachePolicies,
+    // webhook 的重试和超时, 投递任务和注册时的地址检查使用
+    pub webhook_policy: WebhookPolicy,
 }
 
+// 测试用的 AppState, 默认全部使用内存实现, 需要的模块再覆盖其中的数据和策略
+// ? 以前每个测试模块各自拼一份 AppState, 每加一个字段就要改所有副本
+#[cfg(test)]
+type Repositories = (Arc<dyn CourseRepository>, Arc<dyn TeacherRepository>, Arc<dyn WebhookRepository>);
+
+#[cfg(test)]
+pub struct TestAppState {
+    courses: Vec<crate::models::course::Course>,
+    teachers: Vec<crate::models::teacher::Teacher>,
+    repositories: Option<Repositories>,
+    rate_limits: RateLimits,
+    webhook_policy: WebhookPolicy,
+}
+
+#[cfg(test)]
+impl AppState {
+    // 测试中签发 token 使用的密钥
+    pub const TEST_SECRET: &'static [u8] = b"test secret";
+
+    pub fn for_tests() -> TestAppState {
+        TestAppState {
+            courses: Vec::new(),
+            teachers: Vec::new(),
+            repositories: None,
+            rate_limits: RateLimits::disabled(),
+            webhook_policy: WebhookPolicy::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl TestAppState {
+    // 内存仓库中预先放入的课程
+    pub fn courses(mut self, courses: Vec<crate::models::course::Course>) -> Self {
+        self.courses = courses;
+        self
+    }
+
+    // 内存仓库中预先放入的老师
+    pub fn teachers(mut self, teachers: Vec<crate::models::teacher::Teacher>) -> Self {
+        self.teachers = teachers;
+        self
+    }
+
+    // 不使用内存实现, 比如需要连接池统计的测试
+    pub fn repositories(
+        mut self,
+        courses: Arc<dyn CourseRepository>,
+        teachers: Arc<dyn TeacherRepository>,
+        webhooks: Arc<dyn WebhookRepository>,
+    ) -> Self {
+        self.repositories = Some((courses, teachers, webhooks));
+        self
+    }
+
+    pub fn rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    pub fn webhook_policy(mut self, webhook_policy: WebhookPolicy) -> Self {
+        self.webhook_policy = webhook_policy;
+        self
+    }
+
+    pub fn build(self) -> actix_web::web::Data<AppState> {
+        use crate::db_access::memory::{MemoryCourseRepository, MemoryTeacherRepository, MemoryWebhookRepository};
+
+        let (courses, teachers, webhooks) = match self.repositories {
+            Some(repositories) => repositories,
+            None => {
+                let courses = Arc::new(MemoryCourseRepository::with_courses(self.courses));
+                let teachers: Arc<dyn TeacherRepository> =
+                    Arc::new(MemoryTeacherRepository::with_teachers(self.teachers, courses.clone()));
+                let webhooks: Arc<dyn WebhookRepository> = Arc::new(MemoryWebhookRepository::new(courses.clone()));
+                (courses as Arc<dyn CourseRepository>, teachers, webhooks)
+            }
+        };
+        actix_web::web::Data::new(AppState {
+            metrics: Metrics::new(),
+            courses,
+            teachers,
+            webhooks,
+            tokens: TokenService::new(AppState::TEST_SECRET),
+            rate_limits: self.rate_limits,
+            shutdown: Shutdown::new(),
+            cache: CachePolicies::default(),
+            webhook_policy: self.webhook_policy,
+        })
+    }
+}
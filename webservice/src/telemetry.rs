@@ -0,0 +1,54 @@
+// 日志, 使用 tracing 输出带级别, 时间和请求上下文的结构化日志
+// 每个请求一个 span (见 request_id.rs), db_access 中的查询各自一个 debug 级别的子 span
+// ? log.format (或者 LOG_FORMAT) 为 json 时输出一行一个 JSON, 方便日志系统收集, 默认 pretty 是给人看的多行格式
+// ? log.level (或者 LOG_LEVEL) 是过滤规则, 语法和 RUST_LOG 相同, 例如 debug 或者 info,sqlx::query=info, 默认 info,sqlx=warn
+// ? sqlx 使用 log 输出执行的 SQL, 初始化时会把 log 的记录转发给 tracing, 这样 SQL 也会出现在请求的 span 下面
+use crate::config::LogSettings;
+use serde::{Deserialize, Deserializer};
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+use tracing_subscriber::EnvFilter;
+
+// sqlx 默认以 info 级别记录每一条 SQL, 太多了, 需要时通过 log.level 打开
+pub const DEFAULT_LOG_LEVEL: &str = "info,sqlx=warn";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("invalid log format `{}`, expected one of pretty, json", other)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for LogFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/**
+ * 初始化全局的日志输出, 只能调用一次, 需要在读取配置之后尽早调用
+ * ? 格式和级别在加载配置时已经校验过 (见 config.rs)
+ */
+pub fn init_tracing(settings: &LogSettings) {
+    let filter = EnvFilter::try_new(&settings.level).unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_LEVEL));
+    // 输出重定向到文件时不带颜色
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(io::stdout().is_terminal());
+    match settings.format {
+        LogFormat::Json => builder.json().init(),
+        LogFormat::Pretty => builder.pretty().init(),
+    }
+}
@@ -0,0 +1,260 @@
+// 对外推送课程和老师变更的 webhook (见 config.rs 中的 webhooks)
+// 老师或者管理员注册接收地址, 订阅 course.created, teacher.deleted 这样的事件, 外部系统不再需要轮询
+// ? 写入变更历史的同一个事务中为订阅了这个事件的 webhook 创建投递记录 (见 webhooks 迁移), 修改回滚时不会投递
+// ? 后台任务定时领取到期的投递并发送, 对方返回 2xx 算成功, 否则按指数退避重试, 超过 max_attempts 次之后进入死信 (dead)
+// ? 死信和投递记录可以通过 /webhooks/{id}/deliveries 查看, 也可以手动重新投递
+// ? 请求体用 webhook 的密钥签名, 见 sign
+use crate::errors::MyError;
+use crate::models::webhook::{payload, AttemptOutcome, DueDelivery, NextAttempt};
+use crate::state::AppState;
+use actix_web::http::Uri;
+use actix_web::web;
+use futures_util::future::{join_all, select, Either};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::net::IpAddr;
+use std::pin::pin;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+// 签名相关的请求头
+pub const WEBHOOK_ID_HEADER: &str = "x-webhook-id"; // 投递的 id, 重试时不变
+pub const WEBHOOK_EVENT_HEADER: &str = "x-webhook-event";
+pub const WEBHOOK_TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "x-webhook-signature";
+// 记录的错误信息最多保留的字符数
+const MAX_ERROR_CHARS: usize = 500;
+
+// 投递的策略, 由配置生成
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookPolicy {
+    // 关闭时不启动投递任务, 投递记录仍然会创建, 打开之后再发送
+    pub enabled: bool,
+    // 最多发送的次数, 包括第一次
+    pub max_attempts: i32,
+    // 第一次失败之后等待的时间, 之后每次翻倍
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    // 一次请求的超时
+    pub timeout: Duration,
+    // 多久检查一次到期的投递
+    pub poll_interval: Duration,
+    // 一次最多领取的投递
+    pub batch_size: i64,
+    // 是否允许 localhost 和内网地址, 只在开发和测试时打开
+    pub allow_private_targets: bool,
+}
+
+impl Default for WebhookPolicy {
+    fn default() -> Self {
+        WebhookPolicy {
+            enabled: true,
+            max_attempts: 8,
+            initial_backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_secs(60 * 60),
+            timeout: Duration::from_secs(10),
+            poll_interval: Duration::from_secs(5),
+            batch_size: 20,
+            allow_private_targets: false,
+        }
+    }
+}
+
+impl WebhookPolicy {
+    // 第 attempts 次失败之后等待的时间: initial_backoff * 2^(attempts - 1), 不超过 max_backoff
+    pub fn backoff(&self, attempts: i32) -> Duration {
+        let exponent = attempts.saturating_sub(1).clamp(0, 30) as u32;
+        self.initial_backoff.saturating_mul(1 << exponent).min(self.max_backoff)
+    }
+
+    // attempts 是包括这一次在内已经发送的次数
+    pub fn next_attempt(&self, attempts: i32, outcome: &AttemptOutcome) -> NextAttempt {
+        if outcome.error.is_none() {
+            NextAttempt::Delivered
+        } else if attempts >= self.max_attempts {
+            NextAttempt::Dead
+        } else {
+            let backoff = chrono::Duration::from_std(self.backoff(attempts)).unwrap_or_else(|_err| chrono::Duration::zero());
+            NextAttempt::RetryAt(chrono::Utc::now() + backoff)
+        }
+    }
+
+    // 领取之后多久没有记录结果就可以被重新领取, 比一次请求的超时长, 避免还在发送时被其他实例领走
+    fn lease(&self) -> chrono::Duration {
+        chrono::Duration::from_std(self.timeout * 2 + Duration::from_secs(30)).unwrap_or_else(|_err| chrono::Duration::minutes(1))
+    }
+
+    /**
+     * 注册时检查接收地址, 不允许指向本机和内网, 否则可以借服务端访问内部的服务
+     * ? 只检查 IP 地址和 localhost, 域名解析之后才指向内网的情况这里检查不到
+     */
+    pub fn check_target(&self, url: &str) -> Result<(), MyError> {
+        if self.allow_private_targets {
+            return Ok(());
+        }
+        let uri: Uri = url.parse().map_err(|_err| MyError::InvalidInput(format!("Invalid webhook url: {}", url)))?;
+        let host = uri.host().unwrap_or_default().trim_start_matches('[').trim_end_matches(']').to_lowercase();
+        let private = match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified(),
+            Ok(IpAddr::V6(ip)) => {
+                let segment = ip.segments()[0];
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || (segment & 0xfe00) == 0xfc00 // fc00::/7, 唯一本地地址
+                    || (segment & 0xffc0) == 0xfe80 // fe80::/10, 链路本地地址
+                    || ip.to_ipv4_mapped().is_some_and(|ip| ip.is_loopback() || ip.is_private())
+            }
+            Err(_) => host == "localhost" || host.ends_with(".localhost"),
+        };
+        if private {
+            return Err(MyError::InvalidInput(format!(
+                "Webhook url must not point to localhost or a private network: {}",
+                url
+            )));
+        }
+        Ok(())
+    }
+}
+
+// 注册时没有提供密钥时生成一个, 只在创建的响应中返回一次
+pub fn generate_secret() -> String {
+    format!("whsec_{}", Uuid::new_v4().simple())
+}
+
+/**
+ * 请求体的签名: HMAC-SHA256(secret, "{timestamp}.{body}") 的十六进制, 放在 X-Webhook-Signature 中, 格式为 sha256=<hex>
+ * ? 接收方用同样的方法计算并比较, 同时检查 X-Webhook-Timestamp 和当前时间相差不大, 防止请求被重放
+ */
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut message = format!("{}.", timestamp).into_bytes();
+    message.extend_from_slice(body);
+    format!("sha256={}", hmac_sha256(secret.as_bytes(), &message))
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> String {
+    // HMAC 接受任意长度的密钥, 这里不会失败
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// 发送用的 http 客户端, 不跟随重定向, 避免被重定向到内网地址
+// ? awc 的客户端不能跨线程使用, 由投递任务自己创建
+pub fn client(policy: &WebhookPolicy) -> awc::Client {
+    awc::Client::builder().timeout(policy.timeout).disable_redirects().finish()
+}
+
+fn truncate(message: String) -> String {
+    match message.char_indices().nth(MAX_ERROR_CHARS) {
+        Some((index, _)) => message[..index].to_string(),
+        None => message,
+    }
+}
+
+// 发送一次, 返回结果, 不返回错误
+async fn send(client: &awc::Client, delivery: &DueDelivery) -> AttemptOutcome {
+    let body = serde_json::to_vec(&payload(&delivery.event_type, &delivery.event)).unwrap_or_default();
+    let timestamp = chrono::Utc::now().timestamp();
+    let started = Instant::now();
+    let result = client
+        .post(&delivery.url)
+        .insert_header(("content-type", "application/json"))
+        .insert_header(("user-agent", "teacher-service-webhooks"))
+        .insert_header((WEBHOOK_ID_HEADER, delivery.id.to_string()))
+        .insert_header((WEBHOOK_EVENT_HEADER, delivery.event_type.as_str()))
+        .insert_header((WEBHOOK_TIMESTAMP_HEADER, timestamp.to_string()))
+        .insert_header((WEBHOOK_SIGNATURE_HEADER, sign(&delivery.secret, timestamp, &body)))
+        .send_body(body)
+        .await;
+    let duration_ms = started.elapsed().as_millis().min(i32::MAX as u128) as i32;
+    match result {
+        Ok(response) if response.status().is_success() => {
+            AttemptOutcome { status_code: Some(response.status().as_u16() as i32), error: None, duration_ms }
+        }
+        Ok(response) => AttemptOutcome {
+            status_code: Some(response.status().as_u16() as i32),
+            error: Some(format!("unexpected status {}", response.status())),
+            duration_ms,
+        },
+        Err(err) => AttemptOutcome { status_code: None, error: Some(truncate(err.to_string())), duration_ms },
+    }
+}
+
+/**
+ * 领取一批到期的投递, 并发发送并记录结果, 返回发送的数量
+ * ? 领取时已经推迟了 next_attempt_at, 多个实例同时运行也不会重复发送
+ */
+pub async fn deliver_due(app_state: &AppState, client: &awc::Client) -> Result<usize, MyError> {
+    let policy = &app_state.webhook_policy;
+    let due = app_state.webhooks.claim_due_deliveries(policy.batch_size, policy.lease()).await?;
+    let sent = due.len();
+    join_all(due.into_iter().map(|delivery| async move {
+        let outcome = send(client, &delivery).await;
+        let next = policy.next_attempt(delivery.attempts + 1, &outcome);
+        if let Some(error) = &outcome.error {
+            tracing::warn!(delivery_id = delivery.id, url = %delivery.url, error = %error, next = ?next, "webhook delivery failed");
+        }
+        if let Err(err) = app_state.webhooks.record_attempt(delivery.id, outcome, next).await {
+            tracing::error!(delivery_id = delivery.id, error = %err, "unable to record webhook attempt");
+        }
+    }))
+    .await;
+    Ok(sent)
+}
+
+// 投递任务, 在 main 中 spawn, 开始停机时退出, 没有发送完的投递由其他实例或者重启之后继续
+pub async fn deliver_periodically(app_state: web::Data<AppState>) {
+    let client = client(&app_state.webhook_policy);
+    loop {
+        // 一批发满时说明还有到期的投递, 不需要等待
+        let full = match deliver_due(&app_state, &client).await {
+            Ok(sent) => sent as i64 >= app_state.webhook_policy.batch_size,
+            Err(err) => {
+                tracing::error!(error = %err, "unable to claim webhook deliveries");
+                false
+            }
+        };
+        if app_state.shutdown.is_draining() {
+            return;
+        }
+        if full {
+            continue;
+        }
+        let draining = pin!(app_state.shutdown.draining());
+        let wait = pin!(actix_rt::time::sleep(app_state.webhook_policy.poll_interval));
+        if let Either::Left(_) = select(draining, wait).await {
+            return;
+        }
+    }
+}
+
+// * 测试
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_hmac_sha256_of_timestamp_and_body() {
+        // 常见的 HMAC-SHA256 测试向量
+        assert_eq!(
+            hmac_sha256(b"key", b"The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        assert_eq!(sign("secret", 1700000000, b"{}"), format!("sha256={}", hmac_sha256(b"secret", b"1700000000.{}")));
+    }
+
+    #[test]
+    fn backoff_doubles_until_the_cap_and_private_targets_are_rejected() {
+        let policy = WebhookPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_secs(10));
+        assert_eq!(policy.backoff(3), Duration::from_secs(40));
+        assert_eq!(policy.backoff(20), Duration::from_secs(3600));
+
+        for url in ["http://localhost:8080/hook", "http://127.0.0.1/hook", "http://10.1.2.3/", "http://[::1]/", "http://169.254.169.254/"] {
+            assert!(policy.check_target(url).is_err(), "{}", url);
+        }
+        assert!(policy.check_target("https://lms.example.com/hooks").is_ok());
+        let policy = WebhookPolicy { allow_private_targets: true, ..WebhookPolicy::default() };
+        assert!(policy.check_target("http://127.0.0.1/hook").is_ok());
+    }
+}